@@ -0,0 +1,69 @@
+//! Router forwarding throughput over the loopback underlay
+//!     - rebuild is the previous path, the packet is decoded and encoded into a fresh Vec before it's sent
+//!     - in_place rewrites the hop limit of the received buffer and sends it as it is
+//!     - router is the whole unicast handler forwarding to a neighbour (tests/fixtures/Config.toml)
+
+#[path = "../tests/harness/mod.rs"]
+mod harness;
+
+use std::net::{Ipv6Addr, UdpSocket};
+use std::thread;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use emulator::models::config_models::WireFormat;
+use emulator::models::network_packets::JcmpMessage;
+use emulator::models::wire_format::{decode_packet, encode_packet, rewrite_hop_limit};
+
+const PAYLOAD_SIZES: [usize; 3] = [64, 512, 1400];
+
+// a node on network 1 of the fixture
+const NEIGHBOUR_NID: u64 = 0x0000000000000050;
+
+// the neighbour reads and discards everything sent to it
+fn neighbour_sink() -> UdpSocket {
+    let sink = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+    let reader = sink.try_clone().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 2048];
+        while reader.recv(&mut buf).is_ok() {}
+    });
+    sink
+}
+
+fn forward(c: &mut Criterion) {
+    let sink = neighbour_sink();
+    let destination = sink.local_addr().unwrap();
+    let sender = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+
+    // the router resolves the neighbour to the sink
+    harness::fuzz_multicast(&harness::jcmp(NEIGHBOUR_NID, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: destination.port() }));
+
+    let mut group = c.benchmark_group("forward");
+    for size in PAYLOAD_SIZES {
+        let received = harness::packet(harness::header(151, harness::PEER_NID, NEIGHBOUR_NID).with_hop_limit(64), &vec![0x5A; size]);
+        group.throughput(Throughput::Bytes(received.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("rebuild", size), &received, |b, received| {
+            b.iter(|| {
+                let packet = decode_packet(black_box(received)).unwrap();
+                let header = packet.header.with_hop_limit(packet.header.hop_limit() - 1);
+                let pck = encode_packet(WireFormat::Native, header, packet.nonce, &packet.payload);
+                sender.send_to(&pck, destination).unwrap();
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("in_place", size), &received, |b, received| {
+            let mut buf = received.clone();
+            b.iter(|| {
+                rewrite_hop_limit(black_box(&mut buf), 63);
+                sender.send_to(&buf, destination).unwrap();
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("router", size), &received, |b, received| {
+            b.iter(|| harness::fuzz_unicast(black_box(received)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, forward);
+criterion_main!(benches);
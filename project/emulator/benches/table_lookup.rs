@@ -0,0 +1,61 @@
+//! Per-packet route lookup cost against forwarding table size
+//!     - cloned_scan is the previous Mutex<TtlCache> table, cloned and scanned on every lookup
+//!     - indexed is the IndexedTable the forwarding table uses now
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use emulator::models::indexed_table::IndexedTable;
+use ttl_cache::TtlCache;
+
+const SIZES: [u64; 4] = [16, 256, 1024, 4096];
+
+type Route = (u64, u64, String, u8);
+
+// two routes per locator, through different next hops
+fn routes(size: u64) -> impl Iterator<Item = Route> {
+    (0..size).map(|route| (route % 2 + 1, route / 2 + 1, "eth0".to_string(), (route % 5) as u8))
+}
+
+fn cloned_scan_route(table: &Mutex<TtlCache<u64, Route>>, locator: &u64) -> Option<Route> {
+    let map = table.lock().unwrap();
+    let mut result: Option<Route> = None;
+    for (_, entry) in map.clone().iter() {
+        if &entry.1 == locator && result.as_ref().is_none_or(|best| entry.3 < best.3) {
+            result = Some(entry.clone());
+        }
+    }
+    result
+}
+
+fn indexed_route(table: &IndexedTable<(u64, u64), u64, (String, u8)>, locator: &u64) -> Option<Route> {
+    table.lookup(locator).into_iter()
+        .min_by_key(|(_, (_, hop_count))| *hop_count)
+        .map(|((next_hop, locator), (interface_name, hop_count))| (next_hop, locator, interface_name, hop_count))
+}
+
+fn route_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route_lookup");
+    for size in SIZES {
+        let cloned_scan = Mutex::new(TtlCache::new(size as usize));
+        let indexed = IndexedTable::new(size as usize, |(_, locator): &(u64, u64), _: &(String, u8)| *locator);
+        for (key, (next_hop, locator, interface_name, hop_count)) in routes(size).enumerate() {
+            cloned_scan.lock().unwrap().insert(key as u64, (next_hop, locator, interface_name.clone(), hop_count), Duration::from_secs(600));
+            indexed.insert((next_hop, locator), (interface_name, hop_count), Duration::from_secs(600));
+        }
+
+        // a locator in the middle of the table
+        let locator = size / 4 + 1;
+        group.bench_with_input(BenchmarkId::new("cloned_scan", size), &locator, |b, locator| {
+            b.iter(|| black_box(cloned_scan_route(&cloned_scan, black_box(locator))))
+        });
+        group.bench_with_input(BenchmarkId::new("indexed", size), &locator, |b, locator| {
+            b.iter(|| black_box(indexed_route(&indexed, black_box(locator))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, route_lookup);
+criterion_main!(benches);
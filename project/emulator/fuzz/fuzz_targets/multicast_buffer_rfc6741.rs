@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/harness/mod.rs"]
+mod harness;
+
+fuzz_target!(init: harness::use_config(harness::RFC6741), |data: &[u8]| {
+    harness::fuzz_multicast(data);
+});
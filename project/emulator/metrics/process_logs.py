@@ -8,6 +8,34 @@ from scipy.stats import linregress
 
 today_date = datetime.today().strftime('%Y-%m-%d')
 
+def parse_record(line):
+    """Parse a logger line into (kind, timestamp, fields).
+
+    Current runs write one JSON record per line, older runs wrote
+    'LEVEL;0xNID;ts;KIND;topology;value' strings which are still understood.
+    """
+    line = line.strip()
+    if line.startswith("{"):
+        try:
+            record = json.loads(line)
+        except json.JSONDecodeError:
+            return None
+        return record.get("kind"), int(record.get("ts", 0)), record.get("fields", {})
+
+    parts = line.split(";")
+    if len(parts) < 5 or not parts[2].isdigit():
+        return None
+    timestamp = int(parts[2])
+    if parts[3] == "PCB":
+        return "pcb", timestamp, {"topology": int(parts[4]), "pcb": json.loads(parts[5])}
+    if parts[3] == "METRIC":
+        return "metric", timestamp, {"topology": int(parts[4]), "name": "rtt_us", "value": int(parts[5])}
+    if parts[3] == "DISCOVERY_STARTED":
+        return "discovery_started", timestamp, {"topology": int(parts[4])}
+    if parts[3] == "DISCOVERY_COMPLETED":
+        return "discovery_completed", timestamp, {"topology": int(parts[4])}
+    return None
+
 def path_discovery_convergence():
     with open("packet_path_discovery.txt", "r") as file:
         flip = False
@@ -15,15 +43,15 @@ def path_discovery_convergence():
         timings = {}
 
         for line in file:
-            if "DISCOVERY_STARTED" in line or "DISCOVERY_COMPLETED" in line:
-                metric = line.strip()
-                timestamp = int(metric.split(";")[2])
-                nb_routers = int(metric.split(";")[4])
+            record = parse_record(line)
+            if record and record[0] in ("discovery_started", "discovery_completed"):
+                kind, timestamp, fields = record
+                nb_routers = fields["topology"]
 
-                if "DISCOVERY_STARTED" in metric:
+                if kind == "discovery_started":
                     current = timestamp
                     flip = True
-                if flip and "DISCOVERY_COMPLETED" in metric:
+                if flip and kind == "discovery_completed":
                     result = int((timestamp - current))
                     if nb_routers in timings:
                         timings[nb_routers].append(result)
@@ -79,12 +107,11 @@ def packet_overhead_single():
         for line in file:
             metric = line.strip()
 
-            if "PCB" in metric:
+            record = parse_record(metric)
+            if record and record[0] == "pcb":
 
-                metric_split = metric.split(";")
-                key = int(metric_split[4])
-                pcb_string = metric_split[5]
-                pcb = json.loads(pcb_string)
+                key = record[2]["topology"]
+                pcb = record[2]["pcb"]
 
                 overhead_count = 0
                 payload_count = 0
@@ -151,12 +178,11 @@ def packet_overhead_flow():
         for line in file:
             metric = line.strip()
 
-            if "PCB" in metric:
+            record = parse_record(metric)
+            if record and record[0] == "pcb":
 
-                metric_split = metric.split(";")
-                key = int(metric_split[4])
-                pcb_string = metric_split[5]
-                pcb = json.loads(pcb_string)
+                key = record[2]["topology"]
+                pcb = record[2]["pcb"]
 
                 overhead_count = 0
                 payload_count = 0
@@ -223,12 +249,11 @@ def packet_throughput():
             for line in file:
                 metric = line.strip()
 
-                if "PCB" in metric:
+                record = parse_record(metric)
+                if record and record[0] == "pcb":
 
-                    metric_split = metric.split(";")
-                    key = int(metric_split[4])
-                    pcb_string = metric_split[5]
-                    pcb = json.loads(pcb_string)
+                    key = record[2]["topology"]
+                    pcb = record[2]["pcb"]
 
                     packet_sent = 0
                     packet_received = 0
@@ -357,11 +382,11 @@ def packet_latency_rtt():
             for line in file:
                 metric = line.strip()
 
-                if "METRIC" in metric:
+                record = parse_record(metric)
+                if record and record[0] == "metric":
 
-                    metric_split = metric.split(";")
-                    key = int(metric_split[4])
-                    rtt_reading = int(metric_split[5])
+                    key = record[2]["topology"]
+                    rtt_reading = record[2]["value"]
 
                     if first[key] == True:
                         first[key] = False
@@ -0,0 +1,163 @@
+use std::env;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::process::exit;
+use chrono::{TimeZone, Utc};
+use tokio::net::UdpSocket;
+use tokio::signal;
+
+use emulator::layers::underlay_network::under_socket::create_multi_socket;
+use emulator::layers::overlay_network::CONFIG;
+use emulator::services::capture_services::CaptureDirection;
+use emulator::services::dump_services::{describe_packet, read_capture};
+use emulator::services::log_services::is_log_datagram;
+use emulator::services::network_services::{get_multicast_to_join, get_under_interface_by_name};
+use emulator::services::time_services::get_current_timestamp;
+
+const USAGE: &str = "usage:
+    ilnp-dump <capture.pcap|capture.pcapng>
+    ilnp-dump --live [--port <unicast port>]
+
+    --live joins the overlay multicast groups from config/Config.toml
+    --port also binds a unicast port (the node's own port is taken, use it for traffic sent to the dump)";
+
+#[tokio::main]
+async fn main() {
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|arg| arg.as_str()) {
+        Some("--live") => {
+            let port = match args.get(1).map(|arg| arg.as_str()) {
+                Some("--port") => match args.get(2).and_then(|port| port.parse::<u16>().ok()) {
+                    Some(port) => Some(port),
+                    None => {
+                        eprintln!("{}", USAGE);
+                        exit(2);
+                    }
+                },
+                Some(_) => {
+                    eprintln!("{}", USAGE);
+                    exit(2);
+                },
+                None => None
+            };
+            dump_live(port).await
+        },
+        Some("-h") | Some("--help") | None => {
+            println!("{}", USAGE);
+            return;
+        },
+        Some(path) => dump_file(path)
+    };
+
+    if let Err(err) = result {
+        eprintln!("ilnp-dump: {}", err);
+        exit(1);
+    }
+}
+
+/// Print one packet
+fn print_packet(timestamp_us: u64, direction: Option<CaptureDirection>, source: Option<SocketAddr>, pck: &[u8])
+{
+    let time = match Utc.timestamp_micros(timestamp_us as i64) {
+        chrono::LocalResult::Single(time) => time.format("%H:%M:%S%.6f").to_string(),
+        _ => timestamp_us.to_string()
+    };
+    let direction = match direction {
+        Some(CaptureDirection::Inbound) => "in ",
+        Some(CaptureDirection::Outbound) => "out",
+        None => "   "
+    };
+    let source = match source {
+        Some(source) => format!(" from {}", source),
+        None => String::new()
+    };
+
+    println!("{} {} {} bytes{}", time, direction, pck.len(), source);
+    println!("{}", describe_packet(pck));
+}
+
+/// Dump a pcap or pcapng file
+fn dump_file(path: &str)
+    -> Result<(), String>
+{
+    let packets = read_capture(path)?;
+    for packet in &packets {
+        print_packet(packet.timestamp_us, packet.direction, None, &packet.data);
+    }
+    println!("{} packets", packets.len());
+    Ok(())
+}
+
+/// Dump live traffic
+///     - multicast (JCMP) from every overlay network in the config and the DNS group
+///     - unicast (JTP) if a port is given
+async fn dump_live(port: Option<u16>)
+    -> Result<(), String>
+{
+    // same interface and groups as the node
+    let emulator_interface = get_under_interface_by_name(&"enp3s0".to_string())?;
+    let mulcast_socket = create_multi_socket(&emulator_interface, false)?;
+
+    let mut groups: Vec<Ipv6Addr> = get_multicast_to_join(CONFIG.node.networks.clone())?.into_values().collect();
+    groups.push(Ipv6Addr::new(0xff02, 0, 0, emulator_interface.local_uid, 0, 0, 0x5353, 0x5353));
+    for group in &groups {
+        if let Err(err) = mulcast_socket.join_multicast_v6(group, emulator_interface.local_index) {
+            return Err(format!("dump_live(): error joining {}: {}", group, err));
+        }
+        eprintln!("listening on {}", group);
+    }
+
+    let unicast_socket = match port {
+        Some(port) => {
+            match UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))).await {
+                Ok(socket) => {
+                    eprintln!("listening on [::]:{}", port);
+                    Some(socket)
+                },
+                Err(err) => {
+                    return Err(format!("dump_live(): error binding port {}: {}", port, err));
+                }
+            }
+        },
+        None => None
+    };
+
+    let mut mulcast_buf = [0u8; 65535];
+    let mut unicast_buf = [0u8; 65535];
+    loop {
+        tokio::select! {
+            result = mulcast_socket.recv_from(&mut mulcast_buf) => {
+                match result {
+                    Ok((len, addr)) => {
+
+                        // log records share the socket but aren't overlay packets
+                        if !is_log_datagram(&mulcast_buf[..len]) {
+                            print_packet(get_current_timestamp()?, None, Some(addr), &mulcast_buf[..len]);
+                        }
+                    },
+                    Err(err) => {
+                        return Err(format!("dump_live(): error receiving: {}", err));
+                    }
+                }
+            },
+            result = async {
+                match &unicast_socket {
+                    Some(socket) => socket.recv_from(&mut unicast_buf).await,
+                    None => std::future::pending().await
+                }
+            } => {
+                match result {
+                    Ok((len, addr)) => {
+                        print_packet(get_current_timestamp()?, None, Some(addr), &unicast_buf[..len]);
+                    },
+                    Err(err) => {
+                        return Err(format!("dump_live(): error receiving: {}", err));
+                    }
+                }
+            },
+            _ = signal::ctrl_c() => {
+                return Ok(());
+            }
+        }
+    }
+}
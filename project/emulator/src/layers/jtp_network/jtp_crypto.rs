@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::layers::overlay_network::{count_drop, ilnp_nid_tx, CONFIG};
+use crate::models::network_models::{EmulatorSocket, JTPResponse};
+use crate::models::protocol_control_block::DropReason;
+use crate::services::log_services::log_error;
+
+/*******************************************/
+/// Encrypted JTP messages
+/// The first byte of the JTP payload is the message type
+///     - Hello (0x01)          public_key: [u8; 32], tag: [u8; 32]
+///     - Hello Ack (0x02)      public_key: [u8; 32], tag: [u8; 32]
+///     - Data (0x03)           counter: u64, ChaCha20-Poly1305 ciphertext and tag
+pub const JTP_CRYPTO_HELLO: u8 = 0x01;
+pub const JTP_CRYPTO_HELLO_ACK: u8 = 0x02;
+pub const JTP_CRYPTO_DATA: u8 = 0x03;
+
+const DATA_HEADER_SIZE: usize = 1 + 8;
+const HANDSHAKE_SIZE: usize = 1 + 32 + 32;
+const REPLAY_WINDOW: u64 = 64;
+/*******************************************/
+
+
+/// Node key pair
+///     - X25519, generated when the node starts
+static JTP_KEYPAIR: Lazy<(StaticSecret, PublicKey)> = Lazy::new(|| {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    (secret, public)
+});
+
+/// JTP Session Table
+///     - maps correspondent NID to the session keys
+///     - sessions are bound to NIDs so they survive locator changes
+static JTP_SESSION_TABLE: Lazy<Mutex<HashMap<u64, JtpSession>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Public key of this node
+pub fn jtp_public_key()
+    -> [u8; 32]
+{
+    JTP_KEYPAIR.1.to_bytes()
+}
+
+
+/// JTP crypto errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JtpCryptoError {
+    TooShort { actual: usize },
+    Replay { counter: u64 },
+    DecryptFailed,
+    EncryptFailed,
+    InvalidHandshake
+}
+impl fmt::Display for JtpCryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JtpCryptoError::TooShort { actual } => write!(f, "encrypted message too short ({} bytes)", actual),
+            JtpCryptoError::Replay { counter } => write!(f, "counter {} replayed or outside the window", counter),
+            JtpCryptoError::DecryptFailed => write!(f, "failed to decrypt"),
+            JtpCryptoError::EncryptFailed => write!(f, "failed to encrypt"),
+            JtpCryptoError::InvalidHandshake => write!(f, "handshake not signed with the psk")
+        }
+    }
+}
+
+/// Encrypted session with a correspondent
+///     - one key per direction, derived from the X25519 shared secret, the psk and both NIDs
+///     - the nonce is the message counter, received counters are checked against a sliding window
+pub struct JtpSession {
+    remote_public: [u8; 32],
+    tx_cipher: ChaCha20Poly1305,
+    rx_cipher: ChaCha20Poly1305,
+    tx_counter: u64,
+    rx_highest: u64,
+    rx_window: u64
+}
+impl JtpSession {
+
+    pub fn new(local_secret: &StaticSecret, remote_public: &PublicKey, local_nid: u64, remote_nid: u64, psk: &[u8])
+        -> Self
+    {
+        let shared = local_secret.diffie_hellman(remote_public);
+        let tx_key = derive_key(shared.as_bytes(), psk, local_nid, remote_nid);
+        let rx_key = derive_key(shared.as_bytes(), psk, remote_nid, local_nid);
+
+        JtpSession {
+            remote_public: remote_public.to_bytes(),
+            tx_cipher: ChaCha20Poly1305::new(Key::from_slice(&tx_key)),
+            rx_cipher: ChaCha20Poly1305::new(Key::from_slice(&rx_key)),
+            tx_counter: 0,
+            rx_highest: 0,
+            rx_window: 1
+        }
+    }
+
+    /// Encrypt a payload into a Data message
+    ///     - the NIDs are authenticated as associated data
+    pub fn seal(&mut self, source_nid: u64, destination_nid: u64, plaintext: &[u8])
+        -> Result<Vec<u8>, JtpCryptoError>
+    {
+        self.tx_counter += 1;
+        let aad = associated_data(source_nid, destination_nid);
+        let ciphertext = match self.tx_cipher.encrypt(&nonce(self.tx_counter), Payload { msg: plaintext, aad: &aad }) {
+            Ok(ciphertext) => ciphertext,
+            Err(_) => {
+                return Err(JtpCryptoError::EncryptFailed);
+            }
+        };
+
+        let mut message = Vec::with_capacity(DATA_HEADER_SIZE + ciphertext.len());
+        message.push(JTP_CRYPTO_DATA);
+        message.extend_from_slice(&self.tx_counter.to_be_bytes());
+        message.extend_from_slice(&ciphertext);
+        Ok(message)
+    }
+
+    /// Decrypt a Data message
+    ///     - the replay window is only moved once the message is authenticated
+    pub fn open(&mut self, source_nid: u64, destination_nid: u64, message: &[u8])
+        -> Result<Vec<u8>, JtpCryptoError>
+    {
+        if message.len() < DATA_HEADER_SIZE {
+            return Err(JtpCryptoError::TooShort { actual: message.len() });
+        }
+        let mut counter = [0u8; 8];
+        counter.copy_from_slice(&message[1..DATA_HEADER_SIZE]);
+        let counter = u64::from_be_bytes(counter);
+
+        if !self.is_fresh(counter) {
+            return Err(JtpCryptoError::Replay { counter });
+        }
+
+        let aad = associated_data(source_nid, destination_nid);
+        let plaintext = match self.rx_cipher.decrypt(&nonce(counter), Payload { msg: &message[DATA_HEADER_SIZE..], aad: &aad }) {
+            Ok(plaintext) => plaintext,
+            Err(_) => {
+                return Err(JtpCryptoError::DecryptFailed);
+            }
+        };
+
+        self.mark_received(counter);
+        Ok(plaintext)
+    }
+
+    fn is_fresh(&self, counter: u64)
+        -> bool
+    {
+        if counter > self.rx_highest {
+            return true;
+        }
+        let offset = self.rx_highest - counter;
+        offset < REPLAY_WINDOW && self.rx_window & (1 << offset) == 0
+    }
+
+    fn mark_received(&mut self, counter: u64)
+    {
+        if counter > self.rx_highest {
+            let shift = counter - self.rx_highest;
+            self.rx_window = if shift >= REPLAY_WINDOW { 0 } else { self.rx_window << shift };
+            self.rx_window |= 1;
+            self.rx_highest = counter;
+        } else {
+            self.rx_window |= 1 << (self.rx_highest - counter);
+        }
+    }
+}
+
+fn derive_key(shared: &[u8], psk: &[u8], sender_nid: u64, receiver_nid: u64)
+    -> [u8; 32]
+{
+    let mut hasher = Sha256::new();
+    hasher.update(b"ilnp-jtp");
+    hasher.update(shared);
+    hasher.update(psk);
+    hasher.update(sender_nid.to_be_bytes());
+    hasher.update(receiver_nid.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn associated_data(source_nid: u64, destination_nid: u64)
+    -> [u8; 16]
+{
+    let mut aad = [0u8; 16];
+    aad[..8].copy_from_slice(&source_nid.to_be_bytes());
+    aad[8..].copy_from_slice(&destination_nid.to_be_bytes());
+    aad
+}
+
+fn nonce(counter: u64)
+    -> Nonce
+{
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+/// Build a handshake message
+///     - the public key is signed with the psk so only nodes holding it can set up (or replace) a session
+pub fn handshake_message(message_type: u8, public_key: &[u8; 32], source_nid: u64, destination_nid: u64, psk: &[u8])
+    -> Vec<u8>
+{
+    let mut message = Vec::with_capacity(HANDSHAKE_SIZE);
+    message.push(message_type);
+    message.extend_from_slice(public_key);
+    message.extend_from_slice(&handshake_tag(message_type, public_key, source_nid, destination_nid, psk).finalize().into_bytes());
+    message
+}
+
+/// Check a handshake message and return the public key it carries
+pub fn verify_handshake(message: &[u8], source_nid: u64, destination_nid: u64, psk: &[u8])
+    -> Result<[u8; 32], JtpCryptoError>
+{
+    if message.len() != HANDSHAKE_SIZE {
+        return Err(JtpCryptoError::TooShort { actual: message.len() });
+    }
+    let mut public_key = [0u8; 32];
+    public_key.copy_from_slice(&message[1..33]);
+
+    if handshake_tag(message[0], &public_key, source_nid, destination_nid, psk).verify_slice(&message[33..]).is_err() {
+        return Err(JtpCryptoError::InvalidHandshake);
+    }
+    Ok(public_key)
+}
+
+fn handshake_tag(message_type: u8, public_key: &[u8; 32], source_nid: u64, destination_nid: u64, psk: &[u8])
+    -> Hmac<Sha256>
+{
+    // HMAC takes keys of any length
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(psk).expect("hmac accepts any key length");
+    mac.update(b"ilnp-jtp-hello");
+    mac.update(&[message_type]);
+    mac.update(public_key);
+    mac.update(&source_nid.to_be_bytes());
+    mac.update(&destination_nid.to_be_bytes());
+    mac
+}
+
+/// Our handshake message to a correspondent
+fn local_handshake_message(message_type: u8, local_nid: u64, remote_nid: u64)
+    -> Vec<u8>
+{
+    handshake_message(message_type, &jtp_public_key(), local_nid, remote_nid, CONFIG.jtp_crypto.psk.as_bytes())
+}
+
+/// Create the session with a correspondent from their (verified) public key
+///     - a new key (e.g. the correspondent restarted) replaces the session
+fn insert_session(local_nid: u64, remote_nid: u64, remote_public: [u8; 32])
+    -> Result<(), String>
+{
+    match JTP_SESSION_TABLE.lock() {
+        Ok(mut sessions) => {
+
+            // same key means the same session (e.g. a retransmitted Hello), keep the counters
+            if let Some(session) = sessions.get(&remote_nid) {
+                if session.remote_public == remote_public {
+                    return Ok(());
+                }
+            }
+
+            let session = JtpSession::new(&JTP_KEYPAIR.0, &PublicKey::from(remote_public), local_nid, remote_nid, CONFIG.jtp_crypto.psk.as_bytes());
+            sessions.insert(remote_nid, session);
+            Ok(())
+        },
+        Err(err) => {
+            Err(format!("insert_session(): failed to lock JTP_SESSION_TABLE: {}", err))
+        }
+    }
+}
+
+fn has_session(remote_nid: &u64)
+    -> bool
+{
+    match JTP_SESSION_TABLE.lock() {
+        Ok(sessions) => sessions.contains_key(remote_nid),
+        Err(_) => false
+    }
+}
+
+
+/// Send an encrypted JTP packet
+///     - runs the handshake first if there is no session with the correspondent
+pub async fn jtp_encrypted_tx(emulator_socket: &EmulatorSocket, destination_nid: &u64, buf: &[u8])
+    -> Result<(), String>
+{
+    let message = jtp_seal(emulator_socket, destination_nid, buf).await?;
+    ilnp_nid_tx(emulator_socket, destination_nid, &message).await
+}
+
+/// Encrypt a JTP payload for a correspondent
+///     - runs the handshake first if there is no session with the correspondent
+///     - returns the Data message, for senders that build the packet themselves (JtpFlow)
+pub async fn jtp_seal(emulator_socket: &EmulatorSocket, destination_nid: &u64, buf: &[u8])
+    -> Result<Vec<u8>, String>
+{
+    if !has_session(destination_nid) {
+        jtp_handshake(emulator_socket, destination_nid).await?;
+    }
+
+    let local_nid = emulator_socket.local_network.local_nid;
+    match JTP_SESSION_TABLE.lock() {
+        Ok(mut sessions) => {
+            match sessions.get_mut(destination_nid) {
+                Some(session) => {
+                    match session.seal(local_nid, *destination_nid, buf) {
+                        Ok(message) => Ok(message),
+                        Err(err) => Err(format!("jtp_seal(): failed to seal packet for 0x{:016X}: {}", destination_nid, err))
+                    }
+                },
+                None => Err(format!("jtp_seal(): no session with 0x{:016X}", destination_nid))
+            }
+        },
+        Err(err) => {
+            Err(format!("jtp_seal(): failed to lock JTP_SESSION_TABLE: {}", err))
+        }
+    }
+}
+
+/// Handshake
+///     - send Hello every handshake_rto_ms until the Hello Ack creates the session
+async fn jtp_handshake(emulator_socket: &EmulatorSocket, destination_nid: &u64)
+    -> Result<(), String>
+{
+    let hello = local_handshake_message(JTP_CRYPTO_HELLO, emulator_socket.local_network.local_nid, *destination_nid);
+
+    let mut attempt = 0;
+    while attempt < CONFIG.jtp_crypto.handshake_retransmit_limit {
+        ilnp_nid_tx(emulator_socket, destination_nid, &hello).await?;
+        attempt += 1;
+
+        tokio::time::sleep(Duration::from_millis(CONFIG.jtp_crypto.handshake_rto_ms)).await;
+        if has_session(destination_nid) {
+            return Ok(());
+        }
+    }
+
+    Err(format!("jtp_handshake(): no hello ack from 0x{:016X}", destination_nid))
+}
+
+/// Handle a received JTP packet when encryption is enabled
+///     - Hello and Hello Ack have to be signed with the psk
+///     - Hello creates the session and is answered with a Hello Ack
+///     - Hello Ack creates the session
+///     - Data is decrypted and returned for the JTP queue
+///     - Data without a session, or that doesn't decrypt (e.g. we restarted and the sender kept the old session), is answered with a Hello so the sender sets up a new one
+pub async fn jtp_crypto_rx(emulator_socket: &EmulatorSocket, jtp_packet: JTPResponse)
+    -> Option<JTPResponse>
+{
+    let local_nid = emulator_socket.local_network.local_nid;
+    let source_nid = jtp_packet.source_nid;
+
+    match jtp_packet.payload.first() {
+        Some(&JTP_CRYPTO_HELLO) | Some(&JTP_CRYPTO_HELLO_ACK) => {
+            let remote_public = match verify_handshake(&jtp_packet.payload, source_nid, jtp_packet.destination_nid, CONFIG.jtp_crypto.psk.as_bytes()) {
+                Ok(remote_public) => remote_public,
+                Err(err) => {
+                    count_drop(DropReason::JtpHandshakeInvalid);
+                    log_error(emulator_socket, &format!("jtp_crypto_rx(): dropped handshake from 0x{:016X}: {}", source_nid, err)).await;
+                    return None;
+                }
+            };
+            if let Err(err) = insert_session(local_nid, source_nid, remote_public) {
+                log_error(emulator_socket, &err).await;
+                return None;
+            }
+
+            if jtp_packet.payload[0] == JTP_CRYPTO_HELLO {
+                if let Err(err) = ilnp_nid_tx(emulator_socket, &source_nid, &local_handshake_message(JTP_CRYPTO_HELLO_ACK, local_nid, source_nid)).await {
+                    log_error(emulator_socket, &err).await;
+                }
+            }
+            None
+        },
+        Some(&JTP_CRYPTO_DATA) => {
+            let result = match JTP_SESSION_TABLE.lock() {
+                Ok(mut sessions) => {
+                    sessions.get_mut(&source_nid).map(|session| session.open(source_nid, jtp_packet.destination_nid, &jtp_packet.payload))
+                },
+                Err(_) => None
+            };
+
+            match result {
+                Some(Ok(plaintext)) => {
+                    Some(JTPResponse { payload: plaintext, ..jtp_packet })
+                },
+                Some(Err(err)) => {
+                    log_error(emulator_socket, &format!("jtp_crypto_rx(): dropped packet from 0x{:016X}: {}", source_nid, err)).await;
+                    match err {
+                        JtpCryptoError::Replay { .. } => {
+                            count_drop(DropReason::JtpReplay);
+                        },
+                        _ => {
+                            count_drop(DropReason::JtpDecryptFailed);
+                            if let Err(err) = ilnp_nid_tx(emulator_socket, &source_nid, &local_handshake_message(JTP_CRYPTO_HELLO, local_nid, source_nid)).await {
+                                log_error(emulator_socket, &err).await;
+                            }
+                        }
+                    }
+                    None
+                },
+                None => {
+                    count_drop(DropReason::JtpNoSession);
+                    if let Err(err) = ilnp_nid_tx(emulator_socket, &source_nid, &local_handshake_message(JTP_CRYPTO_HELLO, local_nid, source_nid)).await {
+                        log_error(emulator_socket, &err).await;
+                    }
+                    None
+                }
+            }
+        },
+        _ => {
+            count_drop(DropReason::JtpNotEncrypted);
+            log_error(emulator_socket, &format!("jtp_crypto_rx(): dropped unencrypted packet from 0x{:016X}", source_nid)).await;
+            None
+        }
+    }
+}
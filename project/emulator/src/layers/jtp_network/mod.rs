@@ -2,12 +2,20 @@ use once_cell::sync::Lazy;
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::{mpsc, Mutex};
 use tokio::time::timeout;
-use std::sync::Arc;
-use std::time::Duration;
+use std::net::Ipv6Addr;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::{Duration, Instant};
 
-use crate::models::network_models::{EmulatorSocket, JTPResponse};
+use crate::models::{network_models::{EmulatorSocket, JTPResponse, NeighbourState}, network_packets::INLPv6Packet, wire_format::{encode_packet, set_payload_length}};
 use crate::layers::overlay_network::{ open_ilnp_socket, close_ilnp_socket };
-use crate::layers::overlay_network::{ilnp_nid_tx, ilnp_fqdn_tx};
+use crate::layers::overlay_network::{ilnp_nid_tx, ilnp_fqdn_tx, resolve_fqdn_route, CONFIG, PCB, ROUTE_EPOCH};
+use crate::layers::overlay_network::overlay_handlers::{handle_destination_fqdn, probe_stale_neighbour};
+use crate::layers::underlay_network::underlay_uni_tx;
+use crate::services::network_services::{get_session_nonce, lookup_neighbour_cache, lookup_neighbour_nid};
+
+use jtp_crypto::{jtp_encrypted_tx, jtp_seal};
+
+pub mod jtp_crypto;
 
 /// JTP QUEUE
 ///     - this queue is used to store incoming data packets
@@ -35,19 +43,208 @@ pub async fn close_jtp_socket(emulator_socket: EmulatorSocket)
 }
 
 /// Send a JTP packet using NID
+///     - encrypted when jtp_crypto is enabled
 pub async fn jtp_nid_tx(emulator_socket: &EmulatorSocket, destination_nid:&u64, buf:&[u8])
     -> Result<(), String>
 {
+    if CONFIG.jtp_crypto.enabled {
+        return jtp_encrypted_tx(emulator_socket, destination_nid, buf).await;
+    }
     ilnp_nid_tx(emulator_socket, destination_nid, buf).await
 }
 
 /// Send a JTP packet using FQDN
+///     - encrypted when jtp_crypto is enabled, the session is with the NID the name resolves to
 pub async fn jtp_fqdn_tx(emulator_socket: &EmulatorSocket, destination_fqdn:&String, buf:&[u8])
     -> Result<(), String>
 {
+    if CONFIG.jtp_crypto.enabled {
+        let dns_entries = handle_destination_fqdn(emulator_socket, destination_fqdn).await?;
+        match dns_entries.first() {
+            Some((destination_nid, _)) => {
+                return jtp_encrypted_tx(emulator_socket, destination_nid, buf).await;
+            },
+            None => {
+                return Err(format!("jtp_fqdn_tx(): couldn't resolve {}", destination_fqdn));
+            }
+        }
+    }
     ilnp_fqdn_tx(emulator_socket, destination_fqdn, buf).await
 }
 
+/// JTP Flow
+///     - connected-socket style sender to one FQDN, opened with jtp_connect()
+///     - the route and the encoded header are resolved once, sending is a header copy and a send_to on the flow's own buffer
+///     - resolved again once a cached entry (DNS, route, neighbour, nonce session) may have expired, a route went bad or a send failed
+///     - the next hop is only looked at again once its confirmation runs out, a Stale one is probed (NUD)
+///     - encrypted payloads are sealed into the cached header too
+///     - sent packets are counted into the PCB when the flow is checked again or dropped
+pub struct JtpFlow {
+    emulator_socket: EmulatorSocket,
+    destination_fqdn: String,
+    destination_nid: u64,
+    next_hop: (Ipv6Addr, u16),
+    next_hop_nid: Option<u64>,
+    packet: Vec<u8>,
+    header_length: usize,
+    epoch: u64,
+    expires: Instant,
+    recheck: Instant,
+    unreported_tx: u64
+}
+impl JtpFlow {
+
+    /// Resolve the route and encode the header
+    async fn resolve(&mut self)
+        -> Result<(), String>
+    {
+        let epoch = ROUTE_EPOCH.load(Ordering::Relaxed);
+        let (next_hop_ipv6, next_hop_port, source_locator, destination_nid, destination_locator) = resolve_fqdn_route(&self.emulator_socket, &self.destination_fqdn).await?;
+
+        // the header with an empty payload, its length is set on each send
+        let inlp_pck = INLPv6Packet::new()
+            .with_version(6)
+            .with_traffic_class(0)
+            .with_flow_label(0)
+            .with_next_header(151)
+            .with_hop_limit(CONFIG.forwarding.hop_limit)
+            .with_source_locator(source_locator)
+            .with_source_identifier(self.emulator_socket.local_network.local_nid)
+            .with_destination_locator(destination_locator)
+            .with_destination_identifier(destination_nid);
+        let nonce = get_session_nonce(&destination_nid)?;
+
+        // the soonest any of the cached entries could expire
+        // resolving again keeps the nonce session alive
+        let mut ttl = [
+            Duration::from_secs(CONFIG.network.DNS_TTL_S as u64),
+            Duration::from_secs(CONFIG.network.AD_HOC_TTL_S as u64),
+            Duration::from_secs(CONFIG.network.ND_TTL_S)
+        ].into_iter().min().unwrap_or_default();
+        if CONFIG.nonce.enabled {
+            ttl = ttl.min(Duration::from_secs(CONFIG.nonce.session_ttl_s));
+        }
+
+        self.destination_nid = destination_nid;
+        self.next_hop = (next_hop_ipv6, next_hop_port);
+        self.next_hop_nid = lookup_neighbour_nid(&next_hop_ipv6, next_hop_port)?;
+        self.packet = encode_packet(CONFIG.wire.format, inlp_pck, Some(nonce), &[]);
+        self.header_length = self.packet.len();
+        self.epoch = epoch;
+        self.expires = Instant::now() + ttl;
+        self.check_next_hop(Instant::now())
+    }
+
+    /// Neighbour unreachability detection on the next hop
+    ///     - a Reachable next hop is checked again once reachable_time_ms has passed since it was confirmed
+    ///     - a Stale one is probed and checked again after retrans_timer_ms
+    ///     - the flow expires if the next hop is gone
+    fn check_next_hop(&mut self, now: Instant)
+        -> Result<(), String>
+    {
+        let next_hop_nid = match self.next_hop_nid {
+            Some(next_hop_nid) => next_hop_nid,
+            None => {
+                self.recheck = self.expires;
+                return Ok(());
+            }
+        };
+
+        match lookup_neighbour_cache(&next_hop_nid)? {
+            Some(neighbour) if neighbour.state == NeighbourState::Reachable => {
+                self.recheck = neighbour.updated + Duration::from_millis(CONFIG.neighbour_discovery.reachable_time_ms);
+            },
+            Some(neighbour) => {
+                probe_stale_neighbour(&self.emulator_socket, &next_hop_nid, &neighbour)?;
+                self.recheck = now + Duration::from_millis(CONFIG.neighbour_discovery.retrans_timer_ms);
+            },
+            None => {
+                self.expires = now;
+            }
+        }
+        self.recheck = self.recheck.min(self.expires);
+        Ok(())
+    }
+
+    /// Count the packets sent since the last report
+    fn report(&mut self)
+    {
+        if self.unreported_tx == 0 {
+            return;
+        }
+        if let Ok(mut pcb) = PCB.lock() {
+            pcb.data_request_tx += self.unreported_tx;
+            self.unreported_tx = 0;
+        }
+    }
+
+    /// Send a JTP packet on the flow
+    ///     - encrypted when jtp_crypto is enabled, the session is with the NID the name resolved to
+    pub async fn send(&mut self, buf: &[u8])
+        -> Result<(), String>
+    {
+        let now = Instant::now();
+        if now >= self.recheck {
+            self.report();
+            self.check_next_hop(now)?;
+        }
+        if now >= self.expires || ROUTE_EPOCH.load(Ordering::Relaxed) != self.epoch {
+            self.resolve().await?;
+        }
+
+        // the payload after the cached header, sealed with the session when encrypted
+        self.packet.truncate(self.header_length);
+        if CONFIG.jtp_crypto.enabled {
+            let sealed = jtp_seal(&self.emulator_socket, &self.destination_nid, buf).await?;
+            self.packet.extend_from_slice(&sealed);
+        } else {
+            self.packet.extend_from_slice(buf);
+        }
+        set_payload_length(&mut self.packet);
+
+        // send the ILNP packet to the underlay network for sending over unicast
+        // the route is resolved again before the next packet if it failed
+        if let Err(err) = underlay_uni_tx(&self.emulator_socket, &self.next_hop.0, &self.next_hop.1, &self.packet).await {
+            self.expires = Instant::now();
+            self.recheck = self.expires;
+            return Err(err);
+        }
+
+        // count the data packets
+        self.unreported_tx += 1;
+
+        Ok(())
+    }
+}
+impl Drop for JtpFlow {
+    fn drop(&mut self)
+    {
+        self.report();
+    }
+}
+
+/// Open a JTP flow to a FQDN
+///     - resolves the route once, see JtpFlow
+pub async fn jtp_connect(emulator_socket: &EmulatorSocket, destination_fqdn: &str)
+    -> Result<JtpFlow, String>
+{
+    let mut flow = JtpFlow {
+        emulator_socket: emulator_socket.clone(),
+        destination_fqdn: destination_fqdn.to_string(),
+        destination_nid: 0,
+        next_hop: (Ipv6Addr::UNSPECIFIED, 0),
+        next_hop_nid: None,
+        packet: Vec::new(),
+        header_length: 0,
+        epoch: 0,
+        expires: Instant::now(),
+        recheck: Instant::now(),
+        unreported_tx: 0
+    };
+    flow.resolve().await?;
+    Ok(flow)
+}
+
 /// JTP receiver
 ///     - (-1) for blocking
 ///     - (0) for pool
@@ -1,15 +1,20 @@
-use crate::{layers::underlay_network::underlay_multi_tx, models::{network_models::EmulatorSocket, network_packets::{INLPv6Packet, JCMP_Basic_Pck, JCMP_DNS_FQDN_Query_Packet, JCMP_DNS_FQDN_Response_Packet, JCMP_DNS_ILV_Response_Packet, JCMP_ND_Advertisement, JCMP_Pck, JCMP_Router_Request, JCMP_Router_Response}}, services::network_services::{get_over_interface_by_name, get_over_interfaces}};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{layers::underlay_network::{underlay_multi_tx, underlay_uni_tx}, models::{jcmp_auth::{jcmp_auth_append, JcmpAuthTrailer}, network_models::{EmulatorSocket, NeighbourEntry}, network_packets::{INLPv6Packet, JcmpMessage}, wire_format::encode_packet}, services::{network_services::{get_over_interface_by_locator, get_over_interface_by_name, get_over_interfaces, get_session_nonce}, time_services::get_current_timestamp}};
 
 use super::{CONFIG, PCB};
 
+/// JCMP authentication counter
+///     - sent in the trailer so messages in the same microsecond are still unique
+static JCMP_AUTH_COUNTER: AtomicU32 = AtomicU32::new(0);
+
 /// NS - Neighbour Solicitation
 pub async fn jcmp_tx_solicitation(emulator_socket: &EmulatorSocket, destination_nid:&u64, interface_name: &String)
     -> Result<(), String>
 {
 
     // create nd solicitation
-    let jcmp_pck = JCMP_Basic_Pck::new()
-        .with_packet_code(0);
+    let jcmp_pck = JcmpMessage::NeighbourSolicitation;
 
     let (source_locator, _) = get_over_interface_by_name(interface_name)?;
 
@@ -27,14 +32,33 @@ pub async fn jcmp_tx_solicitation(emulator_socket: &EmulatorSocket, destination_
     Ok(())
 }
 
+/// NS - Unicast Neighbour Solicitation
+///     - reachability probe sent straight to the neighbour's cached (IPv6, port)
+pub async fn jcmp_tx_unicast_solicitation(emulator_socket: &EmulatorSocket, destination_nid: &u64, neighbour: &NeighbourEntry)
+    -> Result<(), String>
+{
+    // create nd solicitation
+    let jcmp_pck = JcmpMessage::NeighbourSolicitation;
+
+    // the neighbour is on the network of the interface
+    let (locator, _) = get_over_interface_by_name(&neighbour.interface)?;
+    let ilnp_pck_vec = jcmp_packet(emulator_socket, destination_nid, &locator, &locator, &jcmp_pck)?;
+    underlay_uni_tx(emulator_socket, &neighbour.address, &neighbour.port, &ilnp_pck_vec).await?;
+
+    // count JCMP transmit
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.nd_probe_jcmp_tx += 1;
+    }
+
+    Ok(())
+}
+
 /// NA - Neighbour Advertisement
 pub async fn jcmp_tx_advertisement(emulator_socket: &EmulatorSocket, destination_nid: &u64, interface_name: &String)
     -> Result<(), String>
 {
     // create nd advertisement
-    let jcmp_pck = JCMP_ND_Advertisement {
-        header: JCMP_Basic_Pck::new()
-            .with_packet_code(1),
+    let jcmp_pck = JcmpMessage::NeighbourAdvertisement {
         destination_port: emulator_socket.local_network.local_port
     };
 
@@ -53,6 +77,82 @@ pub async fn jcmp_tx_advertisement(emulator_socket: &EmulatorSocket, destination
     Ok(())
 }
 
+/// NA - Unsolicited Neighbour Advertisement
+///     - sent to the all nodes placeholder (0x00000000ff02ff01) on every network we joined
+pub async fn jcmp_tx_unsolicited_advertisement(emulator_socket: &EmulatorSocket)
+    -> Result<(), String>
+{
+    // placeholder
+    let destination_nid:u64 = 0x00000000ff02ff01;
+
+    // create nd advertisement
+    let jcmp_pck = JcmpMessage::NeighbourAdvertisement {
+        destination_port: emulator_socket.local_network.local_port
+    };
+
+    // send an advertisement for each network we are connected to
+    for (interface_name, (source_locator, _)) in get_over_interfaces()? {
+        jcmp_tx(emulator_socket, &destination_nid, &source_locator, &interface_name, &jcmp_pck).await?;
+        if let Ok(mut pcb) = PCB.lock() {
+            pcb.nd_unsolicited_advertisement_jcmp_tx += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// RS - Router Solicitation
+pub async fn jcmp_tx_router_solicitation(emulator_socket: &EmulatorSocket, interface_name: &String)
+    -> Result<(), String>
+{
+    // placeholder
+    let destination_nid:u64 = 0x00000000ff02ff02;
+
+    // create the packet
+    let jcmp_solicitation_pck = JcmpMessage::RouterSolicitation;
+
+    // send the solicitation
+    let (source_locator, _) = get_over_interface_by_name(interface_name)?;
+    jcmp_tx(emulator_socket, &destination_nid, &source_locator, interface_name, &jcmp_solicitation_pck).await?;
+
+    // count JCMP transmit
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.router_solicitation_jcmp_tx += 1;
+    }
+
+    Ok(())
+}
+
+/// RA - Router Advertisement
+///     - carries every locator we are connected to
+///     - destination is the soliciting host or the all nodes placeholder (0x00000000ff02ff01)
+pub async fn jcmp_tx_router_advertisement(emulator_socket: &EmulatorSocket, destination_nid: &u64, interface_name: &String)
+    -> Result<(), String>
+{
+    // locators we serve
+    let locators: Vec<u64> = get_over_interfaces()?
+        .into_iter()
+        .map(|(_, (locator, _))| locator)
+        .collect();
+
+    // create the packet
+    let jcmp_advertisement_pck = JcmpMessage::RouterAdvertisement {
+        lifetime: CONFIG.router_discovery.router_lifetime_s,
+        locators
+    };
+
+    // send the advertisement
+    let (source_locator, _) = get_over_interface_by_name(interface_name)?;
+    jcmp_tx(emulator_socket, destination_nid, &source_locator, interface_name, &jcmp_advertisement_pck).await?;
+
+    // count JCMP transmit
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.router_advertisement_jcmp_tx += 1;
+    }
+
+    Ok(())
+}
+
 /// DNS - FQDN Query
 pub async fn jcmp_tx_dns_fqdn_query(emulator_socket: &EmulatorSocket, destination_name: &String)
     -> Result<(), String>
@@ -61,10 +161,8 @@ pub async fn jcmp_tx_dns_fqdn_query(emulator_socket: &EmulatorSocket, destinatio
     let dns_holder:u64 = 0x0000000053535353;
 
     // create the packet
-    let jcmp_dnsquery_pck = JCMP_DNS_FQDN_Query_Packet {
-        header: JCMP_Basic_Pck::new()
-            .with_packet_code(4),
-        fqdn:  destination_name.clone().into_bytes()
+    let jcmp_dnsquery_pck = JcmpMessage::DnsFqdnQuery {
+        fqdn: destination_name.clone()
     };
 
     // send the packet on dns interface
@@ -86,10 +184,9 @@ pub async fn jcmp_tx_dns_fqdn_response(emulator_socket: &EmulatorSocket, destina
     -> Result<(), String>
 {
     // create the packet
-    let jcmp_dnsresponse_pck = JCMP_DNS_FQDN_Response_Packet {
-        header: JCMP_Basic_Pck::new().with_packet_code(5),
+    let jcmp_dnsresponse_pck = JcmpMessage::DnsFqdnResponse {
         ttl: CONFIG.network.DNS_TTL_S,
-        fqdn: CONFIG.node.name.clone().into_bytes()
+        fqdn: CONFIG.node.name.clone()
     };
 
     // get interfaces
@@ -125,8 +222,7 @@ pub async fn jcmp_tx_dns_ilv_query(emulator_socket: &EmulatorSocket, destination
     let dns_holder:u64 = 0x0000000053535353;
 
     // create the packet
-    let jcmp_ilvquery_pck = JCMP_Basic_Pck::new()
-        .with_packet_code(6);
+    let jcmp_ilvquery_pck = JcmpMessage::DnsIlvQuery;
 
     // send request on DNS interface
     let _ = jcmp_tx(emulator_socket, destination_nid, &dns_holder, &"dns".to_string(), &jcmp_ilvquery_pck).await?;
@@ -147,9 +243,9 @@ pub async fn jcmp_tx_dns_ilv_response(emulator_socket: &EmulatorSocket, destinat
     -> Result<(), String>
 {
     // create the packet
-    let jcmp_ilvresponse_pck = JCMP_DNS_ILV_Response_Packet::new()
-        .with_packet_code(7)
-        .with_ttl(CONFIG.network.DNS_TTL_S);
+    let jcmp_ilvresponse_pck = JcmpMessage::DnsIlvResponse {
+        ttl: CONFIG.network.DNS_TTL_S
+    };
 
     // get interfaces
     match get_over_interfaces() {
@@ -185,10 +281,10 @@ pub async fn jcmp_tx_router_request(emulator_socket: &EmulatorSocket, lookup_loc
     let destination_nid:u64 = 0x00000000ff02ff02;
 
     // create the packet
-    let jcmp_routerquery_pck = JCMP_Router_Request::new()
-        .with_packet_code(8)
-        .with_hop_count(hop_count.clone())
-        .with_destination_locator(lookup_locator.clone());
+    let jcmp_routerquery_pck = JcmpMessage::RouterRequest {
+        hop_count: *hop_count,
+        destination_locator: *lookup_locator
+    };
 
     // send request
     let (source_locator, _) = get_over_interface_by_name(interface_name)?;
@@ -210,11 +306,11 @@ pub async fn jcmp_tx_router_response(emulator_socket: &EmulatorSocket, lookup_lo
     -> Result<(), String>
 {
     // create the packet
-    let jcmp_routerresponse_pck = JCMP_Router_Response::new()
-        .with_packet_code(9)
-        .with_hop_count(hop_count.clone())
-        .with_destination_locator(lookup_locator.clone())
-        .with_ttl(CONFIG.network.AD_HOC_TTL_S);
+    let jcmp_routerresponse_pck = JcmpMessage::RouterResponse {
+        hop_count: *hop_count,
+        destination_locator: *lookup_locator,
+        ttl: CONFIG.network.AD_HOC_TTL_S
+    };
 
     // send request
     let (source_locator, _) = get_over_interface_by_name(interface_name)?;
@@ -231,34 +327,115 @@ pub async fn jcmp_tx_router_response(emulator_socket: &EmulatorSocket, lookup_lo
     Ok(())
 }
 
-// JCMP TX - Send Control Message
-pub async fn jcmp_tx(emulator_socket: &EmulatorSocket, destination_nid: &u64, source_locator:&u64, interface_name: &String, jcmp_pck:&dyn JCMP_Pck)
+/// DAD - Duplicate NID Probe
+///     - addressed to our own NID, the nonce tells our looped back probes apart
+pub async fn jcmp_tx_duplicate_nid_probe(emulator_socket: &EmulatorSocket, nonce: &u32, interface_name: &String)
+    -> Result<(), String>
+{
+    // create the packet
+    let jcmp_probe_pck = JcmpMessage::DuplicateNidProbe {
+        nonce: *nonce
+    };
+
+    // send the probe
+    let (source_locator, _) = get_over_interface_by_name(interface_name)?;
+    jcmp_tx(emulator_socket, &emulator_socket.local_network.local_nid, &source_locator, interface_name, &jcmp_probe_pck).await?;
+
+    // count JCMP transmit
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.duplicate_nid_probe_jcmp_tx += 1;
+    }
+
+    Ok(())
+}
+
+/// DAD - Duplicate NID Defend
+///     - echoes the nonce of the probe we are answering
+pub async fn jcmp_tx_duplicate_nid_defend(emulator_socket: &EmulatorSocket, nonce: &u32, interface_name: &String)
+    -> Result<(), String>
+{
+    // create the packet
+    let jcmp_defend_pck = JcmpMessage::DuplicateNidDefend {
+        nonce: *nonce
+    };
+
+    // send the defence
+    let (source_locator, _) = get_over_interface_by_name(interface_name)?;
+    jcmp_tx(emulator_socket, &emulator_socket.local_network.local_nid, &source_locator, interface_name, &jcmp_defend_pck).await?;
+
+    // count JCMP transmit
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.duplicate_nid_defend_jcmp_tx += 1;
+    }
+
+    Ok(())
+}
+
+/// Destination Unreachable
+///     - sent to the source of a packet we dropped for lack of a route
+///     - JCMP isn't forwarded so only senders on one of our networks are told
+pub async fn jcmp_tx_destination_unreachable(emulator_socket: &EmulatorSocket, dropped_pck: &INLPv6Packet)
     -> Result<(), String>
 {
-    let jcmp_buf = jcmp_pck.into_bytes();
+    // create the packet
+    let jcmp_unreachable_pck = JcmpMessage::DestinationUnreachable {
+        destination_locator: dropped_pck.destination_locator(),
+        destination_nid: dropped_pck.destination_identifier()
+    };
+
+    // send it on the sender's network
+    let interface_name = get_over_interface_by_locator(&dropped_pck.source_locator())?;
+    jcmp_tx(emulator_socket, &dropped_pck.source_identifier(), &dropped_pck.source_locator(), &interface_name, &jcmp_unreachable_pck).await?;
+
+    // count JCMP transmit
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.destination_unreachable_jcmp_tx += 1;
+    }
 
+    Ok(())
+}
+
+// JCMP TX - Send Control Message
+pub async fn jcmp_tx(emulator_socket: &EmulatorSocket, destination_nid: &u64, source_locator:&u64, interface_name: &String, jcmp_pck: &JcmpMessage)
+    -> Result<(), String>
+{
     // get locator for given interface name
     let (destination_locator, _) = get_over_interface_by_name(interface_name)?;
+    let ilnp_pck_vec = jcmp_packet(emulator_socket, destination_nid, source_locator, &destination_locator, jcmp_pck)?;
+
+    // send the multicast packet
+    let _ = underlay_multi_tx(emulator_socket, interface_name, &ilnp_pck_vec).await;
+
+    Ok(())
+}
+
+// JCMP - Build the ILNP packet
+fn jcmp_packet(emulator_socket: &EmulatorSocket, destination_nid: &u64, source_locator: &u64, destination_locator: &u64, jcmp_pck: &JcmpMessage)
+    -> Result<Vec<u8>, String>
+{
+    let mut jcmp_buf = jcmp_pck.encode();
 
     // create the ILNPv6 header
     let inlp_pck = INLPv6Packet::new()
         .with_version(6)
         .with_traffic_class(0)
         .with_flow_label(0)
-        .with_payload_length(jcmp_buf.len() as u16)
         .with_next_header(150)
         .with_hop_limit(1)
-        .with_source_locator(source_locator.clone())
+        .with_source_locator(*source_locator)
         .with_source_identifier(emulator_socket.local_network.local_nid)
-        .with_destination_locator(destination_locator.clone())
-        .with_destination_identifier(destination_nid.clone())
-        .into_bytes();
-
-    let mut ilnp_pck_vec: Vec<u8> = inlp_pck.to_vec();
-    ilnp_pck_vec.extend_from_slice(&jcmp_buf);
-
-    // send the multicast packet
-    let _ = underlay_multi_tx(emulator_socket, interface_name, &ilnp_pck_vec).await;
+        .with_destination_locator(*destination_locator)
+        .with_destination_identifier(*destination_nid);
+
+    // authenticated control plane
+    if CONFIG.jcmp_auth.enabled {
+        let trailer = JcmpAuthTrailer {
+            timestamp_us: get_current_timestamp()?,
+            counter: JCMP_AUTH_COUNTER.fetch_add(1, Ordering::Relaxed)
+        };
+        jcmp_auth_append(CONFIG.jcmp_auth.key.as_bytes(), &inlp_pck, &mut jcmp_buf, trailer);
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    let inlp_pck = inlp_pck.with_payload_length(jcmp_buf.len() as u16);
+    Ok(encode_packet(CONFIG.wire.format, inlp_pck, Some(get_session_nonce(destination_nid)?), &jcmp_buf))
+}
@@ -1,18 +1,18 @@
-use std::{net::{Ipv6Addr, SocketAddr}, sync::{Arc, Mutex}};
+use std::{collections::HashMap, net::{Ipv6Addr, SocketAddr}, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
 use once_cell::sync::Lazy;
 use overlay_handlers::{handle_destination_fqdn, handle_destination_ilv, handle_destination_nid, handle_ilnp_multicast_buffer, handle_ilnp_unicast_buffer, handle_path_discovery/*, handle_ilnp_buffer, handle_path_discovery*/};
-use tokio::{signal, sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, Mutex as TokioMutex}};
+use tokio::{signal, sync::{mpsc::{channel, error::TrySendError, Receiver, Sender}, Mutex as TokioMutex, Semaphore}, task::JoinHandle};
 use ttl_cache::TtlCache;
 use bytes::BytesMut;
 
 use crate::{
-    models::{config_models::Config, network_models::EmulatorSocket, network_packets::INLPv6Packet, protocol_control_block::ILNP_PCB_S}, 
-    services::{config_services::get_config, log_services::{log_error, log_info}, network_services::{get_over_interface_by_locator, get_over_interface_by_name, get_over_interfaces, get_over_locators, lookup_forwarding_table_route}, time_services::get_current_timestamp}, 
-    layers::underlay_network::{close_underlay_socket, open_underlay_socket, underlay_uni_tx}
+    models::{config_models::{Config, DadAction, LogTransport, QueueOverflow, WireFormat}, indexed_table::IndexedTable, log_models::{LogEvent, MAX_LOG_DATAGRAM}, network_models::{EmulatorSocket, HeldPacket, NeighbourEntry, PendingTable, TokenBucket}, network_packets::INLPv6Packet, protocol_control_block::{DropReason, ILNP_PCB_S}, wire_format::encode_packet}, 
+    services::{capture_services::{capture_packet, close_capture, flush_capture, open_capture, CaptureDirection}, config_services::get_config, log_services::{handle_log_datagram, is_log_datagram, log_error, log_flush, log_info, log_retransmit}, metrics_services::{close_metrics, open_metrics, record_event, write_snapshot}, network_services::{get_over_interface_by_locator, get_over_interface_by_name, get_over_interfaces, get_over_locators, get_session_nonce, get_under_ipv6_by_index, lookup_default_router, lookup_forwarding_table_route}, time_services::get_current_timestamp}, 
+    layers::underlay_network::{batch_io::{enable_gro, recv_batch}, close_underlay_socket, open_underlay_socket, underlay_uni_tx}
 };
 
 mod jcmp_tx;
-mod overlay_handlers;
+pub mod overlay_handlers;
 
 /// Node configurations 
 ///     - load config once to increase performance
@@ -26,42 +26,208 @@ pub static CONFIG: Lazy<Config> = Lazy::new(|| {
 ///     - globally accessible
 pub static PCB: Lazy<Mutex<ILNP_PCB_S>> = Lazy::new(|| Mutex::new(ILNP_PCB_S::default()));
 
+/// Nonce sent in the ILNP Nonce option (rfc6741 wire format)
+///     - one random value per node, used unless nonce sessions are enabled
+pub static LOCAL_NONCE: Lazy<u32> = Lazy::new(rand::random);
+
+/// Multicast receiver task
+///     - stopped when the socket is closed, log_flush then reads the multicast socket itself
+static MULTICAST_RECEIVER: Lazy<Mutex<Option<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Duplicate NID Detection
+///     - DAD_NONCE is carried by our probes so we can ignore them when they loop back
+///     - DAD_TENTATIVE is set while we are probing, our NID isn't defended yet
+///     - DUPLICATE_NID is set once another node claims our NID
+pub static DAD_NONCE: Lazy<u32> = Lazy::new(rand::random);
+static DAD_TENTATIVE: AtomicBool = AtomicBool::new(false);
+pub static DUPLICATE_NID: AtomicBool = AtomicBool::new(false);
+
+/// Count a dropped packet
+///     - increments the PCB counter for the given reason
+///     - reported with the PCB when the socket is closed
+pub fn count_drop(reason: DropReason)
+{
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.drops.count(reason);
+    }
+}
+
 /// Address Resolution Table (Neighbour Discovery)
-///     - maps NID to (interface, IPv6, Unicast Port)
+///     - maps NID to (interface, IPv6, Unicast Port) and its reachability
 ///     - equivalent of ARP table
-static NID_ADDRESS_RESOLUTION_TABLE: Lazy<Mutex<TtlCache<u64, (String, Ipv6Addr, u16)>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
+pub static NID_ADDRESS_RESOLUTION_TABLE: Lazy<Mutex<TtlCache<u64, NeighbourEntry>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
+
+/// Neighbour Address Index
+///     - maps (IPv6, Unicast Port) to the NID bound to it in the Address Resolution Table
+///     - locked after NID_ADDRESS_RESOLUTION_TABLE, entries are checked against the table before use
+pub static NEIGHBOUR_ADDRESS_INDEX: Lazy<Mutex<HashMap<(Ipv6Addr, u16), u64>>> = Lazy::new(|| { Mutex::new(HashMap::new()) });
 
 /// Name Resolution Table (DNS)
-///     - maps HashKey to (FQDN, NID, L64)
-///     - maps HashKey to (NID, L64)
-///     - HashKey is created using (NID, L64)
+///     - maps (NID, L64) to FQDN, indexed by FQDN
+///     - maps (NID, L64) to nothing, indexed by NID
 ///     - each entry is uniquely identifiable by the (NID, L64)
-pub static NAME_ILV_TABLE: Lazy<Mutex<TtlCache<u64, (String, u64, u64)>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
-pub static NID_ILV_TABLE: Lazy<Mutex<TtlCache<u64, (u64, u64)>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
+pub static NAME_ILV_TABLE: Lazy<IndexedTable<(u64, u64), String, String>> = Lazy::new(|| IndexedTable::new(CONFIG.network.ND_CACHE_SIZE, |_, fqdn| fqdn.clone()));
+pub static NID_ILV_TABLE: Lazy<IndexedTable<(u64, u64), u64, ()>> = Lazy::new(|| IndexedTable::new(CONFIG.network.ND_CACHE_SIZE, |(nid, _), _| *nid));
+
+/// Forwarding Entry
+///     - (next_hop (interface), hop_count)
+type ForwardingEntry = (String, u8);
 
 /// Forwarding Table
-///     - maps HashKey to (next_hop (NID), target locator (L64), next_hop (interface), hop_count)
-///     - HashKey is created using next_hop (NID) and target locator (L64)
+///     - maps (next_hop (NID), target locator (L64)) to (next_hop (interface), hop_count), indexed by target locator
 ///     - Each entry is uniquely identifiable by the next hop's identifier (NID) and the target locator (L64)
-pub static LOCATOR_FORWARDING_TABLE: Lazy<Mutex<TtlCache<u64, (u64, u64, String, u8)>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
-
-/// ILNP data packet queue
+pub static LOCATOR_FORWARDING_TABLE: Lazy<IndexedTable<(u64, u64), u64, ForwardingEntry>> = Lazy::new(|| IndexedTable::new(CONFIG.network.ND_CACHE_SIZE, |(_, locator), _| *locator));
+
+/// Default Router Key
+///     - (router NID, interface)
+type DefaultRouterKey = (u64, String);
+
+/// Default Router Table (Router Discovery)
+///     - maps (router NID, interface) to the locators the router serves
+///     - entries expire after the lifetime in the router's last advertisement
+pub static DEFAULT_ROUTER_TABLE: Lazy<Mutex<TtlCache<DefaultRouterKey, Vec<u64>>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
+
+/// Nonce Session
+///     - (nonce we send, (nonce they send, when it was last verified))
+pub type NonceSession = (u32, Option<(u32, Instant)>);
+
+/// Pending Resolution Tables
+///     - tasks waiting for an FQDN (DNS), an NID's locators (DNS ILV), an NID's address (ND) or a route to a locator (path discovery)
+///     - concurrent lookups for the same key share one query and are woken by the response handler
+///     - path discoveries are keyed by (locator, source interface) so every caller's flood skips only the network it came from
+pub static PENDING_FQDN_TABLE: Lazy<PendingTable<String>> = Lazy::new(|| PendingTable::new(Duration::from_millis(CONFIG.network.ND_RTO_MS)));
+pub static PENDING_ILV_TABLE: Lazy<PendingTable<u64>> = Lazy::new(|| PendingTable::new(Duration::from_millis(CONFIG.network.ND_RTO_MS)));
+pub static PENDING_ND_TABLE: Lazy<PendingTable<u64>> = Lazy::new(|| PendingTable::new(Duration::from_millis(CONFIG.network.ND_RTO_MS)));
+pub static PENDING_PATH_TABLE: Lazy<PendingTable<(u64, Option<String>)>> = Lazy::new(|| PendingTable::new(Duration::from_millis(CONFIG.network.AD_HOC_TIMEOUT_MS)));
+
+/// Nonce Session Table (RFC 6744)
+///     - maps correspondent NID to its nonce session
+///     - the correspondent's nonce is learned from the first packet they address to us and is trusted for session_ttl_s after the last verified packet
+///     - entries are kept twice as long after the last packet sent or verified, so our nonce outlives the correspondent's copy of it
+pub static NONCE_SESSION_TABLE: Lazy<Mutex<TtlCache<u64, NonceSession>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.network.ND_CACHE_SIZE)) });
+
+/// JCMP Replay Key
+///     - (sender NID, timestamp, counter)
+type JcmpReplayKey = (u64, u64, u32);
+
+/// JCMP Replay Cache
+///     - replay key of every authenticated JCMP message accepted
+///     - entries outlive the skew window so a replay is caught until its timestamp is stale anyway
+static JCMP_REPLAY_CACHE: Lazy<Mutex<TtlCache<JcmpReplayKey, ()>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.jcmp_auth.replay_cache_size)) });
+
+/// JCMP handler permits
+///     - bounds the number of JCMP packets handled at once
+pub static JCMP_HANDLER_PERMITS: Lazy<Arc<Semaphore>> = Lazy::new(|| Arc::new(Semaphore::new(CONFIG.jcmp_limits.max_concurrent_handlers)));
+
+/// JCMP Rate Limit Table
+///     - maps (source NID, JCMP code) to its token bucket
+///     - entries expire once the bucket would be full again
+static JCMP_RATE_LIMIT_TABLE: Lazy<Mutex<TtlCache<(u64, u8), TokenBucket>>> = Lazy::new(|| { Mutex::new(TtlCache::new(CONFIG.jcmp_limits.max_sources)) });
+
+/// Route Epoch
+///     - bumped whenever a cached route may have gone bad (neighbour removed, routes invalidated, destination unreachable)
+///     - senders caching a next hop (JtpFlow) resolve it again when it changes
+pub static ROUTE_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Hold Queue
+///     - maps a destination locator being discovered to the packets waiting for it
+///     - the task discovering the locator takes the whole queue when it's done
+pub static HOLD_QUEUE: Lazy<Mutex<HashMap<u64, Vec<HeldPacket>>>> = Lazy::new(|| { Mutex::new(HashMap::new()) });
+
+/// ILNP data packet queue of one worker
+///     - (buffer, length, sender) of every unicast datagram received
+type IlnpQueueItem = (BytesMut, usize, SocketAddr);
+type IlnpWorkerQueue = (Sender<IlnpQueueItem>, Arc<TokioMutex<Receiver<IlnpQueueItem>>>);
+
+/// ILNP data packet queues
 ///     - required to consume the unicast UDP packets as quick as possible to avoid drops
-pub static ILNP_QUEUE: Lazy<(UnboundedSender<(BytesMut, usize, SocketAddr)>, Arc<TokioMutex<UnboundedReceiver<(BytesMut, usize, SocketAddr)>>>)> = Lazy::new(|| {
-    let (tx, rx) = unbounded_channel();
-    (tx, Arc::new(TokioMutex::new(rx)))
+///     - one bounded queue per worker, a flow always goes to the same worker so its packets stay in order
+pub static ILNP_QUEUE: Lazy<Vec<IlnpWorkerQueue>> = Lazy::new(|| {
+    let workers = match CONFIG.pipeline.workers {
+        0 => std::thread::available_parallelism().map(|cores| cores.get()).unwrap_or(1),
+        workers => workers
+    };
+    (0..workers).map(|_| {
+        let (tx, rx) = channel(CONFIG.pipeline.queue_size.max(1));
+        (tx, Arc::new(TokioMutex::new(rx)))
+    }).collect()
 });
 
+/// Flow Shard
+///     - picks the worker for a received packet from its source NID, destination NID and flow label
+///     - anything too short for an ILNP header goes to the first worker, it's dropped there anyway
+pub fn flow_shard(buf: &[u8], shards: usize)
+    -> usize
+{
+    if buf.len() < 40 || shards <= 1 {
+        return 0;
+    }
+
+    // FNV-1a over the flow label and both NIDs
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let flow_label = [buf[1] & 0x0f, buf[2], buf[3]];
+    for byte in flow_label.iter().chain(&buf[16..24]).chain(&buf[32..40]) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+/// Enqueue ILNP packet
+///     - inserts a received packet into its flow's ILNP queue for processing
+///     - a full queue either drops the packet or stops receiving until the worker makes room
+async fn enqueue_ilnp_packet(emulator_socket: &EmulatorSocket, packet: BytesMut, len: usize, addr: SocketAddr)
+{
+    let ilnp_tx = &ILNP_QUEUE[flow_shard(&packet, ILNP_QUEUE.len())].0;
+    match ilnp_tx.try_send((packet, len, addr)) {
+        Ok(()) => {},
+        Err(TrySendError::Full(item)) => {
+
+            // drop policy
+            if CONFIG.pipeline.overflow == QueueOverflow::Drop {
+                count_drop(DropReason::IlnpQueueFull);
+            }
+
+            // backpressure, stop receiving until the worker makes room
+            else {
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.ilnp_queue_backpressure += 1;
+                }
+                if let Err(err) = ilnp_tx.send(item).await {
+                    count_drop(DropReason::IlnpQueueFailed);
+                    log_error(emulator_socket, &format!("enqueue_ilnp_packet(): error adding to ilnp queue: {}", err)).await;
+                }
+            }
+
+        },
+        Err(err) => {
+            count_drop(DropReason::IlnpQueueFailed);
+            log_error(emulator_socket, &format!("enqueue_ilnp_packet(): error adding to ilnp queue: {}", err)).await;
+        }
+    }
+}
+
+
 
 /// Open Socket
 ///     - opening socket at the ILNP layer
 ///     - thread created to consume and handle JCMP multicast packets
 ///     - thread created to consume JTP unicast packets
-///     - thread created to handle JTP unicast packets from a queue
+///     - threads created to handle JTP unicast packets from the per-flow queues
 pub async fn open_ilnp_socket() 
     -> Result<EmulatorSocket, String>
 {
 
+    // nonces are only carried by the rfc6741 wire format
+    if CONFIG.nonce.enabled && CONFIG.wire.format != WireFormat::Rfc6741 {
+        return Err("open_ilnp_socket(): nonce sessions need the rfc6741 wire format".to_string());
+    }
+
+    // an empty key would authenticate nothing
+    if CONFIG.jcmp_auth.enabled && CONFIG.jcmp_auth.key.is_empty() {
+        return Err("open_ilnp_socket(): jcmp_auth is enabled without a key".to_string());
+    }
+
     // protocol started - recording time for analysis
     let start_time = get_current_timestamp()?;
     match PCB.lock() {
@@ -73,6 +239,12 @@ pub async fn open_ilnp_socket()
 
     // create socket and clone it
     let emulator_socket = open_underlay_socket().await?;
+
+    // per-node metrics file, kept independently of the logger
+    open_metrics(&emulator_socket.local_network)?;
+
+    // optional pcapng capture of every overlay packet
+    open_capture(&emulator_socket.local_network)?;
     let emulator_socket_clone = EmulatorSocket {
         mulcast_socket: emulator_socket.mulcast_socket.clone(),
         unicast_socket: emulator_socket.unicast_socket.clone(),
@@ -90,15 +262,15 @@ pub async fn open_ilnp_socket()
     };
 
     // create async handler to receive multicast packets
-    // stop after ctrl+c
-    tokio::spawn(async move {
+    // stop after ctrl+c or when the socket is closed
+    let multicast_receiver = tokio::spawn(async move {
 
         // get locators we are connected to
         match get_over_locators() {
             Ok(connected_locators) => {
 
-                // more than enough for JCMP packets
-                let mut buf = [0; 1024];
+                // log records share the socket, sized so they aren't truncated
+                let mut buf = vec![0; MAX_LOG_DATAGRAM];
 
                 loop {
 
@@ -110,6 +282,13 @@ pub async fn open_ilnp_socket()
                             match result {
                                 Ok((len, addr)) => {
 
+                                    // log records and acknowledgements share the multicast socket
+                                    if is_log_datagram(&buf[..len]) {
+                                        handle_log_datagram(&emulator_socket_clone, &buf[..len]);
+                                        continue;
+                                    }
+                                    capture_packet(CaptureDirection::Inbound, &buf[..len]);
+
                                     // clone
                                     let emulator_socket_clone3 = EmulatorSocket {
                                         mulcast_socket: emulator_socket_clone.mulcast_socket.clone(),
@@ -117,12 +296,22 @@ pub async fn open_ilnp_socket()
                                         local_network: emulator_socket_clone.local_network.clone()
                                     };
                                     let connected_locators_clone = connected_locators.clone();
-                                    let buf_clone = buf.clone();
+                                    let packet = buf[..len].to_vec();
+
+                                    // bounded number of handlers, drop the packet if they're all busy
+                                    let permit = match JCMP_HANDLER_PERMITS.clone().try_acquire_owned() {
+                                        Ok(permit) => permit,
+                                        Err(_) => {
+                                            count_drop(DropReason::JcmpHandlerLimit);
+                                            continue;
+                                        }
+                                    };
 
                                     // create a new thread to handle the request
                                     // this will free up the reciever again
                                     tokio::spawn(async move {
-                                        handle_ilnp_multicast_buffer(&emulator_socket_clone3, &connected_locators_clone, &buf_clone, len, addr).await;
+                                        handle_ilnp_multicast_buffer(&emulator_socket_clone3, &connected_locators_clone, &packet, len, addr).await;
+                                        drop(permit);
                                     });
 
                                 },
@@ -144,6 +333,9 @@ pub async fn open_ilnp_socket()
             }
         }
     });
+    if let Ok(mut receiver) = MULTICAST_RECEIVER.lock() {
+        *receiver = Some(multicast_receiver);
+    }
 
     // create async handler to receive unicast packets
     // stop after ctrl+c
@@ -154,10 +346,51 @@ pub async fn open_ilnp_socket()
 
         // https://docs.rs/bytes/latest/bytes/index.html
         let mut buf = BytesMut::with_capacity(total_mtu as usize);
-        let ilnp_tx = &ILNP_QUEUE.0;
+
+        // batched receive, GRO only if the kernel accepts it
+        let mut batch_io = CONFIG.underlay.batch_io;
+        let gro = batch_io && CONFIG.underlay.gro && match enable_gro(&emulator_socket_clone2.unicast_socket) {
+            Ok(()) => true,
+            Err(err) => {
+                log_info(&emulator_socket_clone2, &format!("open_ilnp_socket(): receiving without GRO: {}", err)).await;
+                false
+            }
+        };
+        let mut datagrams: Vec<(BytesMut, SocketAddr)> = Vec::with_capacity(CONFIG.underlay.batch_size);
 
         loop {
 
+            // receive up to batch_size JTP packets per system call
+            if batch_io {
+                tokio::select! {
+                    result = recv_batch(&emulator_socket_clone2.unicast_socket, &mut buf, total_mtu as usize, CONFIG.underlay.batch_size, gro, &mut datagrams) => {
+                        match result {
+                            Ok(()) => {
+                                for (packet, addr) in datagrams.drain(..) {
+                                    capture_packet(CaptureDirection::Inbound, &packet);
+                                    let len = packet.len();
+                                    enqueue_ilnp_packet(&emulator_socket_clone2, packet, len, addr).await;
+                                }
+                            },
+
+                            // no recvmmsg, back to one packet per system call
+                            Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => {
+                                batch_io = false;
+                                log_info(&emulator_socket_clone2, &format!("open_ilnp_socket(): receiving without batch I/O: {}", err)).await;
+                            },
+                            Err(err) => {
+                                log_error(&emulator_socket_clone2, &format!("open_ilnp_socket(): error receiving a batch: {}", &err.to_string())).await;
+                            }
+                        }
+                    },
+                    _ = signal::ctrl_c() => {
+                        log_info(&emulator_socket_clone2, "open_ilnp_socket(): ctrl+c received, exiting the ilnp receiver handler").await;
+                        break;
+                    },
+                }
+                continue;
+            }
+
             // resize to MTU
             buf.resize(total_mtu as usize, 0);
 
@@ -171,15 +404,8 @@ pub async fn open_ilnp_socket()
 
                             // to reduce memory space buffer is reduced to packet size
                             buf.truncate(len);
-                            let packet = buf.clone();
-
-                            // packet inserted into the ILNP queue for processing
-                            match ilnp_tx.send((packet, len, addr)) {
-                                Ok(()) => {},
-                                Err(err) => {
-                                    log_error(&emulator_socket_clone2, &format!("open_ilnp_socket(): error adding to ilnp queue: {}", err)).await;
-                                }
-                            }
+                            capture_packet(CaptureDirection::Inbound, &buf);
+                            enqueue_ilnp_packet(&emulator_socket_clone2, buf.clone(), len, addr).await;
 
                             // replace buffer content with 0
                             buf.clear();
@@ -198,18 +424,181 @@ pub async fn open_ilnp_socket()
         }
     });
 
-    // create async handler to process the unicast packets
-    tokio::spawn(async move {
+    // create async handlers to process the unicast packets, one per queue
+    for (_, ilnp_rx) in ILNP_QUEUE.iter() {
+        let emulator_socket_clone3 = emulator_socket_clone3.clone();
+        let ilnp_rx = ilnp_rx.clone();
+        tokio::spawn(async move {
+
+            // lock the receiver while using it
+            // only this worker using it so lock indefinitly
+            let mut ilnp_rx = ilnp_rx.lock().await;
+
+            // loop to consume the queue and handle the packets
+            while let Some((mut buf, len, addr)) = ilnp_rx.recv().await {
+                handle_ilnp_unicast_buffer(&emulator_socket_clone3, &mut buf, len, addr).await;
+            }
+        });
+    }
+
+    // retransmit log records the logger hasn't acknowledged
+    if CONFIG.logging.transport == LogTransport::Reliable {
+        let emulator_socket_clone4 = emulator_socket.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(CONFIG.logging.retransmit_ms));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        log_retransmit(&emulator_socket_clone4).await;
+                    },
+                    _ = signal::ctrl_c() => {
+                        break;
+                    },
+                }
+            }
+        });
+    }
+
+    // write PCB snapshots to the metrics file
+    if CONFIG.metrics.enabled {
+        let local_network = emulator_socket.local_network.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(CONFIG.metrics.interval_ms));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let pcb_snapshot = match PCB.lock() {
+                            Ok(pcb) => Some(*pcb),
+                            Err(_) => None
+                        };
+                        if let Some(pcb) = pcb_snapshot {
+                            write_snapshot(&local_network, &pcb);
+                        }
+                    },
+                    _ = signal::ctrl_c() => {
+                        break;
+                    },
+                }
+            }
+        });
+    }
 
-        // lock the receiver while using it
-        // only this function using it so lock indefinitly
-        let mut ilnp_rx = ILNP_QUEUE.1.lock().await;
+    // flush the capture file so it can be read while we run
+    if CONFIG.capture.enabled {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(CONFIG.capture.flush_ms.max(1)));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        flush_capture();
+                    },
+                    _ = signal::ctrl_c() => {
+                        break;
+                    },
+                }
+            }
+        });
+    }
 
-        // loop to consume the queue and handle the packets
-        while let Some((buf, len, addr)) = ilnp_rx.recv().await {
-            handle_ilnp_unicast_buffer(&emulator_socket_clone3, &buf.as_ref(), len, addr).await;
+    // make sure nobody else is using our NID
+    // leave the networks again if we refuse to come up
+    if CONFIG.dad.enabled {
+        if let Err(err) = detect_duplicate_nid(&emulator_socket).await {
+            close_capture();
+            let _ = close_underlay_socket(emulator_socket).await;
+            return Err(err);
         }
-    });
+    }
+
+    // tell the neighbours where we are now, they may still have the (IPv6, port) of our last run
+    if CONFIG.neighbour_discovery.unsolicited_advertisements {
+        match jcmp_tx::jcmp_tx_unsolicited_advertisement(&emulator_socket).await {
+            Ok(()) => {},
+            Err(err) => {
+                log_error(&emulator_socket, &err).await;
+            }
+        }
+
+        // the port is fixed for the socket's life but the underlay address can change
+        if CONFIG.neighbour_discovery.address_check_interval_ms > 0 && !CONFIG.app.logger {
+            let emulator_socket_clone6 = emulator_socket.clone();
+            tokio::spawn(async move {
+                let mut advertised_ipv6 = emulator_socket_clone6.local_network.local_ipv6;
+                let mut interval = tokio::time::interval(Duration::from_millis(CONFIG.neighbour_discovery.address_check_interval_ms));
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {
+                            match get_under_ipv6_by_index(emulator_socket_clone6.local_network.local_index) {
+                                Ok(current_ipv6) => {
+                                    if current_ipv6 != advertised_ipv6 {
+                                        log_info(&emulator_socket_clone6, &format!("open_ilnp_socket(): underlay address changed from {} to {}", advertised_ipv6, current_ipv6)).await;
+                                        advertised_ipv6 = current_ipv6;
+                                        match jcmp_tx::jcmp_tx_unsolicited_advertisement(&emulator_socket_clone6).await {
+                                            Ok(()) => {},
+                                            Err(err) => {
+                                                log_error(&emulator_socket_clone6, &err).await;
+                                            }
+                                        }
+                                    }
+                                },
+                                Err(err) => {
+                                    log_error(&emulator_socket_clone6, &err).await;
+                                }
+                            }
+                        },
+                        _ = signal::ctrl_c() => {
+                            break;
+                        },
+                    }
+                }
+            });
+        }
+    }
+
+    // router discovery
+    // routers advertise periodically, hosts ask for the routers once they're up
+    if CONFIG.router_discovery.enabled {
+        if CONFIG.node.router {
+            let emulator_socket_clone5 = emulator_socket.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.router_discovery.advertisement_interval_s));
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {
+                            match get_over_interfaces() {
+                                Ok(interfaces) => {
+                                    for (interface_name, _) in interfaces {
+                                        match jcmp_tx::jcmp_tx_router_advertisement(&emulator_socket_clone5, &0x00000000ff02ff01, &interface_name).await {
+                                            Ok(()) => {},
+                                            Err(err) => {
+                                                log_error(&emulator_socket_clone5, &err).await;
+                                            }
+                                        }
+                                    }
+                                },
+                                Err(err) => {
+                                    log_error(&emulator_socket_clone5, &err).await;
+                                }
+                            }
+                        },
+                        _ = signal::ctrl_c() => {
+                            break;
+                        },
+                    }
+                }
+            });
+        }
+        else {
+            for (interface_name, _) in get_over_interfaces()? {
+                match jcmp_tx::jcmp_tx_router_solicitation(&emulator_socket, &interface_name).await {
+                    Ok(()) => {},
+                    Err(err) => {
+                        log_error(&emulator_socket, &err).await;
+                    }
+                }
+            }
+        }
+    }
 
     // signal that node is up and running as expected
     log_info(&emulator_socket, "open_ilnp_socket(): ILNP layer running.").await;
@@ -227,6 +616,52 @@ pub async fn open_ilnp_socket()
 }
 
 
+/// Duplicate NID Detection
+///     - probes every network we joined for our NID, the receivers answer or defend
+///     - waits one more interval after the last probe for late defences
+///     - on a conflict we either refuse to come up or carry on after raising the alarm
+async fn detect_duplicate_nid(emulator_socket: &EmulatorSocket)
+    -> Result<(), String>
+{
+    // nothing to probe (e.g. the logger)
+    let interfaces = get_over_interfaces()?;
+    if interfaces.is_empty() {
+        return Ok(());
+    }
+
+    DAD_TENTATIVE.store(true, Ordering::Relaxed);
+    for _ in 0..CONFIG.dad.probes {
+        for (interface_name, _) in interfaces.iter() {
+            match jcmp_tx::jcmp_tx_duplicate_nid_probe(emulator_socket, &DAD_NONCE, interface_name).await {
+                Ok(()) => {},
+                Err(err) => {
+                    log_error(emulator_socket, &err).await;
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(CONFIG.dad.probe_interval_ms)).await;
+        if DUPLICATE_NID.load(Ordering::Relaxed) {
+            break;
+        }
+    }
+    DAD_TENTATIVE.store(false, Ordering::Relaxed);
+
+    if DUPLICATE_NID.load(Ordering::Relaxed) {
+        let err = format!("detect_duplicate_nid(): NID 0x{:016X} is already in use", emulator_socket.local_network.local_nid);
+        match CONFIG.dad.on_conflict {
+            DadAction::Refuse => {
+                return Err(err);
+            },
+            DadAction::Alarm => {
+                log_error(emulator_socket, &err).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
 /// Close Socket
 ///     - send the node's PCB to the logger
 ///     - close the underlay socket
@@ -244,20 +679,37 @@ pub async fn close_ilnp_socket(emulator_socket: EmulatorSocket)
     }
 
     // send the pcb to the logger
-    // note PCB;NB corresponds to the topology or number of routers we are testing with
+    // note topology corresponds to the topology or number of routers we are testing with
     // this was necessary to distinguish the entries in the logs
-    match PCB.lock() {
-        Ok(pcb) => {
-            match pcb.to_json_string() {
-                Ok(json_string) => {
-                    log_info(&emulator_socket, &format!("PCB;9;{}", json_string)).await;
-                },
-                Err(_) => {}
-            }
-        },
-        Err(_) => {}
+    let pcb_snapshot = match PCB.lock() {
+        Ok(pcb) => Some(*pcb),
+        Err(_) => None
+    };
+    if let Some(pcb) = pcb_snapshot {
+        write_snapshot(&emulator_socket.local_network, &pcb);
+        record_event(&emulator_socket, LogEvent::Pcb { topology: 9, pcb }).await;
+    }
+
+    // wait for the metrics writer to write the final snapshot
+    if let Err(err) = tokio::task::spawn_blocking(close_metrics).await {
+        eprintln!("SYSTEMLOG: close_ilnp_socket(): failed to close metrics: {}", err);
+    }
+
+    // stop the multicast receiver so log_flush is the only reader of the socket
+    let multicast_receiver = match MULTICAST_RECEIVER.lock() {
+        Ok(mut receiver) => receiver.take(),
+        Err(_) => None
+    };
+    if let Some(multicast_receiver) = multicast_receiver {
+        multicast_receiver.abort();
+        let _ = multicast_receiver.await;
     }
 
+    // make sure the logger received everything before leaving the log group
+    log_flush(&emulator_socket).await;
+
+    close_capture();
+
     // close underlay socket
     close_underlay_socket(emulator_socket).await
 }
@@ -332,6 +784,16 @@ pub async fn ilnp_nid_tx(emulator_socket: &EmulatorSocket, destination_nid:&u64,
 
                 }
 
+                // hosts hand off-link traffic to a default router
+                if result.0 == Ipv6Addr::UNSPECIFIED && !CONFIG.node.router {
+                    for (_, destination_locator) in &dns_entries {
+                        if let Some((router_ipv6, router_port, source_locator)) = default_router_next_hop(emulator_socket, destination_locator).await {
+                            result = (router_ipv6, router_port, source_locator, *destination_nid, *destination_locator);
+                            break;
+                        }
+                    }
+                }
+
                 // next hop wasn't found in the forwarding table
                 // need to perform path discovery
                 if result.0 == Ipv6Addr::UNSPECIFIED {
@@ -391,14 +853,12 @@ pub async fn ilnp_nid_tx(emulator_socket: &EmulatorSocket, destination_nid:&u64,
         .with_flow_label(0)
         .with_payload_length(buf.len() as u16)
         .with_next_header(151)
-        .with_hop_limit(1)
+        .with_hop_limit(CONFIG.forwarding.hop_limit)
         .with_source_locator(result.2)
         .with_source_identifier(emulator_socket.local_network.local_nid)
         .with_destination_locator(result.4)
-        .with_destination_identifier(result.3)
-        .into_bytes();
-    let mut pck_vec: Vec<u8> = inlp_pck.to_vec();
-    pck_vec.extend_from_slice(&buf);
+        .with_destination_identifier(result.3);
+    let pck_vec = encode_packet(CONFIG.wire.format, inlp_pck, Some(get_session_nonce(&result.3)?), buf);
 
     // send the ILNP packet to the underlay network for sending over unicast
     let _ = underlay_uni_tx(emulator_socket, &result.0, &result.1, &pck_vec).await?;
@@ -415,12 +875,78 @@ pub async fn ilnp_nid_tx(emulator_socket: &EmulatorSocket, destination_nid:&u64,
     
 }
 
+/// Default router as the next hop
+///     - only once router discovery found a router
+///     - returns (router IPv6, router port, source locator)
+async fn default_router_next_hop(emulator_socket: &EmulatorSocket, destination_locator: &u64)
+    -> Option<(Ipv6Addr, u16, u64)>
+{
+    let (router_nid, interface_name) = match lookup_default_router(destination_locator) {
+        Ok(router) => router,
+        Err(_) => {
+            return None;
+        }
+    };
+    let (source_locator, _) = match get_over_interface_by_name(&interface_name) {
+        Ok(interface) => interface,
+        Err(err) => {
+            log_error(emulator_socket, &err).await;
+            return None;
+        }
+    };
+
+    // address resolution for the router
+    match handle_destination_nid(emulator_socket, &router_nid, &interface_name).await {
+        Ok((router_ipv6, router_port)) => {
+            Some((router_ipv6, router_port, source_locator))
+        },
+        Err(err) => {
+            log_error(emulator_socket, &err).await;
+            None
+        }
+    }
+}
+
 /// TX Unicast using FQDN
 ///     - get ILV using FQDN
 ///     - try to send locally
 ///     - try to forward packet
 pub async fn ilnp_fqdn_tx(emulator_socket: &EmulatorSocket, destination_fqdn:&String, buf:&[u8])
     -> Result<(), String>
+{
+    let result = resolve_fqdn_route(emulator_socket, destination_fqdn).await?;
+
+    // create the ILNPv6 header
+    let inlp_pck = INLPv6Packet::new()
+        .with_version(6)
+        .with_traffic_class(0)
+        .with_flow_label(0)
+        .with_payload_length(buf.len() as u16)
+        .with_next_header(151)
+        .with_hop_limit(CONFIG.forwarding.hop_limit)
+        .with_source_locator(result.2)
+        .with_source_identifier(emulator_socket.local_network.local_nid)
+        .with_destination_locator(result.4)
+        .with_destination_identifier(result.3);
+    let pck_vec = encode_packet(CONFIG.wire.format, inlp_pck, Some(get_session_nonce(&result.3)?), buf);
+
+    // send the ILNP packet to the underlay network for sending over unicast
+    underlay_uni_tx(emulator_socket, &result.0, &result.1, &pck_vec).await?;
+
+    // count the data packets
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.data_request_tx += 1;
+    }
+
+    Ok(())
+
+}
+
+/// Resolve the route to a FQDN
+///     - get ILV using FQDN, then the next hop: the node itself, a known route, a default router or path discovery
+///     - returns (next hop IPv6, next hop port, source locator, destination NID, destination locator)
+pub async fn resolve_fqdn_route(emulator_socket: &EmulatorSocket, destination_fqdn:&String)
+    -> Result<(Ipv6Addr, u16, u64, u64, u64), String>
 {
     // get ILV for FQDN
     let dns_entries = handle_destination_fqdn(emulator_socket, destination_fqdn).await?;
@@ -484,6 +1010,16 @@ pub async fn ilnp_fqdn_tx(emulator_socket: &EmulatorSocket, destination_fqdn:&St
         }
     }
     
+    // hosts hand off-link traffic to a default router
+    if result.0 == Ipv6Addr::UNSPECIFIED && !CONFIG.node.router {
+        for (destination_nid, destination_locator) in &dns_entries {
+            if let Some((router_ipv6, router_port, source_locator)) = default_router_next_hop(emulator_socket, destination_locator).await {
+                result = (router_ipv6, router_port, source_locator, *destination_nid, *destination_locator);
+                break;
+            }
+        }
+    }
+
     // if we haven't already resolved a route for the locator
     if result.0 == Ipv6Addr::UNSPECIFIED {
 
@@ -494,7 +1030,7 @@ pub async fn ilnp_fqdn_tx(emulator_socket: &EmulatorSocket, destination_fqdn:&St
             // this is not essential for the protocol
             // the number here needs to be changed as we increase the number of routers in our analysis
             if CONFIG.app.test_convergence {
-                log_info(emulator_socket, "DISCOVERY_STARTED;1").await;
+                record_event(emulator_socket, LogEvent::DiscoveryStarted { topology: 1 }).await;
             }
 
             // path discovery
@@ -504,7 +1040,7 @@ pub async fn ilnp_fqdn_tx(emulator_socket: &EmulatorSocket, destination_fqdn:&St
                     // measure ending time of path discovery
                     // the number here needs to be changed as we increase the number of routers in our analysis
                     if CONFIG.app.test_convergence {
-                        log_info(emulator_socket, "DISCOVERY_COMPLETED;1").await;
+                        record_event(emulator_socket, LogEvent::DiscoveryCompleted { topology: 1 }).await;
                     }
 
                     match get_over_interface_by_name(&interface_name) {
@@ -536,37 +1072,9 @@ pub async fn ilnp_fqdn_tx(emulator_socket: &EmulatorSocket, destination_fqdn:&St
 
     // could not resolve host
     if result.0 == Ipv6Addr::UNSPECIFIED {
-        return Err("ilnp_fqdn_tx(): couldn't resolve host".to_string());
+        return Err("resolve_fqdn_route(): couldn't resolve host".to_string());
     }
 
-    // create the ILNPv6 header
-    let inlp_pck = INLPv6Packet::new()
-        .with_version(6)
-        .with_traffic_class(0)
-        .with_flow_label(0)
-        .with_payload_length(buf.len() as u16)
-        .with_next_header(151)
-        .with_hop_limit(1)
-        .with_source_locator(result.2)
-        .with_source_identifier(emulator_socket.local_network.local_nid)
-        .with_destination_locator(result.4)
-        .with_destination_identifier(result.3)
-        .into_bytes();
-
-    let mut pck_vec: Vec<u8> = inlp_pck.to_vec();
-    pck_vec.extend_from_slice(&buf);
-
-    // send the ILNP packet to the underlay network for sending over unicast
-    let _ = underlay_uni_tx(emulator_socket, &result.0, &result.1, &pck_vec).await?;
-
-    // count the data packets
-    match PCB.lock() {
-        Ok(mut pcb) => {
-            pcb.data_request_tx += 1;
-        },
-        Err(_) => {}
-    }
-
-    Ok(())
+    Ok(result)
 
 }
\ No newline at end of file
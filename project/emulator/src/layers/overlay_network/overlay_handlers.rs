@@ -1,10 +1,10 @@
-use std::{net::{IpAddr, Ipv6Addr, SocketAddr}, thread, time::Duration};
+use std::{collections::HashSet, net::{IpAddr, Ipv6Addr, SocketAddr}, sync::atomic::Ordering, time::{Duration, Instant}};
+use std::borrow::Cow;
 use std::convert::TryInto;
 
-use tokio::time::Instant;
 
-use crate::{layers::{jtp_network::JTP_QUEUE, underlay_network::underlay_uni_tx}, models::{network_models::{EmulatorSocket, JTPResponse}, network_packets::{INLPv6Packet, JCMP_DNS_FQDN_Query_Packet, JCMP_DNS_FQDN_Response_Packet, JCMP_DNS_ILV_Response_Packet, JCMP_ND_Advertisement, JCMP_Router_Request, JCMP_Router_Response}}, services::{log_services::log_error, network_services::{get_over_interface_by_locator, get_over_interfaces, insert_into_forwarding_table, insert_into_name_ilv_table, insert_into_nid_ilv_table, lookup_forwarding_table, lookup_forwarding_table_route, lookup_name_ilv_table, lookup_nid_ilv_table}}};
-use super::{jcmp_tx::{jcmp_tx_advertisement, jcmp_tx_dns_fqdn_query, jcmp_tx_dns_fqdn_response, jcmp_tx_dns_ilv_query, jcmp_tx_dns_ilv_response, jcmp_tx_router_request, jcmp_tx_router_response, jcmp_tx_solicitation}, CONFIG, NID_ADDRESS_RESOLUTION_TABLE, PCB};
+use crate::{layers::{jtp_network::{jtp_crypto::jtp_crypto_rx, JTP_QUEUE}, underlay_network::{underlay_uni_tx, underlay_uni_tx_batch}}, models::{config_models::{ValidationAction, WireFormat}, jcmp_auth::{jcmp_auth_verify, JcmpAuthError}, log_models::LogEvent, network_models::{EmulatorSocket, ForwardOutcome, HeldPacket, JTPResponse, NeighbourEntry, NeighbourState, NonceCheck, TokenBucket}, protocol_control_block::{DropReason, HeaderCheck}, network_packets::{DecodeError, INLPv6Packet, JcmpMessage}, wire_format::{decode_packet, rewrite_hop_limit, WireError, WirePacket}}, services::{log_services::log_error, metrics_services::record_event, time_services::get_current_timestamp, network_services::{check_session_nonce, reset_session_nonce, confirm_neighbour_cache, get_over_interface_by_locator, get_over_interfaces, insert_into_default_router_table, insert_into_forwarding_table, insert_into_neighbour_cache, insert_into_name_ilv_table, insert_into_nid_ilv_table, has_session_nonce, invalidate_routes_via, lookup_forwarding_table, lookup_forwarding_table_route, lookup_name_ilv_table, lookup_neighbour_cache, lookup_nid_ilv_table, probe_neighbour_cache, remove_from_neighbour_cache}}};
+use super::{count_drop, jcmp_tx::{jcmp_tx_advertisement, jcmp_tx_destination_unreachable, jcmp_tx_dns_fqdn_query, jcmp_tx_dns_fqdn_response, jcmp_tx_dns_ilv_query, jcmp_tx_dns_ilv_response, jcmp_tx_duplicate_nid_defend, jcmp_tx_router_advertisement, jcmp_tx_router_request, jcmp_tx_router_response, jcmp_tx_solicitation, jcmp_tx_unicast_solicitation}, CONFIG, DAD_NONCE, DAD_TENTATIVE, DUPLICATE_NID, HOLD_QUEUE, JCMP_RATE_LIMIT_TABLE, JCMP_REPLAY_CACHE, PCB, PENDING_FQDN_TABLE, PENDING_ILV_TABLE, PENDING_ND_TABLE, PENDING_PATH_TABLE};
 
 
 /// Handler for the JCMP multicast receiver
@@ -16,6 +16,7 @@ pub async fn handle_ilnp_multicast_buffer(emulator_socket: &EmulatorSocket, conn
     // extract source IPv6
     match addr.ip() {
         IpAddr::V4(_) => {
+            count_drop(DropReason::Ipv4Source);
             log_error(&emulator_socket, "handle_ilnp_multicast_buffer(): received invalid packet: ipv4 packet").await;
             return;
         },
@@ -24,6 +25,7 @@ pub async fn handle_ilnp_multicast_buffer(emulator_socket: &EmulatorSocket, conn
             // check packet has ILNP header
             if len < 40 {
                 //log_error(&emulator_socket, "handle_ilnp_multicast_buffer(): received invalid multicast packet: packet too small").await;
+                count_drop(DropReason::MulticastTooShort);
                 return;
             }
 
@@ -34,6 +36,20 @@ pub async fn handle_ilnp_multicast_buffer(emulator_socket: &EmulatorSocket, conn
                     // parse the header into the struct
                     let ilnp_pck = INLPv6Packet::from_bytes(ilnp_header);
 
+                    // remove the rfc6741 extension headers
+                    let packet = match decode_wire_packet(emulator_socket, ilnp_pck, &buf[..len]).await {
+                        Some(packet) => packet,
+                        None => {
+                            return;
+                        }
+                    };
+                    let ilnp_pck = packet.header;
+
+                    // strict header checks
+                    if !validate_ilnp_header(emulator_socket, &ilnp_pck, packet.payload.len(), 150, DropReason::MulticastNotJcmp).await {
+                        return;
+                    }
+
                     // check the Next Header byte to check it's a JCMP packet
                     if ilnp_pck.next_header() == 150 {
                     
@@ -43,15 +59,20 @@ pub async fn handle_ilnp_multicast_buffer(emulator_socket: &EmulatorSocket, conn
                         if connected_locators.contains(&ilnp_pck.destination_locator()) {
         
                             // check if packet code (in JCMP header) is included
-                            if len < 41 {
+                            if packet.payload.is_empty() {
+                                count_drop(DropReason::JcmpMissingCode);
                                 log_error(&emulator_socket, "handle_ilnp_multicast_buffer(): received invalid jcmp packet: missing code").await;
                                 return;
                             }
             
                             // send to JCMP handler
-                            let payload = &buf[40..len];
-                            handle_jcmp_packet(emulator_socket, source_address, ilnp_pck, payload).await;
+                            handle_jcmp_packet(emulator_socket, source_address, ilnp_pck, packet.nonce, &packet.payload).await;
+
+                        }
 
+                        // not one of our networks
+                        else {
+                            count_drop(DropReason::MulticastForeignLocator);
                         }
         
                     }
@@ -59,11 +80,13 @@ pub async fn handle_ilnp_multicast_buffer(emulator_socket: &EmulatorSocket, conn
                     else {
                         // log packets are being received here - causing infifinite loop
                         // log_error(&emulator_socket, "handle_ilnp_multicast_buffer(): received invalid packet: wrong header or type").await;
+                        count_drop(DropReason::MulticastNotJcmp);
                         return;
                     }
         
                 },
                 Err(err) => {
+                    count_drop(DropReason::HeaderMalformed);
                     log_error(&emulator_socket, &format!("handle_ilnp_multicast_buffer(): failed to parse ilnp header: {}", err)).await;
                 }
             }
@@ -75,12 +98,13 @@ pub async fn handle_ilnp_multicast_buffer(emulator_socket: &EmulatorSocket, conn
 
 
 /// Handler for the JTP unicast receiver
-pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &[u8], len: usize, addr: SocketAddr)
+pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &mut [u8], len: usize, addr: SocketAddr)
 {
 
     // extract source IPv6
     match addr.ip() {
         IpAddr::V4(_) => {
+            count_drop(DropReason::Ipv4Source);
             log_error(&emulator_socket, "handle_ilnp_unicast_buffer(): received ipv4 packet").await;
             return;
         },
@@ -88,6 +112,7 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
     
             // check packet has ilnp header
             if len < 40 {
+                count_drop(DropReason::UnicastTooShort);
                 log_error(&emulator_socket, "handle_ilnp_unicast_buffer(): received invalid packet: packet too small").await;
                 return;
             }
@@ -99,15 +124,51 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
                     // parse header into a struct
                     let ilnp_pck = INLPv6Packet::from_bytes(ilnp_header);    
 
+                    // remove the rfc6741 extension headers
+                    let packet = match decode_wire_packet(emulator_socket, ilnp_pck, &buf[..len]).await {
+                        Some(packet) => packet,
+                        None => {
+                            return;
+                        }
+                    };
+                    let ilnp_pck = packet.header;
+
+                    // strict header checks
+                    // JCMP only comes over unicast as a reachability probe
+                    let expected_next_header = if ilnp_pck.next_header() == 150 { 150 } else { 151 };
+                    if !validate_ilnp_header(emulator_socket, &ilnp_pck, packet.payload.len(), expected_next_header, DropReason::UnicastWrongHeader).await {
+                        return;
+                    }
+
+                    // unicast probe
+                    if ilnp_pck.next_header() == 150 && ilnp_pck.destination_identifier() == emulator_socket.local_network.local_nid {
+                        if let SocketAddr::V6(source) = addr {
+                            handle_jcmp_packet(emulator_socket, *source.ip(), ilnp_pck, packet.nonce, &packet.payload).await;
+                        }
+                        return;
+                    }
+
                     // check Next.Header is 151 for JTP packets
                     if ilnp_pck.next_header() == 151 {
 
+                        // upper layer hint, the neighbour that sent it is still reachable
+                        if let SocketAddr::V6(source) = addr {
+                            if let Err(err) = confirm_neighbour_cache(source.ip(), source.port()) {
+                                log_error(emulator_socket, &err).await;
+                            }
+                        }
+
                         // unicast payload
-                        let payload = &buf[40..len];
+                        let payload = &packet.payload[..];
 
                         // check the packet is for us
                         if ilnp_pck.destination_identifier() == emulator_socket.local_network.local_nid {
 
+                            // check the correspondent's nonce
+                            if !check_nonce(emulator_socket, &ilnp_pck, packet.nonce, false, false).await {
+                                return;
+                            }
+
                             // count packet received
                             let mut err: String = "".to_string();
                             match PCB.lock() {
@@ -130,9 +191,23 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
                                 destination_nid: ilnp_pck.destination_identifier(),
                                 payload: payload.to_vec()
                             };
+
+                            // handshake and decryption when JTP encryption is enabled
+                            let jtp_receive = if CONFIG.jtp_crypto.enabled {
+                                match jtp_crypto_rx(emulator_socket, jtp_receive).await {
+                                    Some(jtp_receive) => jtp_receive,
+                                    None => {
+                                        return;
+                                    }
+                                }
+                            } else {
+                                jtp_receive
+                            };
+
                             let tx = &JTP_QUEUE.0;
                             if let Err(/*err*/_) = tx.try_send(jtp_receive) {
                                 //log_error(&emulator_socket, &format!("handle_ilnp_unicast_buffer(): failed to add ilnp buffer to JTP queue: {}", err)).await;
+                                count_drop(DropReason::JtpQueueFull);
                             }
 
                         }
@@ -141,10 +216,10 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
                         // forwarding packet only if we are a router
                         else if CONFIG.node.router {
 
-                            // handler to forward packets
-                            match handle_router_forward(&emulator_socket, &ilnp_pck, &payload).await
+                            // handler to forward packets, the received buffer is sent on as it is
+                            match handle_router_forward(emulator_socket, &ilnp_pck, &mut buf[..len]).await
                             {
-                                Ok(()) => {
+                                Ok(ForwardOutcome::Sent) => {
 
                                     // count forwarding packets
                                     match PCB.lock() {
@@ -157,7 +232,18 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
 
                                     //log_info(&emulator_socket, "handle_router_forward(): successufully forwarded packet").await;
                                 },
+                                Ok(ForwardOutcome::Held) => {
+
+                                    // counted as forwarded when the hold queue is flushed
+                                    if let Ok(mut pcb) = PCB.lock() {
+                                        pcb.data_request_forward_rx += 1;
+                                        pcb.data_request_held += 1;
+                                    }
+
+                                },
+                                Ok(ForwardOutcome::Dropped) => {},
                                 Err(err) => {
+                                    count_drop(DropReason::ForwardFailed);
                                     log_error(&emulator_socket, &err).await;
                                 }
                             }
@@ -165,17 +251,20 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
                         }
 
                         else {
+                            count_drop(DropReason::UnicastNotForUs);
                             log_error(&emulator_socket, "handle_ilnp_unicast_buffer(): received packet not intended for us").await;
                             return;
                         }
 
                     } else {
+                        count_drop(DropReason::UnicastWrongHeader);
                         log_error(&emulator_socket, "handle_ilnp_unicast_buffer(): received invalid packet: wrong header or type").await;
                         return;
                     }
 
                 },
                 Err(err) => {
+                    count_drop(DropReason::HeaderMalformed);
                     log_error(&emulator_socket, &format!("handle_ilnp_unicast_buffer(): failed to parse ilnp header: {}", err)).await;
                 }
             }
@@ -185,271 +274,540 @@ pub async fn handle_ilnp_unicast_buffer(emulator_socket: &EmulatorSocket, buf: &
 }
 
 
-/// Handles the different types of JCMP packets
-///     - Packet Code 0     (Neigbhour Solicitation)
-///     - Packet Code 1     (Neigbhour Advertisement) 
-///     - Packet Code 4     (DNS FQDN Request)
-///     - Packet Code 5     (DNS FQDN Response)
-///     - Packet Code 6     (DNS ILV Request)
-///     - Packet Code 7     (DNS ILV Response)
-///     - Packet Code 8     (Router Request)
-///     - Packet Code 9     (Router Response)
-async fn handle_jcmp_packet(emulator_socket: &EmulatorSocket, source_address: Ipv6Addr,  ilnp_header: INLPv6Packet, jcmp_payload: &[u8])
+/// Decode the received packet into the native layout
+///     - native packets are passed through untouched
+///     - rfc6741 packets have the destination options and ICMPv6 header removed
+///     - returns None if the packet is discarded
+async fn decode_wire_packet<'a>(emulator_socket: &EmulatorSocket, ilnp_pck: INLPv6Packet, buf: &'a [u8])
+    -> Option<WirePacket<'a>>
 {
+    if CONFIG.wire.format == WireFormat::Native {
+        return Some(WirePacket { header: ilnp_pck, nonce: None, payload: Cow::Borrowed(&buf[40..]) });
+    }
 
-    // check for neighbour solicitation
-    if jcmp_payload[0] == 0 {
+    match decode_packet(buf) {
+        Ok(packet) => {
+            Some(packet)
+        },
+        Err(err) => {
+            match err {
+                WireError::ChecksumMismatch { .. } => count_drop(DropReason::ChecksumMismatch),
+                _ => count_drop(DropReason::WireMalformed)
+            }
+            log_error(emulator_socket, &format!("decode_wire_packet(): dropped rfc6741 packet: {}", err)).await;
+            None
+        }
+    }
+}
 
-        // check the solicitation is for us and we didn't send out the request
-        if CONFIG.node.nid  == ilnp_header.destination_identifier() && CONFIG.node.nid != ilnp_header.source_identifier() {
+/// Verify the JCMP authentication trailer
+///     - only when jcmp_auth is enabled
+///     - the HMAC has to be valid under the network key
+///     - the timestamp has to be within max_skew_ms and (sender, timestamp, counter) not seen before
+///     - returns the message without the trailer, None if the packet is discarded
+async fn authenticate_jcmp<'a>(emulator_socket: &EmulatorSocket, ilnp_header: &INLPv6Packet, jcmp_payload: &'a [u8])
+    -> Option<&'a [u8]>
+{
+    if !CONFIG.jcmp_auth.enabled {
+        return Some(jcmp_payload);
+    }
 
-            // count jcmp request
-            match PCB.lock() {
-                Ok(mut pcb) => {
-                    pcb.nd_solicitation_jcmp_rx += 1;
-                },
-                Err(_) => {}
+    let (jcmp_message, trailer) = match jcmp_auth_verify(CONFIG.jcmp_auth.key.as_bytes(), ilnp_header, jcmp_payload) {
+        Ok(verified) => verified,
+        Err(err) => {
+            match err {
+                JcmpAuthError::Missing { .. } => count_drop(DropReason::JcmpAuthMissing),
+                JcmpAuthError::InvalidTag => count_drop(DropReason::JcmpAuthInvalid)
             }
+            log_error(emulator_socket, &format!("authenticate_jcmp(): dropped jcmp packet from 0x{:016X}: {}", ilnp_header.source_identifier(), err)).await;
+            return None;
+        }
+    };
+
+    // stale or future timestamps
+    let max_skew_us = CONFIG.jcmp_auth.max_skew_ms * 1000;
+    let now = match get_current_timestamp() {
+        Ok(now) => now,
+        Err(err) => {
+            log_error(emulator_socket, &err).await;
+            return None;
+        }
+    };
+    if now.abs_diff(trailer.timestamp_us) > max_skew_us {
+        count_drop(DropReason::JcmpReplay);
+        log_error(emulator_socket, &format!("authenticate_jcmp(): dropped jcmp packet from 0x{:016X}: timestamp outside the skew window", ilnp_header.source_identifier())).await;
+        return None;
+    }
 
-            // get the interface name for the locator we received
-            match get_over_interface_by_locator(&ilnp_header.destination_locator()) {
-                Ok(inf_name) => {
+    // messages already seen
+    let key = (ilnp_header.source_identifier(), trailer.timestamp_us, trailer.counter);
+    let replayed = match JCMP_REPLAY_CACHE.lock() {
+        Ok(mut cache) => {
+            cache.insert(key, (), Duration::from_micros(2 * max_skew_us)).is_some()
+        },
+        Err(_) => true
+    };
+    if replayed {
+        count_drop(DropReason::JcmpReplay);
+        log_error(emulator_socket, &format!("authenticate_jcmp(): dropped jcmp packet from 0x{:016X}: replayed", ilnp_header.source_identifier())).await;
+        return None;
+    }
 
-                    // respond with neighbour advertisement
-                    match jcmp_tx_advertisement(emulator_socket, &ilnp_header.source_identifier(), &inf_name).await {
-                        Ok(())  => {},
-                        Err(err) => {
-                            log_error(emulator_socket, &err).await;
-                        }
-                    }
+    Some(jcmp_message)
+}
+
+/// Take a token from the (source NID, JCMP code) bucket
+///     - only when jcmp_limits.rate_limit is enabled
+///     - our own multicast packets aren't limited
+///     - returns false if the packet is throttled
+fn rate_limit_jcmp(ilnp_header: &INLPv6Packet, jcmp_payload: &[u8])
+    -> bool
+{
+    if !CONFIG.jcmp_limits.rate_limit || ilnp_header.source_identifier() == CONFIG.node.nid {
+        return true;
+    }
+
+    let code = jcmp_payload.first().copied().unwrap_or_default();
+    let key = (ilnp_header.source_identifier(), code);
+    let size = CONFIG.jcmp_limits.bucket_size;
+    let refill_per_s = CONFIG.jcmp_limits.refill_per_s.max(1);
+
+    // an expired bucket is a full one
+    let refill_time = Duration::from_secs_f64(size as f64 / refill_per_s as f64);
+    let now = std::time::Instant::now();
+
+    match JCMP_RATE_LIMIT_TABLE.lock() {
+        Ok(mut table) => {
+            let mut bucket = match table.get(&key) {
+                Some(bucket) => *bucket,
+                None => TokenBucket::new(size, now)
+            };
+            let allowed = bucket.take(size, refill_per_s, now);
+            table.insert(key, bucket, refill_time);
+            allowed
+        },
+        Err(_) => true
+    }
+}
 
+/// Check the nonce of a received packet (RFC 6744)
+///     - only when nonce sessions are enabled
+///     - packets addressed to us have to carry the correspondent's nonce, the first one establishes the session
+///     - overheard packets can't be checked, the ones updating bindings are dropped once the sender has a session
+///     - an unsolicited advertisement to all nodes means the sender restarted or moved, it can't carry our session's nonce
+///       so it is accepted and the sender's nonce is forgotten, the neighbour stays unconfirmed until NUD reaches it
+///     - returns false if the packet is discarded
+async fn check_nonce(emulator_socket: &EmulatorSocket, ilnp_pck: &INLPv6Packet, nonce: Option<u32>, updates_bindings: bool, unsolicited: bool)
+    -> bool
+{
+    if !CONFIG.nonce.enabled {
+        return true;
+    }
+
+    // our own multicast packets
+    let source_nid = ilnp_pck.source_identifier();
+    if source_nid == emulator_socket.local_network.local_nid {
+        return true;
+    }
+
+    // overheard packets
+    if ilnp_pck.destination_identifier() != emulator_socket.local_network.local_nid {
+        if !updates_bindings {
+            return true;
+        }
+        if unsolicited {
+            match reset_session_nonce(&source_nid) {
+                Ok(true) => {
+                    record_event(emulator_socket, LogEvent::NonceSessionReset { nid: source_nid }).await;
+                    return true;
+                },
+                Ok(false) => {
+                    return true;
                 },
                 Err(err) => {
                     log_error(emulator_socket, &err).await;
+                    return false;
                 }
             }
+        }
+        match has_session_nonce(&source_nid) {
+            Ok(false) => {
+                return true;
+            },
+            Ok(true) => {
+                count_drop(DropReason::NonceUnverified);
+                return false;
+            },
+            Err(err) => {
+                log_error(emulator_socket, &err).await;
+                return false;
+            }
+        }
+    }
 
+    match check_session_nonce(&source_nid, nonce) {
+        Ok(NonceCheck::Established) => {
+            if let Some(nonce) = nonce {
+                record_event(emulator_socket, LogEvent::NonceSessionEstablished { nid: source_nid, nonce }).await;
+            }
+            true
+        },
+        Ok(NonceCheck::Verified) => {
+            true
+        },
+        Ok(NonceCheck::Missing) => {
+            count_drop(DropReason::NonceMissing);
+            log_error(emulator_socket, &format!("check_nonce(): dropped packet from 0x{:016X}: missing nonce", source_nid)).await;
+            false
+        },
+        Ok(NonceCheck::Mismatch) => {
+            count_drop(DropReason::NonceMismatch);
+            log_error(emulator_socket, &format!("check_nonce(): dropped packet from 0x{:016X}: wrong nonce", source_nid)).await;
+            false
+        },
+        Err(err) => {
+            log_error(emulator_socket, &err).await;
+            false
         }
+    }
+}
+
+/// Validate a received ILNP header
+///     - payload_length has to match the bytes received (LengthMismatch if short, TrailingBytes if padded)
+///     - version has to be 6
+///     - next header has to be the one handled by the socket (150 multicast, 151 unicast)
+///     - source NID and locator can't be unspecified (0) or all ones
+///     - the configured action decides what happens to a failure, returns false if the packet is discarded
+async fn validate_ilnp_header(emulator_socket: &EmulatorSocket, ilnp_pck: &INLPv6Packet, payload_len: usize, expected_next_header: u8, next_header_drop: DropReason)
+    -> bool
+{
+    let mut failures: Vec<(HeaderCheck, DropReason, String)> = Vec::new();
 
+    if ilnp_pck.version() != 6 {
+        failures.push((HeaderCheck::WrongVersion, DropReason::WrongVersion, format!("version {}", ilnp_pck.version())));
     }
 
-    // check for neighbour advertisement
-    else if jcmp_payload[0] == 1 {
+    let payload_length = ilnp_pck.payload_length() as usize;
+    if payload_length > payload_len {
+        failures.push((HeaderCheck::LengthMismatch, DropReason::LengthMismatch, format!("payload_length {} but {} bytes received", payload_length, payload_len)));
+    }
+    else if payload_length < payload_len {
+        failures.push((HeaderCheck::TrailingBytes, DropReason::TrailingBytes, format!("payload_length {} but {} bytes received", payload_length, payload_len)));
+    }
 
-        // here we could check packet is meant for us but since ND is multicast we can collect all ads
-        if CONFIG.node.nid != ilnp_header.source_identifier() {
+    if ilnp_pck.next_header() != expected_next_header {
+        failures.push((HeaderCheck::UnknownNextHeader, next_header_drop, format!("next header {}", ilnp_pck.next_header())));
+    }
 
-            // count jcmp response
-            match PCB.lock() {
-                Ok(mut pcb) => {
-                    pcb.nd_advertisement_jcmp_rx += 1;
-                },
-                Err(_) => {}
-            }
+    let source_identifier = ilnp_pck.source_identifier();
+    let source_locator = ilnp_pck.source_locator();
+    if source_identifier == 0 || source_identifier == u64::MAX || source_locator == 0 || source_locator == u64::MAX {
+        failures.push((HeaderCheck::InvalidSource, DropReason::InvalidSource, format!("source 0x{:016X}:0x{:016X}", source_locator, source_identifier)));
+    }
 
-            // parse the response
-            match JCMP_ND_Advertisement::from_bytes(jcmp_payload) {
-                Ok(jcmp_nd_pck) => {
+    for (check, reason, detail) in failures {
 
-                    // get the interface name for the locator we received
-                    match get_over_interface_by_locator(&ilnp_header.source_locator()) {
-                        Ok(interface_name) => {
+        // failures are counted whatever the action
+        if let Ok(mut pcb) = PCB.lock() {
+            pcb.validation.count(check);
+        }
 
-                            // add source (intervace, IPv6, port) mapped to source NID
-                            match NID_ADDRESS_RESOLUTION_TABLE.lock() {
-                                Ok(mut map)  => {
-                                    map.insert(ilnp_header.source_identifier(), (interface_name, source_address, jcmp_nd_pck.destination_port), Duration::from_secs(CONFIG.network.ND_TTL_S));
-                                    return;
-                                },
-                                Err(_) => {}
-                            }
-                            log_error(emulator_socket, &format!("handle_jcmp_packet(): failed to insert ND AD to NID_INTERFACE_IP_TABLE")).await;
+        let action = match check {
+            HeaderCheck::LengthMismatch => CONFIG.validation.length_mismatch,
+            HeaderCheck::TrailingBytes => CONFIG.validation.trailing_bytes,
+            HeaderCheck::WrongVersion => CONFIG.validation.wrong_version,
+            HeaderCheck::UnknownNextHeader => CONFIG.validation.unknown_next_header,
+            HeaderCheck::InvalidSource => CONFIG.validation.invalid_source
+        };
+        match action {
+            ValidationAction::Drop => {
+                count_drop(reason);
+                return false;
+            },
+            ValidationAction::Log => {
+                count_drop(reason);
+                log_error(emulator_socket, &format!("validate_ilnp_header(): dropped packet: {:?}: {}", check, detail)).await;
+                return false;
+            },
+            ValidationAction::Count => {}
+        }
+    }
 
-                        },
-                        Err(err)  => {
-                            log_error(emulator_socket, &err).await;
-                        }
-                    }
+    true
+}
 
-                },
-                Err(err) => {
-                    log_error(emulator_socket, &err).await;
-                }
-            }
 
+/// Another node is using our NID
+///     - marks the NID as duplicated for the probing phase
+///     - counted and logged as an alarm once we are up
+async fn report_duplicate_nid(emulator_socket: &EmulatorSocket, ilnp_header: &INLPv6Packet)
+{
+    DUPLICATE_NID.store(true, Ordering::Relaxed);
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.duplicate_nid_detected += 1;
+    }
+    log_error(emulator_socket, &format!("report_duplicate_nid(): NID 0x{:016X} is also used on locator 0x{:016X}", ilnp_header.destination_identifier(), ilnp_header.source_locator())).await;
+}
+
+
+/// Handles the different types of JCMP packets
+///     - Packet Code 0     (Neigbhour Solicitation)
+///     - Packet Code 1     (Neigbhour Advertisement) 
+///     - Packet Code 2     (Router Solicitation)
+///     - Packet Code 3     (Router Advertisement)
+///     - Packet Code 4     (DNS FQDN Request)
+///     - Packet Code 5     (DNS FQDN Response)
+///     - Packet Code 6     (DNS ILV Request)
+///     - Packet Code 7     (DNS ILV Response)
+///     - Packet Code 8     (Router Request)
+///     - Packet Code 9     (Router Response)
+///     - Packet Code 10    (Duplicate NID Probe)
+///     - Packet Code 11    (Duplicate NID Defend)
+async fn handle_jcmp_packet(emulator_socket: &EmulatorSocket, source_address: Ipv6Addr,  ilnp_header: INLPv6Packet, nonce: Option<u32>, jcmp_payload: &[u8])
+{
+
+    // authenticated control plane, checked before any handler touches our state
+    let jcmp_payload = match authenticate_jcmp(emulator_socket, &ilnp_header, jcmp_payload).await {
+        Some(jcmp_payload) => jcmp_payload,
+        None => {
+            return;
         }
+    };
 
+    // flood protection per source and code
+    if !rate_limit_jcmp(&ilnp_header, jcmp_payload) {
+        count_drop(DropReason::JcmpRateLimited);
+        return;
     }
 
-    // check for dns FQDN lookup
-    else if jcmp_payload[0] == 4 {
+    // decode the message
+    let jcmp_message = match JcmpMessage::decode(jcmp_payload) {
+        Ok(jcmp_message) => {
+            jcmp_message
+        },
+        Err(DecodeError::UnknownCode(code)) => {
+            count_drop(DropReason::JcmpUnsupported);
+            log_error(emulator_socket, &format!("handle_jcmp_packet(): jcmp packet code {:?} not supported", code)).await;
+            return;
+        },
+        Err(err) => {
+            count_drop(DropReason::JcmpMalformed);
+            log_error(emulator_socket, &format!("handle_jcmp_packet(): malformed jcmp packet: {}", err)).await;
+            return;
+        }
+    };
+
+    // advertisements and responses update our tables so they need a verified nonce
+    // unsolicited router advertisements go to all nodes so they can't carry a correspondent's nonce
+    let updates_bindings = matches!(jcmp_message,
+        JcmpMessage::NeighbourAdvertisement { .. } | JcmpMessage::DnsFqdnResponse { .. } | JcmpMessage::DnsIlvResponse { .. } | JcmpMessage::RouterResponse { .. });
+    let unsolicited = matches!(jcmp_message, JcmpMessage::NeighbourAdvertisement { .. }) && ilnp_header.destination_identifier() == 0x00000000ff02ff01;
+    if !check_nonce(emulator_socket, &ilnp_header, nonce, updates_bindings, unsolicited).await {
+        return;
+    }
 
-        // check we didn't send out the request
-        if CONFIG.node.nid != ilnp_header.source_identifier() {
+    match jcmp_message {
 
-            // count jcmp request
-            match PCB.lock() {
-                Ok(mut pcb) => {
-                    pcb.dns_fqdn_query_jcmp_rx += 1;
-                },
-                Err(_) => {}
-            }
+        // neighbour solicitation
+        JcmpMessage::NeighbourSolicitation => {
 
-            // parse the response
-            match JCMP_DNS_FQDN_Query_Packet::from_bytes(jcmp_payload) {
-                Ok(jcmp_query_pck) => {
+            // check the solicitation is for us and we didn't send out the request
+            if CONFIG.node.nid  == ilnp_header.destination_identifier() && CONFIG.node.nid != ilnp_header.source_identifier() {
 
-                    match String::from_utf8(jcmp_query_pck.fqdn) {
-                        Ok(fqdn) => {
+                // count jcmp request
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.nd_solicitation_jcmp_rx += 1;
+                }
 
-                            // check dns name is ours
-                            if emulator_socket.local_network.local_fqdn == fqdn {
-
-                                // send dns response
-                                match jcmp_tx_dns_fqdn_response(emulator_socket, &ilnp_header.source_identifier()).await {
-                                    Ok(()) => {},
-                                    Err(err) => {
-                                        log_error(emulator_socket, &err).await;
-                                    }
-                                }
+                // get the interface name for the locator we received
+                match get_over_interface_by_locator(&ilnp_header.destination_locator()) {
+                    Ok(inf_name) => {
 
+                        // respond with neighbour advertisement
+                        match jcmp_tx_advertisement(emulator_socket, &ilnp_header.source_identifier(), &inf_name).await {
+                            Ok(())  => {},
+                            Err(err) => {
+                                log_error(emulator_socket, &err).await;
                             }
-                        }, 
-                        Err(err)  => {
-                            log_error(emulator_socket, &format!("handle_ilnp_buffer(): couldn't convert fqdn from DNS query: {}", err)).await
                         }
-                    }
 
-                },
-                Err(err) => {
-                    log_error(emulator_socket, &err).await;
+                    },
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
                 }
 
             }
 
-        }
+        },
 
-    }
+        // neighbour advertisement
+        JcmpMessage::NeighbourAdvertisement { destination_port } => {
 
-    // check for DNS FQDN response
-    else if jcmp_payload[0] == 5 {
+            // here we could check packet is meant for us but since ND is multicast we can collect all ads
+            if CONFIG.node.nid != ilnp_header.source_identifier() {
 
-        // check we didn't send the response
-        // collect all responses except ours to reduce packet overhead
-        if CONFIG.node.nid != ilnp_header.source_identifier() {
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.nd_advertisement_jcmp_rx += 1;
+                }
+
+                // get the interface name for the locator we received
+                match get_over_interface_by_locator(&ilnp_header.source_locator()) {
+                    Ok(interface_name) => {
+
+                        // add source (intervace, IPv6, port) mapped to source NID
+                        // an answer to our solicitation confirms the neighbour is reachable
+                        let solicited = CONFIG.node.nid == ilnp_header.destination_identifier();
+                        match insert_into_neighbour_cache(&ilnp_header.source_identifier(), &interface_name, source_address, destination_port, solicited) {
+                            Ok(changed) => {
+
+                                // record new or changed neighbours
+                                if changed {
+                                    record_event(emulator_socket, LogEvent::NeighbourResolved {
+                                        nid: ilnp_header.source_identifier(),
+                                        interface: interface_name,
+                                        address: source_address.to_string(),
+                                        port: destination_port
+                                    }).await;
+                                }
+                            },
+                            Err(err) => {
+                                log_error(emulator_socket, &err).await;
+                            }
+                        }
+
+                    },
+                    Err(err)  => {
+                        log_error(emulator_socket, &err).await;
+                    }
+                }
+
+                // wake anyone resolving the NID
+                PENDING_ND_TABLE.resolve(&ilnp_header.source_identifier());
 
-            // count jcmp response
-            match PCB.lock() {
-                Ok(mut pcb) => {
-                    pcb.dns_fqdn_response_jcmp_rx += 1;
-                },
-                Err(_) => {}
             }
 
-            // parse the response
-            match JCMP_DNS_FQDN_Response_Packet::from_bytes(jcmp_payload) {
-                Ok(jcmp_response_pck) => {
+        },
 
-                    match String::from_utf8(jcmp_response_pck.fqdn) {
-                        Ok(fqdn) => {
+        // dns FQDN lookup
+        JcmpMessage::DnsFqdnQuery { fqdn } => {
 
-                            // insert the response into the name resolution table
-                            match insert_into_name_ilv_table((fqdn, ilnp_header.source_identifier(), ilnp_header.source_locator()), jcmp_response_pck.ttl as u64) {
-                                Ok(()) => {},
-                                Err(err) => {
-                                    log_error(emulator_socket, &err).await;
-                                }
-                            }
+            // check we didn't send out the request
+            if CONFIG.node.nid != ilnp_header.source_identifier() {
 
-                        },
+                // count jcmp request
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.dns_fqdn_query_jcmp_rx += 1;
+                }
+
+                // check dns name is ours
+                if emulator_socket.local_network.local_fqdn == fqdn {
+
+                    // send dns response
+                    match jcmp_tx_dns_fqdn_response(emulator_socket, &ilnp_header.source_identifier()).await {
+                        Ok(()) => {},
                         Err(err) => {
-                            log_error(emulator_socket, &format!("handle_jcmp_packet(): failed convert fqdn to string: {}", err)).await;
+                            log_error(emulator_socket, &err).await;
                         }
                     }
 
-                },
-                Err(err)  => {
-                    log_error(emulator_socket, &err).await;
                 }
+
             }
 
-        }
+        },
 
-    }
+        // DNS FQDN response
+        JcmpMessage::DnsFqdnResponse { ttl, fqdn } => {
 
-    // check for DNS ILV request
-    else if jcmp_payload[0] == 6 {
+            // check we didn't send the response
+            // collect all responses except ours to reduce packet overhead
+            if CONFIG.node.nid != ilnp_header.source_identifier() {
 
-        // check ILV query is for us and we didn't send it
-        if CONFIG.node.nid  == ilnp_header.destination_identifier() && CONFIG.node.nid != ilnp_header.source_identifier() {
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.dns_fqdn_response_jcmp_rx += 1;
+                }
 
-            // count jcmp request
-            match PCB.lock() {
-                Ok(mut pcb) => {
-                    pcb.dns_ilv_query_jcmp_rx += 1;
-                },
-                Err(_) => {}
-            }
-            
-            // send a DNS ILV response
-            match jcmp_tx_dns_ilv_response(emulator_socket, &ilnp_header.source_identifier()).await {
-                Ok(()) => {},
-                Err(err) => {
-                    log_error(emulator_socket, &err).await;
+                // insert the response into the name resolution table
+                match insert_into_name_ilv_table((fqdn.clone(), ilnp_header.source_identifier(), ilnp_header.source_locator()), ttl as u64) {
+                    Ok(true) => {
+                        record_event(emulator_socket, LogEvent::NameResolved {
+                            fqdn: fqdn.clone(),
+                            nid: ilnp_header.source_identifier(),
+                            locator: ilnp_header.source_locator()
+                        }).await;
+                    },
+                    Ok(false) => {},
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
                 }
+
+                // wake anyone resolving the name
+                PENDING_FQDN_TABLE.resolve(&fqdn);
+
             }
 
-        }
+        },
 
-    }
+        // DNS ILV request
+        JcmpMessage::DnsIlvQuery => {
 
-    // check for DNS ILV response
-    else if jcmp_payload[0] == 7 {
+            // check ILV query is for us and we didn't send it
+            if CONFIG.node.nid  == ilnp_header.destination_identifier() && CONFIG.node.nid != ilnp_header.source_identifier() {
 
-        // check it was not sent by us
-        // receive all requests expect ours to reduce packet overhead
-        if CONFIG.node.nid != ilnp_header.source_identifier() {
+                // count jcmp request
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.dns_ilv_query_jcmp_rx += 1;
+                }
+                
+                // send a DNS ILV response
+                match jcmp_tx_dns_ilv_response(emulator_socket, &ilnp_header.source_identifier()).await {
+                    Ok(()) => {},
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
+                }
 
-            // count jcmp response
-            match PCB.lock() {
-                Ok(mut pcb) => {
-                    pcb.dns_ilv_response_jcmp_rx += 1;
-                },
-                Err(_) => {}
             }
 
-            // parse the response
-            let jcmp_ilvresponse_payload:[u8; 2] = match jcmp_payload.try_into() {
-                Ok(jcmp_ilvresponse_payload) => {
-                    jcmp_ilvresponse_payload
-                },
-                Err(err) => {
-                    log_error(emulator_socket, &format!("handle_jcmp_packet(): failed to serialise jcmp dns ilv response: {}", err)).await;
-                    return;
+        },
+
+        // DNS ILV response
+        JcmpMessage::DnsIlvResponse { ttl } => {
+
+            // check it was not sent by us
+            // receive all requests expect ours to reduce packet overhead
+            if CONFIG.node.nid != ilnp_header.source_identifier() {
+
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.dns_ilv_response_jcmp_rx += 1;
                 }
-            };
 
-            // add the results in the name resolution table
-            let jcmp_ilvresponse_pck= JCMP_DNS_ILV_Response_Packet::from_bytes(jcmp_ilvresponse_payload);
-            match insert_into_nid_ilv_table((ilnp_header.source_identifier(), ilnp_header.source_locator()), jcmp_ilvresponse_pck.ttl() as u64) {
-                Ok(()) => {},
-                Err(err) => {
-                    log_error(emulator_socket, &err).await;
+                // add the results in the name resolution table
+                match insert_into_nid_ilv_table((ilnp_header.source_identifier(), ilnp_header.source_locator()), ttl as u64) {
+                    Ok(()) => {},
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
                 }
-            }
 
-        }
+                // wake anyone resolving the NID
+                PENDING_ILV_TABLE.resolve(&ilnp_header.source_identifier());
 
-    }
+            }
 
-    // check for router request
-    else if jcmp_payload[0] == 8 {
+        },
 
-        // check it wasn't sent by us
-        if CONFIG.node.nid != ilnp_header.source_identifier() {
+        // router request
+        JcmpMessage::RouterRequest { hop_count: current_hop_count, destination_locator: lookup_locator } => {
 
+            // check it wasn't sent by us
             // only routers can forward so only routers should respond to this
-            if CONFIG.node.router {
+            if CONFIG.node.nid != ilnp_header.source_identifier() && CONFIG.node.router {
 
                 // count jcmp request
                 match PCB.lock() {
@@ -458,28 +816,14 @@ async fn handle_jcmp_packet(emulator_socket: &EmulatorSocket, source_address: Ip
                     },
                     Err(_) => {}
                 }
-
-                // parse the request
-                let jcmp_routerrequest_payload: [u8; 10] = match jcmp_payload.try_into() {
-                    Ok(jcmp_routerrequest_payload) => {
-                        jcmp_routerrequest_payload
-                    },
-                    Err(err) => {
-                        log_error(emulator_socket, &format!("handle_jcmp_packet(): failed to serialise jcmp router request: {}", err)).await;
-                        return;
-                    }
-                };
-                let jcmp_routerrequest_pck = JCMP_Router_Request::from_bytes(jcmp_routerrequest_payload);
                 
                 // if max hop count reached stop the request
                 // this avoids infinite looping
-                let current_hop_count = jcmp_routerrequest_pck.hop_count();
                 if current_hop_count > CONFIG.network.AD_MAX_HOPS {
                     return;
                 }
 
                 // get interface name for the source of the jcmp request
-                let lookup_locator = jcmp_routerrequest_pck.destination_locator();
                 match get_over_interface_by_locator(&ilnp_header.source_locator()) {
                     Ok(source_interface_name) => {
 
@@ -494,9 +838,9 @@ async fn handle_jcmp_packet(emulator_socket: &EmulatorSocket, source_address: Ip
                                 // if no we need to discover the path to the target locator
                                 // 1 is added to the request hop count to stop infinite looping
                                 // 1 is added to the response to count the hops back to the source
-                                match handle_path_discovery(&emulator_socket, Some(&source_interface_name), &lookup_locator, &(current_hop_count+1)).await {
+                                match handle_path_discovery(emulator_socket, Some(&source_interface_name), &lookup_locator, &current_hop_count.saturating_add(1)).await {
                                     Ok((_, _, _, hop_count)) => {
-                                        let _ = jcmp_tx_router_response(&emulator_socket, &lookup_locator, &ilnp_header.source_identifier(), &source_interface_name, &(hop_count+1)).await;
+                                        let _ = jcmp_tx_router_response(emulator_socket, &lookup_locator, &ilnp_header.source_identifier(), &source_interface_name, &hop_count.saturating_add(1)).await;
                                     },
                                     Err(err) => {
                                         log_error(&emulator_socket, &err).await;
@@ -514,93 +858,225 @@ async fn handle_jcmp_packet(emulator_socket: &EmulatorSocket, source_address: Ip
 
             }
 
-        }
-
-    }
+        },
 
-    // check for router response
-    else if jcmp_payload[0] == 9 {
+        // router response
+        JcmpMessage::RouterResponse { hop_count, destination_locator: lookup_locator, ttl } => {
 
-        // check we didn't send the rsponse
-        if CONFIG.node.nid != ilnp_header.source_identifier() {
+            // check we didn't send the rsponse
+            if CONFIG.node.nid != ilnp_header.source_identifier() {
 
-            // count jcmp response
-            match PCB.lock() {
-                Ok(mut pcb) => {
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
                     pcb.router_response_jcmp_rx += 1;
-                },
-                Err(_) => {}
-            }
-
-            // parse the response
-            let jcmp_routerresponse_payload: [u8; 11] = match jcmp_payload.try_into() {
-                Ok(jcmp_routerresponse_payload) => {
-                    jcmp_routerresponse_payload
-                },
-                Err(err) => {
-                    log_error(emulator_socket, &format!("handle_jcmp_packet(): failed to serialise jcmp router response: {}", err)).await;
-                    return;
                 }
-            };
-            let jcmp_routerresponse_pck = JCMP_Router_Response::from_bytes(jcmp_routerresponse_payload);
-            
-            // retrieve metrics
-            let lookup_locator = jcmp_routerresponse_pck.destination_locator();
-            let hop_count = jcmp_routerresponse_pck.hop_count();
-
-            // get interface name for the network we receive the response in
-            match get_over_interface_by_locator(&ilnp_header.source_locator()) {
-                Ok(interface_name) => {
-
-                    // check if entry already exists
-                    match lookup_forwarding_table(&ilnp_header.source_identifier(), &lookup_locator) {
-                        Ok(entry) => {
-
-                            // if new entry has a better hop count replace it
-                            if entry.3 > hop_count {
-                                match insert_into_forwarding_table((ilnp_header.source_identifier(), lookup_locator, interface_name, hop_count), jcmp_routerresponse_pck.ttl() as u64) {
-                                    Ok(()) => {},
+
+                // get interface name for the network we receive the response in
+                match get_over_interface_by_locator(&ilnp_header.source_locator()) {
+                    Ok(interface_name) => {
+
+                        // check if entry already exists
+                        match lookup_forwarding_table(&ilnp_header.source_identifier(), &lookup_locator) {
+                            Ok(entry) => {
+
+                                // if new entry has a better hop count replace it
+                                if entry.3 > hop_count {
+                                    match insert_into_forwarding_table((ilnp_header.source_identifier(), lookup_locator, interface_name.clone(), hop_count), ttl as u64) {
+                                        Ok(()) => {
+                                            record_event(emulator_socket, LogEvent::RouteDiscovered { locator: lookup_locator, next_hop: ilnp_header.source_identifier(), interface: interface_name, hop_count }).await;
+                                        },
+                                        Err(err) => {
+                                            log_error(emulator_socket, &err).await;
+                                        }
+                                    }
+                                }
+                            },
+                            Err(_) => {
+
+                                // insert new entry in the forwarding table
+                                match insert_into_forwarding_table((ilnp_header.source_identifier(), lookup_locator, interface_name.clone(), hop_count), ttl as u64) {
+                                    Ok(()) => {
+                                        record_event(emulator_socket, LogEvent::RouteDiscovered { locator: lookup_locator, next_hop: ilnp_header.source_identifier(), interface: interface_name, hop_count }).await;
+                                    },
                                     Err(err) => {
                                         log_error(emulator_socket, &err).await;
                                     }
                                 }
                             }
-                        },
-                        Err(_) => {
+                        }
 
-                            // insert new entry in the forwarding table
-                            match insert_into_forwarding_table((ilnp_header.source_identifier(), lookup_locator, interface_name, hop_count), jcmp_routerresponse_pck.ttl() as u64) {
+                    },
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
+                }
+
+            }
+
+        },
+
+        // duplicate nid probe
+        JcmpMessage::DuplicateNidProbe { nonce } => {
+
+            // someone else probing for our NID, our own probes carry our nonce
+            if CONFIG.node.nid == ilnp_header.destination_identifier() && nonce != *DAD_NONCE {
+
+                // count jcmp request
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.duplicate_nid_probe_jcmp_rx += 1;
+                }
+
+                // still probing ourselves, neither of us can keep the NID
+                // otherwise the NID is ours and we defend it
+                if !DAD_TENTATIVE.load(Ordering::Relaxed) {
+                    match get_over_interface_by_locator(&ilnp_header.destination_locator()) {
+                        Ok(inf_name) => {
+                            match jcmp_tx_duplicate_nid_defend(emulator_socket, &nonce, &inf_name).await {
                                 Ok(()) => {},
                                 Err(err) => {
                                     log_error(emulator_socket, &err).await;
                                 }
                             }
+                        },
+                        Err(err) => {
+                            log_error(emulator_socket, &err).await;
                         }
                     }
+                }
 
-                },
-                Err(err) => {
+                report_duplicate_nid(emulator_socket, &ilnp_header).await;
+            }
+
+        },
+
+        // duplicate nid defend
+        JcmpMessage::DuplicateNidDefend { nonce } => {
+
+            // answer to one of our probes
+            if CONFIG.node.nid == ilnp_header.destination_identifier() && nonce == *DAD_NONCE {
+
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.duplicate_nid_defend_jcmp_rx += 1;
+                }
+
+                report_duplicate_nid(emulator_socket, &ilnp_header).await;
+            }
+
+        },
+
+        // destination unreachable
+        JcmpMessage::DestinationUnreachable { destination_locator, destination_nid } => {
+
+            // a router dropped one of our packets
+            if CONFIG.node.nid == ilnp_header.destination_identifier() && CONFIG.node.nid != ilnp_header.source_identifier() {
+
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.destination_unreachable_jcmp_rx += 1;
+                }
+
+                // the route to the locator through the reporting router is no good
+                if let Err(err) = invalidate_routes_via(&ilnp_header.source_identifier(), Some(&destination_locator)) {
                     log_error(emulator_socket, &err).await;
                 }
+
+                record_event(emulator_socket, LogEvent::DestinationUnreachable {
+                    locator: destination_locator,
+                    nid: destination_nid,
+                    reported_by: ilnp_header.source_identifier()
+                }).await;
             }
 
-        }
+        },
 
-    }
+        // router solicitation
+        JcmpMessage::RouterSolicitation => {
 
-    else {
-        log_error(emulator_socket, &format!("handle_jtp_packet(): jcmp packet code {:?} not supported", jcmp_payload[0])).await;
-    }
+            // only routers answer and only when router discovery is on
+            if CONFIG.router_discovery.enabled && CONFIG.node.router && CONFIG.node.nid != ilnp_header.source_identifier() {
 
+                // count jcmp request
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.router_solicitation_jcmp_rx += 1;
+                }
+
+                // answer on the network the solicitation came from
+                match get_over_interface_by_locator(&ilnp_header.destination_locator()) {
+                    Ok(inf_name) => {
+                        match jcmp_tx_router_advertisement(emulator_socket, &ilnp_header.source_identifier(), &inf_name).await {
+                            Ok(()) => {},
+                            Err(err) => {
+                                log_error(emulator_socket, &err).await;
+                            }
+                        }
+                    },
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
+                }
+
+            }
+
+        },
+
+        // router advertisement
+        JcmpMessage::RouterAdvertisement { lifetime, locators } => {
+
+            // solicited or not, every advertisement refreshes the router
+            if CONFIG.router_discovery.enabled && CONFIG.node.nid != ilnp_header.source_identifier() {
+
+                // count jcmp response
+                if let Ok(mut pcb) = PCB.lock() {
+                    pcb.router_advertisement_jcmp_rx += 1;
+                }
+
+                // get the interface name for the locator we received
+                match get_over_interface_by_locator(&ilnp_header.source_locator()) {
+                    Ok(interface_name) => {
+                        match insert_into_default_router_table(&ilnp_header.source_identifier(), &interface_name, locators, lifetime) {
+                            Ok(true) => {
+                                record_event(emulator_socket, LogEvent::DefaultRouterLearned {
+                                    nid: ilnp_header.source_identifier(),
+                                    interface: interface_name,
+                                    lifetime
+                                }).await;
+                            },
+                            Ok(false) => {},
+                            Err(err) => {
+                                log_error(emulator_socket, &err).await;
+                            }
+                        }
+                    },
+                    Err(err) => {
+                        log_error(emulator_socket, &err).await;
+                    }
+                }
+
+            }
+
+        }
+
+    }
 
 }
 
 
 /// Handles forwarding a packet
-pub async fn handle_router_forward(emulator_socket: &EmulatorSocket, ilnp_pck: &INLPv6Packet, payload: &[u8])
-    -> Result<(), String>
+///     - pck is the packet as received, only its hop limit is rewritten before it's sent on
+///     - the sender's nonce is passed on unchanged
+///     - packets for a locator without a route are held while it's discovered, other packets keep flowing
+pub async fn handle_router_forward(emulator_socket: &EmulatorSocket, ilnp_pck: &INLPv6Packet, pck: &mut [u8])
+    -> Result<ForwardOutcome, String>
 {
 
+    // the packet would leave with no hops left
+    if ilnp_pck.hop_limit() <= 1 {
+        count_drop(DropReason::HopLimitExceeded);
+        return Ok(ForwardOutcome::Dropped);
+    }
+    rewrite_hop_limit(pck, ilnp_pck.hop_limit() - 1);
+
     // get interface name for locator received
     match get_over_interface_by_locator(&ilnp_pck.destination_locator()) {
 
@@ -610,47 +1086,153 @@ pub async fn handle_router_forward(emulator_socket: &EmulatorSocket, ilnp_pck: &
             let destination_nid = ilnp_pck.destination_identifier();
             let (destination_address, destination_port) = handle_destination_nid(emulator_socket, &destination_nid, &interface_name).await?;
 
-            // create the ILNPv6 header
-            let mut pck_vec: Vec<u8> = ilnp_pck.into_bytes().to_vec();
-            pck_vec.extend_from_slice(&payload);
-
             // forward packet to node
-            let _ = underlay_uni_tx(emulator_socket, &destination_address, &destination_port, &pck_vec).await?;
-            Ok(())
+            underlay_uni_tx(emulator_socket, &destination_address, &destination_port, pck).await?;
+            Ok(ForwardOutcome::Sent)
 
         },
 
         // need to forward packet to another router
         Err(_) => {
-            
-            // path discovery
-            match handle_path_discovery(emulator_socket, None, &ilnp_pck.destination_locator(), &0).await {
-                Ok((router_nid, _, interface_name, _)) => {
-
-                    // address resolution
-                    match handle_destination_nid(emulator_socket, &router_nid, &interface_name).await {
-                        Ok((ipv6, port)) => {
 
-                            // create the ILNPv6 header
-                            let mut pck_vec: Vec<u8> = ilnp_pck.into_bytes().to_vec();
-                            pck_vec.extend_from_slice(&payload);
-                            
-                            // forward packet to router
-                            let _ = underlay_uni_tx(emulator_socket, &ipv6, &port, &pck_vec).await?;
-                            Ok(())
+            // route already known
+            if let Ok((router_nid, _, interface_name, _)) = lookup_forwarding_table_route(&ilnp_pck.destination_locator()) {
+                handle_router_next_hop(emulator_socket, &router_nid, &interface_name, pck).await?;
+                return Ok(ForwardOutcome::Sent);
+            }
 
-                        },
-                        Err(err) => {
-                            Err(err)
-                        }
+            // hold the packet until the path is discovered
+            let first_held = match HOLD_QUEUE.lock() {
+                Ok(mut hold_queue) => {
+                    let locator = ilnp_pck.destination_locator();
+                    if !hold_queue.contains_key(&locator) && hold_queue.len() >= CONFIG.forwarding.hold_max_locators {
+                        count_drop(DropReason::HoldQueueFull);
+                        return Ok(ForwardOutcome::Dropped);
                     }
 
+                    let held = hold_queue.entry(locator).or_default();
+                    if held.len() >= CONFIG.forwarding.hold_queue_size {
+                        count_drop(DropReason::HoldQueueFull);
+                        return Ok(ForwardOutcome::Dropped);
+                    }
+                    held.push(HeldPacket { header: *ilnp_pck, pck: pck.to_vec(), held: Instant::now() });
+                    held.len() == 1
                 },
                 Err(err) => {
-                    Err(err)
+                    return Err(format!("handle_router_forward(): failed to lock the hold queue: {}", err));
                 }
+            };
+
+            // the first packet held for a locator starts the discovery
+            if first_held {
+                let emulator_socket_clone = emulator_socket.clone();
+                let locator = ilnp_pck.destination_locator();
+                tokio::spawn(async move {
+                    handle_held_packets(&emulator_socket_clone, locator).await;
+                });
             }
 
+            Ok(ForwardOutcome::Held)
+
+        }
+    }
+
+}
+
+
+/// Forward a packet to the next hop router
+async fn handle_router_next_hop(emulator_socket: &EmulatorSocket, router_nid: &u64, interface_name: &String, pck: &[u8])
+    -> Result<(), String>
+{
+
+    // address resolution
+    let (ipv6, port) = handle_destination_nid(emulator_socket, router_nid, interface_name).await?;
+
+    // forward packet to router
+    underlay_uni_tx(emulator_socket, &ipv6, &port, pck).await?;
+    Ok(())
+
+}
+
+
+/// Flush the packets held for a locator
+///     - once the path discovery finishes the held packets are forwarded
+///     - packets held longer than hold_timeout_ms are dropped
+///     - if no path was found they're dropped and every sender gets one destination unreachable
+async fn handle_held_packets(emulator_socket: &EmulatorSocket, locator: u64)
+{
+
+    let route = handle_path_discovery(emulator_socket, None, &locator, &0).await;
+
+    // take the queue, later packets find the route or start a new discovery
+    let held = match HOLD_QUEUE.lock() {
+        Ok(mut hold_queue) => hold_queue.remove(&locator).unwrap_or_default(),
+        Err(_) => Vec::new()
+    };
+
+    match route {
+        Ok((router_nid, _, interface_name, _)) => {
+            let hold_timeout = Duration::from_millis(CONFIG.forwarding.hold_timeout_ms);
+
+            // waited too long
+            let held: Vec<HeldPacket> = held.into_iter()
+                .filter(|packet| {
+                    let expired = packet.held.elapsed() > hold_timeout;
+                    if expired {
+                        count_drop(DropReason::HoldExpired);
+                    }
+                    !expired
+                })
+                .collect();
+            if held.is_empty() {
+                return;
+            }
+
+            // address resolution, once for the whole queue
+            let (ipv6, port) = match handle_destination_nid(emulator_socket, &router_nid, &interface_name).await {
+                Ok(neighbour) => neighbour,
+                Err(err) => {
+                    for _ in &held {
+                        count_drop(DropReason::ForwardFailed);
+                    }
+                    log_error(emulator_socket, &err).await;
+                    return;
+                }
+            };
+
+            // forward the queue to the router in one batch
+            let pcks: Vec<(Ipv6Addr, u16, Vec<u8>)> = held.into_iter()
+                .map(|packet| (ipv6, port, packet.pck))
+                .collect();
+            match underlay_uni_tx_batch(emulator_socket, &pcks).await {
+                Ok(()) => {
+
+                    // count forwarding packets
+                    if let Ok(mut pcb) = PCB.lock() {
+                        pcb.data_request_forward_tx += pcks.len() as u64;
+                    }
+
+                },
+                Err(err) => {
+                    for _ in &pcks {
+                        count_drop(DropReason::ForwardFailed);
+                    }
+                    log_error(emulator_socket, &err).await;
+                }
+            }
+        },
+        Err(err) => {
+            log_error(emulator_socket, &err).await;
+
+            let mut reported = HashSet::new();
+            for packet in held {
+                count_drop(DropReason::ForwardFailed);
+                if CONFIG.forwarding.unreachable_messages && reported.insert(packet.header.source_identifier()) {
+                    if let Err(err) = jcmp_tx_destination_unreachable(emulator_socket, &packet.header).await {
+                        log_error(emulator_socket, &err).await;
+                    }
+                }
+            }
         }
     }
 
@@ -661,44 +1243,107 @@ pub async fn handle_destination_nid(emulator_socket: &EmulatorSocket, destinatio
     -> Result<(Ipv6Addr, u16), String>
 {
 
-    // IPv6 and port
-    let mut ipv6_address = Ipv6Addr::UNSPECIFIED;
-    let mut destination_port: u16 = 0;
-    
     // attempt ND_RETRANSMIT_LIMIT times
     let mut attempt = 0;
-    while attempt < CONFIG.network.ND_RETRANSMIT_LIMIT && ipv6_address == Ipv6Addr::UNSPECIFIED && destination_port == 0 {
+    loop {
 
         // lookup in the table first
-        match NID_ADDRESS_RESOLUTION_TABLE.lock() {
-            Ok(map) => {
-                if let Some((_, des_addr, dest_port)) = map.get(destination_nid) {
-                    ipv6_address = des_addr.clone();
-                    destination_port = dest_port.clone();
-                }
-            },
-            Err(err) => {
-                return Err(format!("handle_destination_nid(): failed to lock NID_INTERFACE_IP_TABLE: {}", err));
-            }
+        // stale neighbours are still used while we probe them
+        if let Some(neighbour) = lookup_neighbour_cache(destination_nid)? {
+            probe_stale_neighbour(emulator_socket, destination_nid, &neighbour)?;
+            return Ok((neighbour.address, neighbour.port));
+        }
+        if attempt >= CONFIG.network.ND_RETRANSMIT_LIMIT {
+            break;
         }
 
-        // if nothing in the table send solicitation
-        if ipv6_address == Ipv6Addr::UNSPECIFIED && destination_port == 0 {
-            let _ = jcmp_tx_solicitation(emulator_socket, destination_nid, interface_name).await;
-            attempt += 1;
+        // wait for the advertisement with everybody else resolving the NID
+        let (notify, send_solicitation) = PENDING_ND_TABLE.join(destination_nid);
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        // advertised while we joined
+        if lookup_neighbour_cache(destination_nid)?.is_some() {
+            continue;
+        }
 
-            // timeout
-            tokio::time::sleep(Duration::from_millis(CONFIG.network.ND_RTO_MS)).await;
+        // only one solicitation per NID is outstanding
+        if send_solicitation {
+            let _ = jcmp_tx_solicitation(emulator_socket, destination_nid, interface_name).await;
         }
+        attempt += 1;
+
+        // timeout
+        let _ = tokio::time::timeout(PENDING_ND_TABLE.rto(), notified).await;
     }
 
     // if fails then host is unreachable
-    if ipv6_address != Ipv6Addr::UNSPECIFIED && destination_port != 0 {
-        Ok((ipv6_address, destination_port))
-    } else {
-        Err(format!("handle_destination_nid(): host {} unreachable", destination_nid))
+    Err(format!("handle_destination_nid(): host {} unreachable", destination_nid))
+
+}
+
+
+/// Start Neighbour Unreachability Detection on a Stale neighbour
+///     - the neighbour is still used while it's probed
+pub fn probe_stale_neighbour(emulator_socket: &EmulatorSocket, nid: &u64, neighbour: &NeighbourEntry)
+    -> Result<(), String>
+{
+    if neighbour.state == NeighbourState::Stale && probe_neighbour_cache(nid)? {
+        let emulator_socket_clone = emulator_socket.clone();
+        let nid = *nid;
+        tokio::spawn(async move {
+            handle_neighbour_probe(&emulator_socket_clone, &nid).await;
+        });
     }
+    Ok(())
+}
 
+/// Neighbour Unreachability Detection
+///     - unicast solicitations retrans_timer_ms apart, max_unicast_probes of them
+///     - the neighbour's advertisement (or traffic from it) moves it back to Reachable
+///     - otherwise it's removed from the cache and every route through it is invalidated
+async fn handle_neighbour_probe(emulator_socket: &EmulatorSocket, nid: &u64)
+{
+    for _ in 0..CONFIG.neighbour_discovery.max_unicast_probes {
+        match lookup_neighbour_cache(nid) {
+            Ok(Some(neighbour)) if neighbour.state == NeighbourState::Probe => {
+                if let Err(err) = jcmp_tx_unicast_solicitation(emulator_socket, nid, &neighbour).await {
+                    log_error(emulator_socket, &err).await;
+                }
+            },
+            Ok(_) => {
+                return;
+            },
+            Err(err) => {
+                log_error(emulator_socket, &err).await;
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(CONFIG.neighbour_discovery.retrans_timer_ms)).await;
+    }
+
+    // still no answer
+    let neighbour = match lookup_neighbour_cache(nid) {
+        Ok(Some(neighbour)) if neighbour.state == NeighbourState::Probe => neighbour,
+        Ok(_) => {
+            return;
+        },
+        Err(err) => {
+            log_error(emulator_socket, &err).await;
+            return;
+        }
+    };
+    if let Err(err) = remove_from_neighbour_cache(nid) {
+        log_error(emulator_socket, &err).await;
+    }
+    if let Err(err) = invalidate_routes_via(nid, None) {
+        log_error(emulator_socket, &err).await;
+    }
+    if let Ok(mut pcb) = PCB.lock() {
+        pcb.nd_unreachable += 1;
+    }
+    record_event(emulator_socket, LogEvent::NeighbourUnreachable { nid: *nid, interface: neighbour.interface }).await;
 }
 
 
@@ -709,33 +1354,39 @@ pub async fn handle_destination_fqdn(emulator_socket: &EmulatorSocket, destinati
 
     // attempt ND_RETRANSMIT_LIMIT times
     let mut attempt = 0;
-    while attempt < CONFIG.network.ND_RETRANSMIT_LIMIT {
+    loop {
 
         // check the name resolution table first
-        match lookup_name_ilv_table(destination_fqdn) {
-            Ok(entries) => {
+        let entries = lookup_name_ilv_table(destination_fqdn)?;
+        if !entries.is_empty() {
+            let result: Vec<(u64, u64)> = entries.into_iter()
+                .map(|(_, nid, loc)| (nid, loc))
+                .collect();
+            return Ok(result);
+        }
+        if attempt >= CONFIG.network.ND_RETRANSMIT_LIMIT {
+            break;
+        }
 
-                if entries.len() != 0 {
-                    let result: Vec<(u64, u64)> = entries.into_iter()
-                        .map(|(_, nid, loc)| (nid, loc))
-                        .collect();
-                    return Ok(result);
-                }
-                else {
+        // wait for the response with everybody else resolving the name
+        let (notify, send_query) = PENDING_FQDN_TABLE.join(destination_fqdn);
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
 
-                    // send DNS query if nothing is found
-                    let _ = jcmp_tx_dns_fqdn_query(emulator_socket, destination_fqdn).await;
-                    attempt += 1;
-                }
+        // answered while we joined
+        if !lookup_name_ilv_table(destination_fqdn)?.is_empty() {
+            continue;
+        }
 
-            },
-            Err(err) => {
-                return Err(err);
-            }
+        // only one DNS query per name is outstanding
+        if send_query {
+            let _ = jcmp_tx_dns_fqdn_query(emulator_socket, destination_fqdn).await;
         }
+        attempt += 1;
 
         // timeout
-        thread::sleep(Duration::from_millis(CONFIG.network.ND_RTO_MS));
+        let _ = tokio::time::timeout(PENDING_FQDN_TABLE.rto(), notified).await;
 
     }
 
@@ -750,29 +1401,37 @@ pub async fn handle_destination_ilv(emulator_socket: &EmulatorSocket, destinatio
 
     // attempt ND_RETRANSMIT_LIMIT times
     let mut attempt = 0;
-    while attempt < CONFIG.network.ND_RETRANSMIT_LIMIT {
+    loop {
 
         // check the table first
-        match lookup_nid_ilv_table(&destination_nid) {
-            Ok(entries) => {
-                if entries.len() != 0 { 
-                    return Ok(entries);
-                }
-                else {
+        let entries = lookup_nid_ilv_table(destination_nid)?;
+        if !entries.is_empty() {
+            return Ok(entries);
+        }
+        if attempt >= CONFIG.network.ND_RETRANSMIT_LIMIT {
+            break;
+        }
 
-                    // if nothing found send a DNS query
-                    let _ = jcmp_tx_dns_ilv_query(emulator_socket, destination_nid).await;
-                    attempt += 1;
-                }
-            },
-            Err(err)  => {
-                return Err(err);
-            }
+        // wait for the response with everybody else resolving the NID
+        let (notify, send_query) = PENDING_ILV_TABLE.join(destination_nid);
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        // answered while we joined
+        if !lookup_nid_ilv_table(destination_nid)?.is_empty() {
+            continue;
+        }
+
+        // only one DNS query per NID is outstanding
+        if send_query {
+            let _ = jcmp_tx_dns_ilv_query(emulator_socket, destination_nid).await;
         }
+        attempt += 1;
 
         // timeout
-        thread::sleep(Duration::from_millis(CONFIG.network.ND_RTO_MS));
-        
+        let _ = tokio::time::timeout(PENDING_ILV_TABLE.rto(), notified).await;
+
     }
 
     Err(format!("handle_destination_ILV(): could not establish {}'s locator and identifier", destination_nid))
@@ -781,59 +1440,47 @@ pub async fn handle_destination_ilv(emulator_socket: &EmulatorSocket, destinatio
 
 
 /// Path Discovery function
+///     - concurrent discoveries for the same locator from the same network share one router request flood
+///     - the flood skips the callers' source interface, callers from other networks flood on their own
+///     - callers are woken when a route to the locator is inserted, or give up after AD_HOC_TIMEOUT_MS
 pub async fn handle_path_discovery(emulator_socket: &EmulatorSocket, source_interface: Option<&String> , lookup_locator: &u64, current_hop_count: &u8)
     -> Result<(u64, u64, String, u8), String>
 {
 
-    let mut disc_done = false;
-    let start_time = Instant::now();
-    let loop_duration = Duration::from_millis(CONFIG.network.AD_HOC_TIMEOUT_MS);
-
-    // loop until timeout
-    while start_time.elapsed() < loop_duration {
-
-        // check forwarding table
-        match lookup_forwarding_table_route(lookup_locator) {
-            Ok(entry) => {
-
-                return Ok(entry);
+    // check forwarding table first
+    if let Ok(entry) = lookup_forwarding_table_route(lookup_locator) {
+        return Ok(entry);
+    }
 
-            },
-            Err(_) => {
+    // wait for the response with everybody else discovering the locator
+    let (notify, send_request) = PENDING_PATH_TABLE.join(&(*lookup_locator, source_interface.cloned()));
+    let notified = notify.notified();
+    tokio::pin!(notified);
+    notified.as_mut().enable();
 
-                // if not found and router request not sent then send it
-                if !disc_done {
-                
-                    // get all interfaces
-                    match get_over_interfaces() {
-                        Ok(interfaces) => {
-
-                            // send out discovery to all networks
-                            for (interface_name, _) in interfaces {
-                                if let Some(si) = source_interface {
-                                    if &interface_name == si { continue; }
-                                }
-                                let _ = jcmp_tx_router_request(emulator_socket, lookup_locator, &interface_name, current_hop_count).await;
-                            }
-                
-                            disc_done = true;
+    // discovered while we joined
+    if let Ok(entry) = lookup_forwarding_table_route(lookup_locator) {
+        return Ok(entry);
+    }
 
-                        },
-                        Err(err) => {
-                            return Err(err);
-                        }
-                    }
-        
-                    
-                }
+    // only one router request flood per locator and source network is outstanding
+    if send_request {
 
+        // send out discovery to all networks
+        for (interface_name, _) in get_over_interfaces()? {
+            if let Some(si) = source_interface {
+                if &interface_name == si { continue; }
             }
+            let _ = jcmp_tx_router_request(emulator_socket, lookup_locator, &interface_name, current_hop_count).await;
         }
 
-        // timeout
-        tokio::time::sleep(Duration::from_nanos(CONFIG.network.AD_HOC_RTO_NS)).await;
     }
 
-    Err(format!("handle_path_discovery(): failed to resolve route for 0x{:016X} skipping {:?}\n", lookup_locator, source_interface))
+    // wait for the response, or a route learned some other way, until timeout
+    let _ = tokio::time::timeout(PENDING_PATH_TABLE.rto(), notified).await;
+    match lookup_forwarding_table_route(lookup_locator) {
+        Ok(entry) => Ok(entry),
+        Err(_) => Err(format!("handle_path_discovery(): failed to resolve route for 0x{:016X} skipping {:?}\n", lookup_locator, source_interface))
+    }
 
-}
\ No newline at end of file
+}
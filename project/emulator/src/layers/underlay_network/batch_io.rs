@@ -0,0 +1,257 @@
+use std::{io, mem, net::{SocketAddr, SocketAddrV6}, ops::Range, os::unix::io::AsRawFd, ptr, sync::atomic::{AtomicBool, Ordering}};
+use bytes::BytesMut;
+use tokio::{io::Interest, net::UdpSocket as TokioUdpSocket};
+
+/// Largest UDP payload over IPv6
+///     - the most GRO hands over and GSO sends in one buffer
+pub const MAX_UDP_PAYLOAD: usize = 65527;
+
+/// Most segments in one GSO send (UDP_MAX_SEGMENTS)
+pub const MAX_SEGMENTS: usize = 64;
+
+/// Control message room per datagram
+///     - one UDP_GRO (int) or UDP_SEGMENT (u16) message, u64 for the cmsghdr alignment
+type Control = [u64; 8];
+
+/// GSO availability
+///     - cleared the first time the kernel refuses a segmented send
+static GSO_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
+/// Enable GRO (UDP_GRO)
+///     - the kernel may then hand over several datagrams from one sender as one buffer
+pub fn enable_gro(socket: &TokioUdpSocket)
+    -> Result<(), String>
+{
+    let enable: libc::c_int = 1;
+    unsafe {
+        if libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_GRO,
+            &enable as *const _ as *const libc::c_void,
+            mem::size_of_val(&enable) as libc::socklen_t
+        ) != 0 {
+            return Err(format!("enable_gro(): failed to setup UDP_GRO: {}", io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Receive a batch of datagrams
+///     - waits for the socket then takes up to batch_size datagrams with one recvmmsg
+///     - datagrams are split off buf without copying, coalesced (GRO) buffers are split into their datagrams
+///     - with GRO every slot has room for a coalesced buffer, there are still batch_size slots
+pub async fn recv_batch(socket: &TokioUdpSocket, buf: &mut BytesMut, slot_size: usize, batch_size: usize, gro: bool, datagrams: &mut Vec<(BytesMut, SocketAddr)>)
+    -> io::Result<()>
+{
+    let slot_size = if gro { MAX_UDP_PAYLOAD } else { slot_size };
+    let count = batch_size.max(1);
+
+    loop {
+        socket.readable().await?;
+        match socket.try_io(Interest::READABLE, || recvmmsg_into(socket.as_raw_fd(), buf, slot_size, count, datagrams)) {
+            Ok(()) => {
+                return Ok(());
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                continue;
+            },
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+}
+
+fn recvmmsg_into(fd: libc::c_int, buf: &mut BytesMut, slot_size: usize, count: usize, datagrams: &mut Vec<(BytesMut, SocketAddr)>)
+    -> io::Result<()>
+{
+    buf.clear();
+    buf.resize(slot_size * count, 0);
+
+    let mut names: Vec<libc::sockaddr_in6> = vec![unsafe { mem::zeroed() }; count];
+    let mut controls: Vec<Control> = vec![[0; 8]; count];
+    let mut iovecs: Vec<libc::iovec> = buf.chunks_mut(slot_size)
+        .map(|slot| libc::iovec { iov_base: slot.as_mut_ptr() as *mut libc::c_void, iov_len: slot.len() })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(count);
+    for index in 0..count {
+        let mut msg_hdr: libc::msghdr = unsafe { mem::zeroed() };
+        msg_hdr.msg_name = &mut names[index] as *mut _ as *mut libc::c_void;
+        msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+        msg_hdr.msg_iov = &mut iovecs[index];
+        msg_hdr.msg_iovlen = 1;
+        msg_hdr.msg_control = controls[index].as_mut_ptr() as *mut libc::c_void;
+        msg_hdr.msg_controllen = mem::size_of::<Control>() as _;
+        msgs.push(libc::mmsghdr { msg_hdr, msg_len: 0 });
+    }
+
+    let received = unsafe { libc::recvmmsg(fd, msgs.as_mut_ptr(), count as libc::c_uint, libc::MSG_DONTWAIT, ptr::null_mut()) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // read everything the kernel filled in before splitting buf
+    let received: Vec<(usize, SocketAddr, usize)> = msgs.iter().zip(&names).take(received as usize)
+        .map(|(msg, name)| {
+            let len = msg.msg_len as usize;
+            (len, socket_addr(name), gro_segment(&msg.msg_hdr).unwrap_or(len))
+        })
+        .collect();
+
+    for (len, addr, segment) in received {
+        let mut slot = buf.split_to(slot_size);
+        slot.truncate(len);
+        while segment > 0 && slot.len() > segment {
+            datagrams.push((slot.split_to(segment), addr));
+        }
+        datagrams.push((slot, addr));
+    }
+    Ok(())
+}
+
+fn socket_addr(name: &libc::sockaddr_in6)
+    -> SocketAddr
+{
+    SocketAddr::V6(SocketAddrV6::new(name.sin6_addr.s6_addr.into(), u16::from_be(name.sin6_port), name.sin6_flowinfo, name.sin6_scope_id))
+}
+
+/// Segment size of a coalesced datagram, from its UDP_GRO control message
+fn gro_segment(msg_hdr: &libc::msghdr)
+    -> Option<usize>
+{
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg_hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                let segment = ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                return Some(segment as usize);
+            }
+            cmsg = libc::CMSG_NXTHDR(msg_hdr, cmsg);
+        }
+    }
+    None
+}
+
+/// GSO groups
+///     - consecutive packets to the same destination, all the size of the first but the last which may be shorter
+///     - at most MAX_SEGMENTS packets and MAX_UDP_PAYLOAD bytes each
+///     - without gso every packet is its own group
+pub fn gso_groups(packets: &[(SocketAddrV6, &[u8])], gso: bool)
+    -> Vec<Range<usize>>
+{
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < packets.len() {
+        let (destination, first) = &packets[start];
+        let segment = first.len();
+        let mut total = segment;
+        let mut end = start + 1;
+
+        while gso && segment > 0 && end < packets.len() && end - start < MAX_SEGMENTS {
+            let (next_destination, next) = &packets[end];
+            if next_destination != destination || next.len() > segment || next.is_empty() || total + next.len() > MAX_UDP_PAYLOAD {
+                break;
+            }
+            total += next.len();
+            end += 1;
+
+            // a shorter packet ends the group
+            if next.len() < segment {
+                break;
+            }
+        }
+
+        groups.push(start..end);
+        start = end;
+    }
+    groups
+}
+
+/// Send a batch of datagrams
+///     - one sendmmsg per batch, each GSO group is one message the kernel segments
+///     - if the kernel refuses GSO the rest of the batch goes out one datagram per message
+pub async fn send_batch(socket: &TokioUdpSocket, packets: &[(SocketAddrV6, &[u8])], gso: bool)
+    -> io::Result<()>
+{
+    let mut gso = gso && GSO_SUPPORTED.load(Ordering::Relaxed);
+    let mut groups = gso_groups(packets, gso);
+    let mut sent = 0;
+
+    while sent < groups.len() {
+        socket.writable().await?;
+        match socket.try_io(Interest::WRITABLE, || sendmmsg_from(socket.as_raw_fd(), packets, &groups[sent..])) {
+            Ok(count) => {
+                sent += count;
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                continue;
+            },
+
+            // no segmentation offload on this path
+            Err(err) if gso && groups[sent].len() > 1 && matches!(err.raw_os_error(), Some(libc::EIO) | Some(libc::EINVAL) | Some(libc::ENOPROTOOPT) | Some(libc::EOPNOTSUPP)) => {
+                GSO_SUPPORTED.store(false, Ordering::Relaxed);
+                gso = false;
+                groups = (groups[sent].start..packets.len()).map(|index| index..index + 1).collect();
+                sent = 0;
+            },
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn sendmmsg_from(fd: libc::c_int, packets: &[(SocketAddrV6, &[u8])], groups: &[Range<usize>])
+    -> io::Result<usize>
+{
+    let mut names: Vec<libc::sockaddr_in6> = Vec::with_capacity(groups.len());
+    let mut controls: Vec<Control> = vec![[0; 8]; groups.len()];
+    let mut iovecs: Vec<libc::iovec> = Vec::with_capacity(groups.last().map_or(0, |group| group.end) - groups.first().map_or(0, |group| group.start));
+    for group in groups {
+        let destination = packets[group.start].0;
+        names.push(libc::sockaddr_in6 {
+            sin6_family: libc::AF_INET6 as libc::sa_family_t,
+            sin6_port: destination.port().to_be(),
+            sin6_flowinfo: destination.flowinfo(),
+            sin6_addr: libc::in6_addr { s6_addr: destination.ip().octets() },
+            sin6_scope_id: destination.scope_id()
+        });
+        for (_, payload) in &packets[group.clone()] {
+            iovecs.push(libc::iovec { iov_base: payload.as_ptr() as *mut libc::c_void, iov_len: payload.len() });
+        }
+    }
+
+    let mut msgs: Vec<libc::mmsghdr> = Vec::with_capacity(groups.len());
+    let mut iovec_index = 0;
+    for (index, group) in groups.iter().enumerate() {
+        let mut msg_hdr: libc::msghdr = unsafe { mem::zeroed() };
+        msg_hdr.msg_name = &mut names[index] as *mut _ as *mut libc::c_void;
+        msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+        msg_hdr.msg_iov = &mut iovecs[iovec_index];
+        msg_hdr.msg_iovlen = group.len() as _;
+        iovec_index += group.len();
+
+        // segment size for the kernel
+        if group.len() > 1 {
+            let segment = packets[group.start].1.len() as u16;
+            msg_hdr.msg_control = controls[index].as_mut_ptr() as *mut libc::c_void;
+            msg_hdr.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as u32) } as _;
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg_hdr);
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment);
+            }
+        }
+        msgs.push(libc::mmsghdr { msg_hdr, msg_len: 0 });
+    }
+
+    let sent = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, libc::MSG_DONTWAIT) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
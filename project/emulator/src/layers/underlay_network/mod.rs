@@ -2,13 +2,17 @@ use std::collections::HashMap;
 use std::net::{Ipv6Addr, SocketAddrV6};
 use std::sync::{Arc, Mutex};
 use once_cell::sync::Lazy;
+use tokio::net::UdpSocket as TokioUdpSocket;
 use under_socket::{create_multi_socket, create_unicast_socket};
 
 pub mod under_socket;
+pub mod batch_io;
 use crate::layers::overlay_network::CONFIG;
 use crate::services::network_services::get_over_interface_by_name;
 use crate::services::{log_services::{log_error, log_info}, network_services::{get_under_interface_by_name, get_multicast_to_join}};
-use crate::models::network_models::EmulatorSocket;
+use crate::models::network_models::{EmulatorLocalNetwork, EmulatorSocket};
+use crate::services::config_services::get_uid;
+use crate::services::capture_services::{capture_packet, CaptureDirection};
 
 /// INTERFACES
 ///     - placeholder for different simulated networks the node is connected to
@@ -154,6 +158,65 @@ pub async fn open_underlay_socket()
 
 }
 
+/// Open Loopback Socket
+///     - in-memory underlay used by the tests and fuzz targets
+///     - both sockets are bound to [::1] on ephemeral ports, no multicast groups are joined
+///     - configured networks, DNS and log are registered as interfaces so the handlers can resolve them
+///     - anything the handlers send goes to the loopback address and is dropped
+#[allow(dead_code)]
+pub async fn open_loopback_underlay_socket()
+    -> Result<EmulatorSocket, String>
+{
+    let mulcast_socket = match TokioUdpSocket::bind(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0)).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            return Err(format!("open_loopback_underlay_socket(): failed to bind multicast socket: {}", err));
+        }
+    };
+    let unicast_socket = match TokioUdpSocket::bind(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0)).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            return Err(format!("open_loopback_underlay_socket(): failed to bind unicast socket: {}", err));
+        }
+    };
+    let local_port = match unicast_socket.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(err) => {
+            return Err(format!("open_loopback_underlay_socket(): failed to get unicast port: {}", err));
+        }
+    };
+
+    let local_network = EmulatorLocalNetwork {
+        local_uid: get_uid()?,
+        local_index: 0,
+        local_nid: CONFIG.node.nid,
+        local_fqdn: CONFIG.node.name.clone(),
+        local_ipv6: Ipv6Addr::LOCALHOST,
+        local_port
+    };
+
+    // register the interfaces the same way open_underlay_socket does
+    match INTERFACES.lock() {
+        Ok(mut map) => {
+            let multi_ipv6s = get_multicast_to_join(CONFIG.node.networks.clone())?;
+            for (interface_count, (locator, _)) in multi_ipv6s.into_iter().enumerate() {
+                map.insert(format!("multi{}", interface_count), (locator, Ipv6Addr::LOCALHOST));
+            }
+            map.insert("dns".to_string(), (0x000053535353, Ipv6Addr::LOCALHOST));
+            map.insert("log".to_string(), ((local_network.local_nid << 16) | local_network.local_nid, Ipv6Addr::LOCALHOST));
+        },
+        Err(err) => {
+            return Err(format!("open_loopback_underlay_socket(): failed to lock INTERFACES: {}", err));
+        }
+    }
+
+    Ok(EmulatorSocket {
+        mulcast_socket: Arc::new(mulcast_socket),
+        unicast_socket: Arc::new(unicast_socket),
+        local_network
+    })
+}
+
 /// Join multicast group
 ///     - use the socket to join multicast group using "UdpSocket.join_multicast_v6()"
 pub fn join_multicast(emulator_socket: &EmulatorSocket, multi_ipv6: &Ipv6Addr)
@@ -252,6 +315,11 @@ pub async fn underlay_multi_tx(emulator_socket: &EmulatorSocket, interface_name:
     // get interface multicast IPv6 address
     let interface = get_over_interface_by_name(interface_name)?;
 
+    // log records aren't overlay traffic
+    if interface_name != "log" {
+        capture_packet(CaptureDirection::Outbound, pck);
+    }
+
     // send packet over multicast
     let dest_addr = SocketAddrV6::new(interface.1, emulator_socket.local_network.local_uid, 0, emulator_socket.local_network.local_index);
     match emulator_socket.mulcast_socket.send_to(&pck, &dest_addr).await {
@@ -274,6 +342,8 @@ pub async fn underlay_uni_tx(emulator_socket: &EmulatorSocket, destination_addre
     -> Result<(), String>
 {
 
+    capture_packet(CaptureDirection::Outbound, pck);
+
     // send packet over unicast
     let dest_addr = SocketAddrV6::new(destination_address.clone(), destination_port.clone(), 0, emulator_socket.local_network.local_index);
     match emulator_socket.unicast_socket.send_to(&pck, &dest_addr).await {
@@ -285,4 +355,38 @@ pub async fn underlay_uni_tx(emulator_socket: &EmulatorSocket, destination_addre
         }
     }
 
-}
\ No newline at end of file
+}
+
+
+/// TX Unicast UDP (batch)
+///     - sends every (destination IPv6, port, packet) with as few system calls as possible (sendmmsg, GSO)
+///     - runs of same sized packets to one destination go out as one GSO buffer when gso is enabled
+///     - falls back to underlay_uni_tx per packet when batch_io is disabled
+pub async fn underlay_uni_tx_batch(emulator_socket: &EmulatorSocket, pcks: &[(Ipv6Addr, u16, Vec<u8>)])
+    -> Result<(), String>
+{
+
+    if !CONFIG.underlay.batch_io {
+        for (destination_address, destination_port, pck) in pcks {
+            underlay_uni_tx(emulator_socket, destination_address, destination_port, pck).await?;
+        }
+        return Ok(());
+    }
+
+    let mut batch: Vec<(SocketAddrV6, &[u8])> = Vec::with_capacity(pcks.len());
+    for (destination_address, destination_port, pck) in pcks {
+        capture_packet(CaptureDirection::Outbound, pck);
+        batch.push((SocketAddrV6::new(*destination_address, *destination_port, 0, emulator_socket.local_network.local_index), pck.as_slice()));
+    }
+
+    // send packets over unicast
+    match batch_io::send_batch(&emulator_socket.unicast_socket, &batch, CONFIG.underlay.gso).await {
+        Ok(()) => {
+            Ok(())
+        },
+        Err(err) => {
+            Err(format!("underlay_uni_tx_batch(): error sending {} packets: {}", pcks.len(), err))
+        }
+    }
+
+}
@@ -1,11 +1,14 @@
-mod services;
-mod models;
-mod layers;
+use emulator::{layers, models, services};
 
 use modular_bitfield_msb::bitfield;
 use modular_bitfield_msb::prelude::{B16, B64};
 use rand::rngs::StdRng;
-use services::log_services::log_info;
+use services::log_services::{log_filter_accepts, LogObservation, LogStreamTracker, RotatingFileSink, LOG_GAP_RETRANSMITS};
+use services::metrics_services::record_event;
+use services::time_services::get_current_timestamp;
+use layers::underlay_network::underlay_multi_tx;
+use models::config_models::LogTransport;
+use models::log_models::{LogControl, LogEvent, LogLevel, LogRecord, LOG_PROTOCOL_VERSION, MAX_LOG_DATAGRAM};
 use tokio::io::{self, AsyncBufReadExt};
 use tokio::time::{self, sleep};
 use tokio::{signal, time::Instant};
@@ -19,7 +22,7 @@ use layers::overlay_network::CONFIG;
 use layers::underlay_network::{close_underlay_socket, open_underlay_socket};
 
 // import JTP protocol
-use crate::layers::jtp_network::{close_jtp_socket, jtp_rx, jtp_nid_tx, jtp_fqdn_tx, open_jtp_socket };
+use crate::layers::jtp_network::{close_jtp_socket, jtp_connect, jtp_rx, jtp_nid_tx, jtp_fqdn_tx, open_jtp_socket, JtpFlow };
 
 #[tokio::main]
 async fn main() {
@@ -182,11 +185,17 @@ async fn throughput_test()
                 let start_time = Instant::now();
                 let loop_duration = Duration::from_secs(30);
 
+                // resolve node2 once, not for every packet
+                let mut flow: Option<JtpFlow> = None;
+
                 while start_time.elapsed() < loop_duration {
 
-                    match jtp_fqdn_tx(&emulator_socket, &"node2".to_string(), &buffer).await {
-                        Ok(()) => {},
-                        Err(_) => {}
+                    if flow.is_none() {
+                        flow = jtp_connect(&emulator_socket, "node2").await.ok();
+                    }
+
+                    if let Some(flow) = flow.as_mut() {
+                        let _ = flow.send(&buffer).await;
                     }
 
                 }
@@ -276,7 +285,7 @@ async fn rtt_test()
                                             if sequence == i {
                                                          
                                                 let elapsed = now.timestamp_micros() as u64 - rtt_header_response.timestamp();
-                                                log_info(&emulator_socket, &format!("METRIC;1;{}", elapsed)).await;
+                                                record_event(&emulator_socket, LogEvent::Metric { topology: 1, name: "rtt_us".to_string(), value: elapsed }).await;
 
                                             }
 
@@ -523,25 +532,92 @@ async fn logger_app()
 
             println!("\x1B[2J\x1B[1;1H");
 
-            let mut buf = [0; 1024];
+            // open the file sink if configured
+            let mut file_sink = match &CONFIG.logging.file {
+                Some(path) => {
+                    match RotatingFileSink::open(path, CONFIG.logging.max_file_bytes, CONFIG.logging.max_files) {
+                        Ok(sink) => Some(sink),
+                        Err(err) => {
+                            eprintln!("** - failed to open log file: {}", err);
+                            None
+                        }
+                    }
+                },
+                None => None
+            };
+
+            // sized for the largest datagram so long records aren't truncated
+            let mut buf = vec![0; MAX_LOG_DATAGRAM];
+            let mut tracker = LogStreamTracker::new();
+
+            // a gap is written once the node had LOG_GAP_RETRANSMITS chances to resend the records
+            let gap_window = Duration::from_millis(CONFIG.logging.retransmit_ms * LOG_GAP_RETRANSMITS);
+            let mut gap_timer = time::interval(Duration::from_millis(CONFIG.logging.retransmit_ms.max(1)));
             loop {
                 tokio::select! {
                     result = emulator_socket.mulcast_socket.recv_from(&mut buf) => {
                         match result {
                             Ok((len, addr)) => {
 
-                                match std::str::from_utf8(&buf[..len]) {
-                                    Ok(log) => {
-                                        println!("{}", log);
+                                match LogRecord::from_json_bytes(&buf[..len]) {
+                                    Ok(record) => {
+
+                                        // detect missing and duplicate records
+                                        let observation = tracker.observe(&record);
+
+                                        // acknowledge so the node can release or resend records
+                                        if CONFIG.logging.transport == LogTransport::Reliable && record.seq != 0 {
+                                            let ack = LogControl::Ack {
+                                                v: LOG_PROTOCOL_VERSION,
+                                                nid: record.nid,
+                                                session: record.session,
+                                                seq: tracker.acknowledged(record.nid, record.session)
+                                            };
+                                            if let Ok(ack_string) = ack.to_json_string() {
+                                                let _ = underlay_multi_tx(&emulator_socket, &"log".to_string(), ack_string.as_bytes()).await;
+                                            }
+                                        }
+
+                                        if observation == LogObservation::Duplicate {
+                                            continue;
+                                        }
+
+                                        // nothing will fill a gap without retransmission
+                                        if CONFIG.logging.transport != LogTransport::Reliable {
+                                            for (nid, session, from, to) in tracker.expired_gaps(Duration::ZERO) {
+                                                logger_write_gap(&mut file_sink, emulator_socket.local_network.local_nid, nid, session, from, to);
+                                            }
+                                        }
+
+                                        // filter by level and node
+                                        if !log_filter_accepts(&record, CONFIG.logging.level, &CONFIG.logging.nodes) {
+                                            continue;
+                                        }
+
+                                        // records are already JSON lines so write them out as received
+                                        let line = String::from_utf8_lossy(&buf[..len]);
+                                        println!("{}", line);
+                                        if let Some(sink) = file_sink.as_mut() {
+                                            if let Err(err) = sink.write_line(&line) {
+                                                eprintln!("** - {}", err);
+                                            }
+                                        }
+
                                     },
                                     Err(err) => {
-                                        let string = String::from_utf8_lossy(&buf);
+
+                                        // our own acknowledgements loop back on the group
+                                        if LogControl::from_json_bytes(&buf[..len]).is_ok() {
+                                            continue;
+                                        }
+
+                                        let string = String::from_utf8_lossy(&buf[..len]);
                                         println!("{}, {:?}", string, addr);
-                                        for byte in buf {
+                                        for byte in &buf[..len] {
                                             print!("{:02x} ", byte);
                                         }
                                         println!();
-                                        eprintln!("** - failed to convert log to string: {}", err);
+                                        eprintln!("** - failed to parse log record: {}", err);
                                     }
                                 }
                             },
@@ -550,6 +626,13 @@ async fn logger_app()
                             }
                         }
                     },
+
+                    // gaps retransmissions didn't fill
+                    _ = gap_timer.tick(), if CONFIG.logging.transport == LogTransport::Reliable => {
+                        for (nid, session, from, to) in tracker.expired_gaps(gap_window) {
+                            logger_write_gap(&mut file_sink, emulator_socket.local_network.local_nid, nid, session, from, to);
+                        }
+                    },
                     _ = signal::ctrl_c() => {
                         println!("** - ctrl+c received, exiting");
                         break;
@@ -557,6 +640,16 @@ async fn logger_app()
                 }
             }
 
+            // write the gaps still open
+            for (nid, session, from, to) in tracker.expired_gaps(Duration::ZERO) {
+                logger_write_gap(&mut file_sink, emulator_socket.local_network.local_nid, nid, session, from, to);
+            }
+
+            // report the records that never arrived
+            for (nid, session, from, to) in tracker.outstanding_gaps() {
+                eprintln!("** - missing records from 0x{:016X} (session {}): {}..={}", nid, session, from, to);
+            }
+
             let _ = close_underlay_socket(emulator_socket).await;
         },
         Err(err) => {
@@ -565,6 +658,36 @@ async fn logger_app()
     }
 }
 
+/// Logger gap record
+///     - written by the logger itself so post-processing knows which records are missing
+fn logger_write_gap(file_sink: &mut Option<RotatingFileSink>, logger_nid: u64, nid: u64, session: u64, from: u64, to: u64)
+{
+    let gap = LogRecord {
+        v: LOG_PROTOCOL_VERSION,
+        level: LogLevel::Warn,
+        node: "logger".to_string(),
+        nid: logger_nid,
+        session: 0,
+        seq: 0,
+        ts: get_current_timestamp().unwrap_or(0),
+        event: LogEvent::LogGap { nid, session, from, to }
+    };
+
+    match gap.to_json_string() {
+        Ok(line) => {
+            println!("{}", line);
+            if let Some(sink) = file_sink.as_mut() {
+                if let Err(err) = sink.write_line(&line) {
+                    eprintln!("** - {}", err);
+                }
+            }
+        },
+        Err(err) => {
+            eprintln!("** - {}", err);
+        }
+    }
+}
+
 
 
 async fn user_app()
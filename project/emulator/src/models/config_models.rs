@@ -1,10 +1,42 @@
 use serde::Deserialize;
 
+use super::log_models::LogLevel;
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub app: AppConfig,
     pub node: NodeConfig,
-    pub network: NetworkConfig
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub wire: WireConfig,
+    #[serde(default)]
+    pub nonce: NonceConfig,
+    #[serde(default)]
+    pub jcmp_auth: JcmpAuthConfig,
+    #[serde(default)]
+    pub jtp_crypto: JtpCryptoConfig,
+    #[serde(default)]
+    pub jcmp_limits: JcmpLimitsConfig,
+    #[serde(default)]
+    pub dad: DadConfig,
+    #[serde(default)]
+    pub router_discovery: RouterDiscoveryConfig,
+    #[serde(default)]
+    pub neighbour_discovery: NeighbourDiscoveryConfig,
+    #[serde(default)]
+    pub forwarding: ForwardingConfig,
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    #[serde(default)]
+    pub underlay: UnderlayConfig
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,6 +56,9 @@ pub struct AppConfig {
 pub struct NodeConfig {
     pub router: bool,
     pub networks: Vec<u16>,
+
+    // 0 (or left out) is unset, see dad.auto_nid
+    #[serde(default)]
     pub nid: u64,
     pub name: String
 }
@@ -40,7 +75,421 @@ pub struct NetworkConfig {
     pub DNS_TTL_S: u8,
 
     pub AD_HOC_TIMEOUT_MS: u64,
-    pub AD_HOC_RTO_NS: u64,
     pub AD_HOC_TTL_S: u8,
     pub AD_MAX_HOPS: u8
-}
\ No newline at end of file
+}
+
+/// Logger configuration
+///     - only used by the node running the logger
+///     - level is the minimum level written out
+///     - nodes filters by node name or NID (e.g. "node1" or "0x0000000000000001"), empty means all
+///     - file is rotated once it reaches max_file_bytes, keeping max_files old files
+///     - transport "reliable" makes the logger acknowledge records and the nodes retransmit
+///       anything not acknowledged after retransmit_ms, holding at most spool_size records
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    pub nodes: Vec<String>,
+    pub file: Option<String>,
+    pub max_file_bytes: u64,
+    pub max_files: u32,
+    pub transport: LogTransport,
+    pub spool_size: usize,
+    pub retransmit_ms: u64,
+    pub flush_timeout_ms: u64
+}
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Debug,
+            nodes: Vec::new(),
+            file: Some("logs/ilnp.jsonl".to_string()),
+            max_file_bytes: 64 * 1024 * 1024,
+            max_files: 5,
+            transport: LogTransport::Multicast,
+            spool_size: 10000,
+            retransmit_ms: 200,
+            flush_timeout_ms: 5000
+        }
+    }
+}
+
+/// Log transport
+///     - multicast: fire and forget (original behaviour)
+///     - reliable: sequenced records are acknowledged by the logger and retransmitted by the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTransport {
+    Multicast,
+    Reliable
+}
+
+/// Per-node metrics file configuration
+///     - written by every node independently of the logger
+///     - path defaults to logs/<node name>.metrics.<format>
+///     - a PCB snapshot is written every interval_ms, events as they happen
+///     - csv writes snapshots to path and events alongside it (x.csv -> x.events.csv)
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub path: Option<String>,
+    pub format: MetricsFormat,
+    pub interval_ms: u64
+}
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            format: MetricsFormat::Jsonl,
+            interval_ms: 1000
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsFormat {
+    Jsonl,
+    Csv
+}
+
+/// Packet capture configuration
+///     - every overlay packet sent or received is written to a pcapng file
+///     - path defaults to logs/<node name>.pcapng
+///     - read it back with ilnp-dump
+///     - buffered packets are flushed every flush_ms so the file can be read while the node runs
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    pub path: Option<String>,
+    pub flush_ms: u64
+}
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            flush_ms: 1000
+        }
+    }
+}
+
+/// Header validation configuration
+///     - one action per check applied to received ILNP headers
+///     - drop: discard the packet
+///     - log: discard the packet and log why
+///     - count: only count the failure, the packet is still processed
+///     - failures are always counted in the PCB (validation)
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    pub length_mismatch: ValidationAction,
+    pub trailing_bytes: ValidationAction,
+    pub wrong_version: ValidationAction,
+    pub unknown_next_header: ValidationAction,
+    pub invalid_source: ValidationAction
+}
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            length_mismatch: ValidationAction::Drop,
+            trailing_bytes: ValidationAction::Drop,
+            wrong_version: ValidationAction::Drop,
+            unknown_next_header: ValidationAction::Drop,
+            invalid_source: ValidationAction::Drop
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationAction {
+    Drop,
+    Log,
+    Count
+}
+
+
+/// Wire format configuration
+///     - native: ILNPv6 header with next header 150 (JCMP) or 151 (JTP)
+///     - rfc6741: IPv6 layout per RFC 6741/6744, a destination options header carrying the
+///       Nonce option, JCMP as ICMPv6 with a checksum and JTP on experimental next header 253
+///     - every node in the topology has to use the same format
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WireConfig {
+    pub format: WireFormat
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Native,
+    Rfc6741
+}
+
+
+/// Nonce session configuration (RFC 6744)
+///     - needs the rfc6741 wire format
+///     - a nonce is picked per correspondent and learned from the first packet a correspondent sends us
+///     - packets addressed to us with a missing or wrong nonce are dropped
+///     - overheard advertisements and responses can't be checked and are dropped once a session exists
+///     - sessions expire session_ttl_s after the last verified packet, so a restarted node is accepted again
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NonceConfig {
+    pub enabled: bool,
+    pub session_ttl_s: u64
+}
+impl Default for NonceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            session_ttl_s: 600
+        }
+    }
+}
+
+
+/// JCMP authentication configuration
+///     - every JCMP message carries an HMAC-SHA256 trailer under the network key
+///     - messages without a valid trailer are dropped before any handler runs
+///     - messages outside max_skew_ms of our clock, or already seen, are dropped as replays
+///     - every node needs the same key and clocks synchronised within max_skew_ms
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct JcmpAuthConfig {
+    pub enabled: bool,
+    pub key: String,
+    pub max_skew_ms: u64,
+    pub replay_cache_size: usize
+}
+impl Default for JcmpAuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key: String::new(),
+            max_skew_ms: 5000,
+            replay_cache_size: 4096
+        }
+    }
+}
+
+
+/// JTP encryption configuration
+///     - psk is mixed into the keys and signs the Hello / Hello Ack, without it the exchange isn't authenticated
+///     - keys come from an X25519 exchange (Hello / Hello Ack) bound to the NIDs, not the locators
+///     - psk is mixed into the keys, without it the exchange isn't authenticated
+///     - Hello is retransmitted every handshake_rto_ms, at most handshake_retransmit_limit times
+///     - every node has to enable it, unencrypted packets are dropped
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct JtpCryptoConfig {
+    pub enabled: bool,
+    pub psk: String,
+    pub handshake_rto_ms: u64,
+    pub handshake_retransmit_limit: u64
+}
+impl Default for JtpCryptoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            psk: String::new(),
+            handshake_rto_ms: 200,
+            handshake_retransmit_limit: 5
+        }
+    }
+}
+
+
+/// JCMP flood protection
+///     - at most max_concurrent_handlers JCMP packets are handled at once, the rest are dropped
+///     - with rate_limit each (source NID, JCMP code) has a token bucket holding bucket_size
+///       packets and refilled with refill_per_s packets a second
+///     - buckets are kept for at most max_sources (NID, code) pairs, an idle bucket is forgotten once full again
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct JcmpLimitsConfig {
+    pub max_concurrent_handlers: usize,
+    pub rate_limit: bool,
+    pub bucket_size: u32,
+    pub refill_per_s: u32,
+    pub max_sources: usize
+}
+impl Default for JcmpLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_handlers: 256,
+            rate_limit: true,
+            bucket_size: 100,
+            refill_per_s: 50,
+            max_sources: 4096
+        }
+    }
+}
+
+
+/// Duplicate NID detection
+///     - before coming up the node sends a probe on each network, probes times, probe_interval_ms apart
+///     - a node already using the NID defends it, on_conflict decides whether we still come up
+///     - auto_nid picks a random NID when node.nid is unset
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DadConfig {
+    pub enabled: bool,
+    pub probes: u32,
+    pub probe_interval_ms: u64,
+    pub on_conflict: DadAction,
+    pub auto_nid: bool
+}
+impl Default for DadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            probes: 3,
+            probe_interval_ms: 200,
+            on_conflict: DadAction::Refuse,
+            auto_nid: false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DadAction {
+    Refuse,
+    Alarm
+}
+
+
+/// Router discovery (JCMP router solicitation / advertisement)
+///     - routers advertise themselves every advertisement_interval_s and whenever solicited
+///     - hosts keep every advertising router as a default router for router_lifetime_s
+///     - off-link traffic from a host goes to a default router instead of starting path discovery
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct RouterDiscoveryConfig {
+    pub enabled: bool,
+    pub advertisement_interval_s: u64,
+    pub router_lifetime_s: u16
+}
+impl Default for RouterDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            advertisement_interval_s: 30,
+            router_lifetime_s: 90
+        }
+    }
+}
+
+
+/// Neighbour discovery
+///     - with unsolicited_advertisements the node advertises its (IPv6, port) on every network once it's up
+///     - the underlay address is checked every address_check_interval_ms and advertised again when it changes (0 disables)
+///     - neighbours are reachable for reachable_time_ms after a confirmation, then stale
+///     - a stale neighbour in use gets max_unicast_probes solicitations, retrans_timer_ms apart, before it's unreachable
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NeighbourDiscoveryConfig {
+    pub unsolicited_advertisements: bool,
+    pub address_check_interval_ms: u64,
+    pub reachable_time_ms: u64,
+    pub retrans_timer_ms: u64,
+    pub max_unicast_probes: u32
+}
+impl Default for NeighbourDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            unsolicited_advertisements: true,
+            address_check_interval_ms: 1000,
+            reachable_time_ms: 30000,
+            retrans_timer_ms: 1000,
+            max_unicast_probes: 3
+        }
+    }
+}
+
+/// Forwarding
+///     - packets for a locator without a route are held while the route is discovered
+///     - at most hold_queue_size packets per locator and hold_max_locators locators at once
+///     - packets held longer than hold_timeout_ms are dropped when the queue is flushed
+///     - with unreachable_messages the senders of packets dropped for lack of a route get a JCMP destination unreachable
+///     - data packets leave with hop_limit, each router decrements it and drops the packets that run out
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ForwardingConfig {
+    pub hold_queue_size: usize,
+    pub hold_max_locators: usize,
+    pub hold_timeout_ms: u64,
+    pub unreachable_messages: bool,
+    pub hop_limit: u8
+}
+impl Default for ForwardingConfig {
+    fn default() -> Self {
+        Self {
+            hold_queue_size: 64,
+            hold_max_locators: 1024,
+            hold_timeout_ms: 3000,
+            unreachable_messages: true,
+            hop_limit: 64
+        }
+    }
+}
+
+/// Unicast processing pipeline
+///     - received packets are handled by workers, the packets of a flow (source/destination NID + flow label) always go to the same worker
+///     - workers is the number of workers (0 means one per core)
+///     - each worker has a queue of queue_size packets
+///     - when a queue is full the receiver waits for room (backpressure) or drops the packet (drop)
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    pub workers: usize,
+    pub queue_size: usize,
+    pub overflow: QueueOverflow
+}
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            workers: 0,
+            queue_size: 1024,
+            overflow: QueueOverflow::Backpressure
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueOverflow {
+    Backpressure,
+    Drop
+}
+
+/// Underlay socket I/O
+///     - with batch_io the unicast socket receives up to batch_size datagrams per recvmmsg and sends with sendmmsg
+///     - gro lets the kernel hand over coalesced datagrams, gso sends runs of same sized packets to one destination as one buffer
+///     - each falls back to the per datagram path when the kernel doesn't support it
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct UnderlayConfig {
+    pub batch_io: bool,
+    pub batch_size: usize,
+    pub gro: bool,
+    pub gso: bool
+}
+impl Default for UnderlayConfig {
+    fn default() -> Self {
+        Self {
+            batch_io: true,
+            batch_size: 32,
+            gro: true,
+            gso: true
+        }
+    }
+}
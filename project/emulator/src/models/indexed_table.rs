@@ -0,0 +1,290 @@
+use std::{collections::{btree_map::Entry as BTreeEntry, BTreeMap, HashSet}, hash::Hash, sync::Mutex, time::{Duration, Instant}};
+use dashmap::{mapref::entry::Entry, DashMap};
+
+/// Timer wheel granularity and size
+///     - expiry is checked every WHEEL_TICK, one revolution is WHEEL_SLOTS ticks
+///     - entries living longer than a revolution wait in the overflow until the hand gets within a revolution of them
+const WHEEL_TICK: Duration = Duration::from_millis(100);
+const WHEEL_SLOTS: usize = 512;
+
+/// Timer Wheel
+///     - each slot holds the (key, deadline) of the entries expiring in that tick of the current revolution
+///     - the overflow holds the keys due after this revolution, sorted by deadline
+///     - advancing the wheel hands back the keys whose deadline passed
+struct TimerWheel<K> {
+    start: Instant,
+    current: u64,
+    slots: Vec<Vec<(K, Instant)>>,
+    overflow: BTreeMap<Instant, Vec<K>>
+}
+impl<K: PartialEq> TimerWheel<K> {
+    fn new(start: Instant)
+        -> Self
+    {
+        TimerWheel { start, current: 0, slots: (0..WHEEL_SLOTS).map(|_| Vec::new()).collect(), overflow: BTreeMap::new() }
+    }
+
+    fn tick_of(&self, instant: Instant)
+        -> u64
+    {
+        (instant.saturating_duration_since(self.start).as_nanos() / WHEEL_TICK.as_nanos()) as u64
+    }
+
+    /// Tick a deadline is kept under
+    ///     - never behind the hand, it would only be seen a revolution later
+    ///     - None if it's past this revolution
+    fn slot_of(&self, deadline: Instant)
+        -> Option<usize>
+    {
+        let tick = self.tick_of(deadline).max(self.current);
+        if tick >= self.current + WHEEL_SLOTS as u64 {
+            return None;
+        }
+        Some((tick % WHEEL_SLOTS as u64) as usize)
+    }
+
+    fn schedule(&mut self, key: K, deadline: Instant)
+    {
+        match self.slot_of(deadline) {
+            Some(slot_index) => self.slots[slot_index].push((key, deadline)),
+            None => self.overflow.entry(deadline).or_default().push(key)
+        }
+    }
+
+    /// Forget a key's deadline
+    ///     - called when the entry is refreshed or removed so no stale pair is left behind
+    fn cancel(&mut self, key: &K, deadline: Instant)
+    {
+        match self.slot_of(deadline) {
+            Some(slot_index) => {
+                let slot = &mut self.slots[slot_index];
+                if let Some(index) = slot.iter().position(|(scheduled, scheduled_deadline)| scheduled == key && *scheduled_deadline == deadline) {
+                    slot.swap_remove(index);
+                }
+            },
+            None => {
+                if let BTreeEntry::Occupied(mut keys) = self.overflow.entry(deadline) {
+                    keys.get_mut().retain(|scheduled| scheduled != key);
+                    if keys.get().is_empty() {
+                        keys.remove();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Advance the hand up to now
+    ///     - returns the keys whose deadline passed
+    ///     - overflow keys now within a revolution move into their slot
+    fn advance(&mut self, now: Instant)
+        -> Vec<(K, Instant)>
+    {
+        let mut due = Vec::new();
+        let target = self.tick_of(now);
+
+        // a whole revolution visits every slot
+        let steps = (target.saturating_sub(self.current) + 1).min(WHEEL_SLOTS as u64);
+        for step in 0..steps {
+            let slot = &mut self.slots[((self.current + step) % WHEEL_SLOTS as u64) as usize];
+            let mut index = 0;
+            while index < slot.len() {
+                if slot[index].1 <= now {
+                    due.push(slot.swap_remove(index));
+                } else {
+                    index += 1;
+                }
+            }
+        }
+        self.current = target;
+
+        // the revolution now ends further out
+        let horizon = self.start + WHEEL_TICK * (self.current + WHEEL_SLOTS as u64) as u32;
+        let later = self.overflow.split_off(&horizon);
+        for (deadline, keys) in std::mem::replace(&mut self.overflow, later) {
+            for key in keys {
+                if deadline <= now {
+                    due.push((key, deadline));
+                } else {
+                    self.schedule(key, deadline);
+                }
+            }
+        }
+        due
+    }
+
+    /// Take the key with the nearest deadline
+    ///     - slots are visited from the hand onwards, the overflow only counts once this revolution is empty
+    fn take_soonest(&mut self)
+        -> Option<(K, Instant)>
+    {
+        for step in 0..WHEEL_SLOTS as u64 {
+            let slot = &mut self.slots[((self.current + step) % WHEEL_SLOTS as u64) as usize];
+            let soonest = slot.iter().enumerate()
+                .min_by_key(|(_, (_, deadline))| *deadline)
+                .map(|(index, _)| index);
+            if let Some(index) = soonest {
+                return Some(slot.swap_remove(index));
+            }
+        }
+
+        // everything is at least a revolution away
+        let mut keys = self.overflow.first_entry()?;
+        let deadline = *keys.key();
+        let key = keys.get_mut().pop()?;
+        if keys.get().is_empty() {
+            keys.remove();
+        }
+        Some((key, deadline))
+    }
+}
+
+/// Indexed Table
+///     - concurrent map from a unique key to a value with a time to live
+///     - a secondary index (e.g. by NID, FQDN or locator) is kept next to it so lookups never scan the table
+///     - lookups only take the shard locks of the entries they read, nothing is cloned but the results
+///     - expired entries are never returned, a timer wheel removes them as the table is written to
+///     - when the table is full the entry closest to expiring makes room
+pub struct IndexedTable<K, I, V> {
+    capacity: usize,
+    index_of: fn(&K, &V) -> I,
+    entries: DashMap<K, (V, Instant)>,
+    index: DashMap<I, HashSet<K>>,
+    wheel: Mutex<TimerWheel<K>>
+}
+impl<K: Eq + Hash + Clone, I: Eq + Hash + Clone, V: Clone> IndexedTable<K, I, V> {
+    pub fn new(capacity: usize, index_of: fn(&K, &V) -> I)
+        -> Self
+    {
+        IndexedTable {
+            capacity,
+            index_of,
+            entries: DashMap::new(),
+            index: DashMap::new(),
+            wheel: Mutex::new(TimerWheel::new(Instant::now()))
+        }
+    }
+
+    /// Insert or refresh an entry
+    ///     - returns the previous value if it hadn't expired
+    pub fn insert(&self, key: K, value: V, ttl: Duration)
+        -> Option<V>
+    {
+        let now = Instant::now();
+        let deadline = now + ttl;
+        let mut wheel = match self.wheel.lock() {
+            Ok(wheel) => wheel,
+            Err(poisoned) => poisoned.into_inner()
+        };
+
+        // expiry
+        for (key, deadline) in wheel.advance(now) {
+            self.remove_if(&key, |_, expires| *expires == deadline);
+        }
+
+        // make room
+        if self.capacity > 0 && !self.entries.contains_key(&key) {
+            while self.entries.len() >= self.capacity {
+                match wheel.take_soonest() {
+                    Some((oldest, oldest_deadline)) => {
+                        self.remove_if(&oldest, |_, expires| *expires == oldest_deadline);
+                    },
+                    None => break
+                }
+            }
+        }
+
+        // the entry's shard lock is held while the index follows
+        let index = (self.index_of)(&key, &value);
+        let previous = match self.entries.entry(key.clone()) {
+            Entry::Occupied(mut entry) => {
+                let previous_index = (self.index_of)(&key, &entry.get().0);
+                if previous_index != index {
+                    self.unindex(&previous_index, &key);
+                }
+                wheel.cancel(&key, entry.get().1);
+                self.index.entry(index).or_default().insert(key.clone());
+                Some(entry.insert((value, deadline)))
+            },
+            Entry::Vacant(entry) => {
+                self.index.entry(index).or_default().insert(key.clone());
+                entry.insert((value, deadline));
+                None
+            }
+        };
+        wheel.schedule(key, deadline);
+
+        previous.and_then(|(value, expires)| if expires > now { Some(value) } else { None })
+    }
+
+    /// Entry for a key
+    pub fn get(&self, key: &K)
+        -> Option<V>
+    {
+        let now = Instant::now();
+        self.entries.get(key)
+            .filter(|entry| entry.1 > now)
+            .map(|entry| entry.0.clone())
+    }
+
+    /// Entries under an index value
+    pub fn lookup(&self, index: &I)
+        -> Vec<(K, V)>
+    {
+        // copy the keys out first, the index lock is never held while reading the entries
+        let keys: Vec<K> = match self.index.get(index) {
+            Some(keys) => keys.iter().cloned().collect(),
+            None => {
+                return Vec::new();
+            }
+        };
+
+        let now = Instant::now();
+        keys.into_iter()
+            .filter_map(|key| {
+                let value = self.entries.get(&key)
+                    .filter(|entry| entry.1 > now)
+                    .map(|entry| entry.0.clone())?;
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    /// Remove every entry matching
+    pub fn remove_where(&self, matches: impl Fn(&K, &V) -> bool)
+    {
+        let mut wheel = match self.wheel.lock() {
+            Ok(wheel) => wheel,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        let keys: Vec<K> = self.entries.iter()
+            .filter(|entry| matches(entry.key(), &entry.value().0))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in keys {
+            if let Some((_, deadline)) = self.remove_if(&key, |value, _| matches(&key, value)) {
+                wheel.cancel(&key, deadline);
+            }
+        }
+    }
+
+    fn remove_if(&self, key: &K, matches: impl Fn(&V, &Instant) -> bool)
+        -> Option<(V, Instant)>
+    {
+        // the entry's shard lock is held while the index follows
+        match self.entries.entry(key.clone()) {
+            Entry::Occupied(entry) if matches(&entry.get().0, &entry.get().1) => {
+                self.unindex(&(self.index_of)(key, &entry.get().0), key);
+                Some(entry.remove())
+            },
+            _ => None
+        }
+    }
+
+    fn unindex(&self, index: &I, key: &K)
+    {
+        self.index.remove_if_mut(index, |_, keys| {
+            keys.remove(key);
+            keys.is_empty()
+        });
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::network_packets::INLPv6Packet;
+
+/*******************************************/
+/// JCMP authentication trailer
+/// Appended to the JCMP message when the control plane is authenticated
+///     - timestamp (u64, microseconds) and counter (u32) make every message unique for replay protection
+///     - HMAC-SHA256 under the network key covers the ILV addresses, the message, timestamp and counter
+pub const JCMP_AUTH_TAG_SIZE: usize = 32;
+pub const JCMP_AUTH_TRAILER_SIZE: usize = 8 + 4 + JCMP_AUTH_TAG_SIZE;
+
+/// Fields of a verified trailer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JcmpAuthTrailer {
+    pub timestamp_us: u64,
+    pub counter: u32
+}
+
+/// JCMP authentication errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JcmpAuthError {
+    Missing { actual: usize },
+    InvalidTag
+}
+impl fmt::Display for JcmpAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JcmpAuthError::Missing { actual } => write!(f, "no room for the {} byte trailer in {} bytes", JCMP_AUTH_TRAILER_SIZE, actual),
+            JcmpAuthError::InvalidTag => write!(f, "invalid hmac")
+        }
+    }
+}
+/*******************************************/
+
+
+/// Append the trailer to an encoded JCMP message
+pub fn jcmp_auth_append(key: &[u8], header: &INLPv6Packet, jcmp: &mut Vec<u8>, trailer: JcmpAuthTrailer)
+{
+    jcmp.extend_from_slice(&trailer.timestamp_us.to_be_bytes());
+    jcmp.extend_from_slice(&trailer.counter.to_be_bytes());
+    let tag = jcmp_auth_tag(key, header, jcmp).finalize().into_bytes();
+    jcmp.extend_from_slice(&tag);
+}
+
+/// Verify and strip the trailer
+///     - returns the JCMP message without the trailer
+///     - the tag is compared in constant time
+pub fn jcmp_auth_verify<'a>(key: &[u8], header: &INLPv6Packet, payload: &'a [u8])
+    -> Result<(&'a [u8], JcmpAuthTrailer), JcmpAuthError>
+{
+    if payload.len() < JCMP_AUTH_TRAILER_SIZE {
+        return Err(JcmpAuthError::Missing { actual: payload.len() });
+    }
+
+    let (signed, tag) = payload.split_at(payload.len() - JCMP_AUTH_TAG_SIZE);
+    if jcmp_auth_tag(key, header, signed).verify_slice(tag).is_err() {
+        return Err(JcmpAuthError::InvalidTag);
+    }
+
+    let (message, fields) = signed.split_at(signed.len() - 12);
+    let mut timestamp_us = [0u8; 8];
+    let mut counter = [0u8; 4];
+    timestamp_us.copy_from_slice(&fields[..8]);
+    counter.copy_from_slice(&fields[8..]);
+
+    Ok((message, JcmpAuthTrailer {
+        timestamp_us: u64::from_be_bytes(timestamp_us),
+        counter: u32::from_be_bytes(counter)
+    }))
+}
+
+fn jcmp_auth_tag(key: &[u8], header: &INLPv6Packet, signed: &[u8])
+    -> Hmac<Sha256>
+{
+    // HMAC takes keys of any length
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(&header.source_locator().to_be_bytes());
+    mac.update(&header.source_identifier().to_be_bytes());
+    mac.update(&header.destination_locator().to_be_bytes());
+    mac.update(&header.destination_identifier().to_be_bytes());
+    mac.update(signed);
+    mac
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use super::protocol_control_block::ILNP_PCB_S;
+
+/// Log protocol version
+///     - bumped whenever LogRecord or LogEvent change in a non-additive way
+pub const LOG_PROTOCOL_VERSION: u8 = 1;
+
+/// Largest log datagram
+///     - maximum UDP payload over IPv6 (65535 - 8 byte UDP header)
+///     - the logger receive buffer is sized to this so records are never truncated
+pub const MAX_LOG_DATAGRAM: usize = 65527;
+
+/// Log Level
+///     - ordered so records can be filtered with a minimum level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error
+}
+
+/// Log Event
+///     - the kind of record and its typed fields
+///     - "topology" is the key used to group runs in the analysis (e.g. number of routers)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "fields", rename_all = "snake_case")]
+pub enum LogEvent {
+    Message { text: String },
+    Metric { topology: u32, name: String, value: u64 },
+    Pcb { topology: u32, pcb: ILNP_PCB_S },
+    DiscoveryStarted { topology: u32 },
+    DiscoveryCompleted { topology: u32 },
+    LogGap { nid: u64, session: u64, from: u64, to: u64 },
+    PcbSnapshot { pcb: ILNP_PCB_S },
+    RouteDiscovered { locator: u64, next_hop: u64, interface: String, hop_count: u8 },
+    NameResolved { fqdn: String, nid: u64, locator: u64 },
+    NeighbourResolved { nid: u64, interface: String, address: String, port: u16 },
+    NonceSessionEstablished { nid: u64, nonce: u32 },
+    NonceSessionReset { nid: u64 },
+    DefaultRouterLearned { nid: u64, interface: String, lifetime: u16 },
+    NeighbourUnreachable { nid: u64, interface: String },
+    DestinationUnreachable { locator: u64, nid: u64, reported_by: u64 }
+}
+
+/// Log Record
+///     - one JSON line per record
+///     - sent by the nodes to the logger over the log multicast group
+///     - session is the node's start time, seq counts records within a session (from 1)
+///     - seq 0 means the record is not sequenced (e.g. written by the logger itself)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub v: u8,
+    pub level: LogLevel,
+    pub node: String,
+    pub nid: u64,
+    #[serde(default)]
+    pub session: u64,
+    #[serde(default)]
+    pub seq: u64,
+    pub ts: u64,
+    #[serde(flatten)]
+    pub event: LogEvent
+}
+
+impl LogRecord {
+    pub fn to_json_string(&self)
+        -> Result<String, String>
+    {
+        match serde_json::to_string(self) {
+            Ok(json_string) => {
+                Ok(json_string)
+            },
+            Err(err) => {
+                Err(format!("LogRecord::to_json_string(): failed to serialise record: {}", err))
+            }
+        }
+    }
+
+    pub fn from_json_bytes(bytes: &[u8])
+        -> Result<Self, String>
+    {
+        match serde_json::from_slice::<LogRecord>(bytes) {
+            Ok(record) => {
+                Ok(record)
+            },
+            Err(err) => {
+                Err(format!("LogRecord::from_json_bytes(): failed to parse record: {}", err))
+            }
+        }
+    }
+}
+
+
+/// Log Control
+///     - sent by the logger back to the nodes over the log multicast group
+///     - Ack is cumulative: every record up to and including seq was received
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "control", rename_all = "snake_case")]
+pub enum LogControl {
+    Ack { v: u8, nid: u64, session: u64, seq: u64 }
+}
+
+impl LogControl {
+    pub fn to_json_string(&self)
+        -> Result<String, String>
+    {
+        match serde_json::to_string(self) {
+            Ok(json_string) => {
+                Ok(json_string)
+            },
+            Err(err) => {
+                Err(format!("LogControl::to_json_string(): failed to serialise control: {}", err))
+            }
+        }
+    }
+
+    pub fn from_json_bytes(bytes: &[u8])
+        -> Result<Self, String>
+    {
+        match serde_json::from_slice::<LogControl>(bytes) {
+            Ok(control) => {
+                Ok(control)
+            },
+            Err(err) => {
+                Err(format!("LogControl::from_json_bytes(): failed to parse control: {}", err))
+            }
+        }
+    }
+}
\ No newline at end of file
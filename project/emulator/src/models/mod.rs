@@ -1,4 +1,8 @@
 pub mod config_models;
+pub mod indexed_table;
+pub mod jcmp_auth;
+pub mod log_models;
 pub mod network_models;
 pub mod network_packets;
-pub mod protocol_control_block;
\ No newline at end of file
+pub mod protocol_control_block;
+pub mod wire_format;
\ No newline at end of file
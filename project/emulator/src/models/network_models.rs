@@ -1,5 +1,7 @@
-use std::{net::Ipv6Addr, sync::Arc};
-use tokio::net::UdpSocket;
+use std::{collections::HashMap, hash::Hash, net::Ipv6Addr, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use tokio::{net::UdpSocket, sync::Notify};
+
+use super::network_packets::INLPv6Packet;
 
 #[derive(Debug, Clone)]
 pub struct EmulatorLocalNetwork {
@@ -33,4 +35,159 @@ pub struct JTPResponse {
     pub destination_locator: u64,
     pub destination_nid: u64,
     pub payload: Vec<u8>
-}
\ No newline at end of file
+}
+
+/// Result of checking a received nonce against the correspondent's session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceCheck {
+    Established,
+    Verified,
+    Missing,
+    Mismatch
+}
+
+/// Neighbour reachability (RFC 4861 neighbour unreachability detection)
+///     - Reachable until reachable_time_ms after the last confirmation
+///     - Stale entries are still used, using one starts the unicast probes
+///     - Probe entries that never get an answer are removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighbourState {
+    Reachable,
+    Stale,
+    Probe
+}
+
+/// Neighbour cache entry
+///     - (interface, IPv6, unicast port) of the neighbour
+///     - updated is when the state last changed, for a Reachable entry the last confirmation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeighbourEntry {
+    pub interface: String,
+    pub address: Ipv6Addr,
+    pub port: u16,
+    pub state: NeighbourState,
+    pub updated: Instant
+}
+
+/// Token bucket
+///     - holds up to size tokens, refilled continuously at refill_per_s
+///     - each packet takes a token, packets finding the bucket empty are throttled
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant
+}
+impl TokenBucket {
+    pub fn new(size: u32, now: Instant)
+        -> Self
+    {
+        TokenBucket { tokens: size as f64, last_refill: now }
+    }
+
+    pub fn take(&mut self, size: u32, refill_per_s: u32, now: Instant)
+        -> bool
+    {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill_per_s as f64).min(size as f64);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Forwarding outcome
+///     - Held packets wait for their route and are counted as forwarded once flushed
+///     - Dropped packets were already counted with their drop reason
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardOutcome {
+    Sent,
+    Held,
+    Dropped
+}
+
+/// Packet held while its route is discovered
+///     - header is the decoded header, pck the packet as it will be sent on
+#[derive(Debug, Clone)]
+pub struct HeldPacket {
+    pub header: INLPv6Packet,
+    pub pck: Vec<u8>,
+    pub held: Instant
+}
+
+/// Pending resolutions
+///     - one entry per key being resolved, shared by every task waiting on it
+///     - join tells the caller whether it has to send the query, at most one query per key every rto
+///     - the response handler calls resolve to wake every waiter at once
+///     - entries older than rto have no waiters left and are dropped
+pub struct PendingTable<K> {
+    rto: Duration,
+    pending: Mutex<HashMap<K, (Arc<Notify>, Instant)>>
+}
+impl<K: Eq + Hash + Clone> PendingTable<K> {
+    pub fn new(rto: Duration)
+        -> Self
+    {
+        PendingTable { rto, pending: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn rto(&self)
+        -> Duration
+    {
+        self.rto
+    }
+
+    /// Wait for key
+    ///     - returns the Notify to wait on and true if the caller sends the query
+    pub fn join(&self, key: &K)
+        -> (Arc<Notify>, bool)
+    {
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        let now = Instant::now();
+        pending.retain(|_, (_, queried)| now.duration_since(*queried) < self.rto);
+
+        match pending.get(key) {
+            Some((notify, _)) => (notify.clone(), false),
+            None => {
+                let notify = Arc::new(Notify::new());
+                pending.insert(key.clone(), (notify.clone(), now));
+                (notify, true)
+            }
+        }
+    }
+
+    /// Wake everybody waiting for key
+    pub fn resolve(&self, key: &K)
+    {
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        if let Some((notify, _)) = pending.remove(key) {
+            notify.notify_waiters();
+        }
+    }
+
+    /// Wake everybody waiting for any key matching
+    pub fn resolve_matching<F: Fn(&K) -> bool>(&self, matches: F)
+    {
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        pending.retain(|key, (notify, _)| {
+            if matches(key) {
+                notify.notify_waiters();
+                return false;
+            }
+            true
+        });
+    }
+}
@@ -1,4 +1,5 @@
-#![allow(warnings)]
+// #[bitfield] expands to parenthesised field types and a new() without a Default
+#![allow(unused_parens, clippy::new_without_default)]
 
 use modular_bitfield_msb::{bitfield, prelude::{B4, B8, B16, B20, B64}};
 
@@ -26,213 +27,231 @@ pub struct INLPv6Packet {
 /*******************************************/
 /// JCMP Packets
 /// This is the control plane packet built on top of ILNP
-pub trait JCMP_Pck: Send + Sync {
-    fn into_bytes(&self) -> Vec<u8>;
-    fn get_packet_code(&self) -> u8;
-}
-
-/// JCMP Basic Packet
-/// This is the JCMP header, packet code is required in the JCMP protocol
+/// The first byte is the packet code, followed by the body of the message
 ///     - ND Solicitation (0x00)
-///     - ND Advertisement (0x01)
+///     - ND Advertisement (0x01)           destination_port: u16
 ///     - ND Router Solicitation (0x02)
-///     - ND Router Advertisement (0x03)
-///     - DNS IlV Query (0x06)
-#[bitfield]
-#[derive(Debug, Clone, Copy)]
-pub struct JCMP_Basic_Pck {
-    pub packet_code: B8
-}
-impl JCMP_Pck for JCMP_Basic_Pck {
-    fn into_bytes(&self) -> Vec<u8> {
-        self.bytes.to_vec()
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.packet_code()
-    }
+///     - ND Router Advertisement (0x03)    lifetime: u16, locators: u64 each (the router's NID is the source NID)
+///     - DNS FQDN Query (0x04)             fqdn: utf8
+///     - DNS FQDN Response (0x05)          ttl: u8, fqdn: utf8
+///     - DNS ILV Query (0x06)
+///     - DNS ILV Response (0x07)           ttl: u8
+///     - RREQ Router Request (0x08)        hop_count: u8, destination_locator: u64
+///     - RRES Router Response (0x09)       hop_count: u8, destination_locator: u64, ttl: u8
+///     - DAD Duplicate NID Probe (0x0A)    nonce: u32
+///     - DAD Duplicate NID Defend (0x0B)   nonce: u32
+///     - Destination Unreachable (0x0C)    destination_locator: u64, destination_nid: u64 (of the dropped packet)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JcmpMessage {
+    NeighbourSolicitation,
+    NeighbourAdvertisement { destination_port: u16 },
+    RouterSolicitation,
+    RouterAdvertisement { lifetime: u16, locators: Vec<u64> },
+    DnsFqdnQuery { fqdn: String },
+    DnsFqdnResponse { ttl: u8, fqdn: String },
+    DnsIlvQuery,
+    DnsIlvResponse { ttl: u8 },
+    RouterRequest { hop_count: u8, destination_locator: u64 },
+    RouterResponse { hop_count: u8, destination_locator: u64, ttl: u8 },
+    DuplicateNidProbe { nonce: u32 },
+    DuplicateNidDefend { nonce: u32 },
+    DestinationUnreachable { destination_locator: u64, destination_nid: u64 }
 }
 
-#[derive(Debug)]
-pub struct JCMP_ND_Advertisement {
-    pub header: JCMP_Basic_Pck,
-    pub destination_port: u16
+/// JCMP decoding errors
+///     - UnknownCode is a valid packet we don't support, everything else is malformed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Empty,
+    UnknownCode(u8),
+    TooShort { code: u8, expected: usize, actual: usize },
+    TrailingBytes { code: u8, expected: usize, actual: usize },
+    InvalidFqdn { code: u8 }
 }
-impl JCMP_ND_Advertisement {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
-        let header_size = std::mem::size_of::<JCMP_Basic_Pck>();
-        if bytes.len() < header_size + 2 {
-            return Err("JCMP_ND_Neighbour_Advertisement::from_bytes(): header too small".to_string());
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "missing packet code"),
+            DecodeError::UnknownCode(code) => write!(f, "packet code {} not supported", code),
+            DecodeError::TooShort { code, expected, actual } => write!(f, "packet code {}: expected at least {} bytes, got {}", code, expected, actual),
+            DecodeError::TrailingBytes { code, expected, actual } => write!(f, "packet code {}: expected {} bytes, got {}", code, expected, actual),
+            DecodeError::InvalidFqdn { code } => write!(f, "packet code {}: fqdn is not valid utf8", code)
         }
-        let header_array: [u8; 1] = match bytes[..header_size].try_into() {
-            Ok(header_array) => {
-                header_array
-            },
-            Err(err) => {
-                return Err(format!("JCMP_ND_Neighbour_Advertisement::from_bytes(): header converting issue: {}", err));
-            }
-        };
-
-        let header = JCMP_Basic_Pck::from_bytes(header_array);
-        let destination_port = u16::from_be_bytes(bytes[header_size..header_size + 2].try_into().unwrap());
-        Ok(JCMP_ND_Advertisement {
-            header,
-            destination_port,
-        })
-    }
-}
-impl JCMP_Pck for JCMP_ND_Advertisement {
-    fn into_bytes(&self) -> Vec<u8> {
-        let header_bytes = self.header.into_bytes();
-        let destination_port_bytes = self.destination_port.to_be_bytes();
-        let mut bytes = Vec::with_capacity(header_bytes.len() + 2);
-        bytes.extend_from_slice(&header_bytes);
-        bytes.extend_from_slice(&destination_port_bytes);
-        bytes
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.header.packet_code()
     }
 }
 
-/// JCMP DNS FQDN Query Packet
-/// This packet is used to query the fake DNS by FQDN
-///     - DNS FQDN Query (0x04)
-#[derive(Debug)]
-pub struct JCMP_DNS_FQDN_Query_Packet {
-    pub header: JCMP_Basic_Pck,
-    pub fqdn: Vec<u8>
-}
-impl JCMP_DNS_FQDN_Query_Packet {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {   
-        let header_size = std::mem::size_of::<JCMP_Basic_Pck>();
-        let header_bytes = &bytes[..header_size];
-        match header_bytes.try_into() as Result<[u8; 1], _> {
-            Ok(header_array) => {
-                let header = JCMP_Basic_Pck::from_bytes(header_array);
-                let fqdn = bytes[header_size..].to_vec();
-                Ok(JCMP_DNS_FQDN_Query_Packet { header, fqdn })
-            },
-            Err(err) => {
-                Err(format!("JCMPDNSQueryPacket::from_bytes(): {}", err))
-            }
-        }
-    }
-}
-impl JCMP_Pck for JCMP_DNS_FQDN_Query_Packet {
-    fn into_bytes(&self) -> Vec<u8> {
-        let header_bytes = self.header.into_bytes();
-        let mut bytes = Vec::with_capacity(header_bytes.len() + self.fqdn.len());
-        bytes.extend_from_slice(&header_bytes);
-        bytes.extend_from_slice(&self.fqdn);
-        bytes
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.header.packet_code()
-    }
-}
+impl JcmpMessage {
 
-/// JCMP DNS FQDN Response Packet
-/// This packet is used to respond to the fake DNS by FQDN
-///     - DNS FQDN Response (0x05)
-#[derive(Debug)]
-pub struct JCMP_DNS_FQDN_Response_Packet {
-    pub header: JCMP_Basic_Pck,
-    pub ttl: u8,
-    pub fqdn: Vec<u8>
-}
-impl JCMP_DNS_FQDN_Response_Packet {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {   
-        let header_size = std::mem::size_of::<JCMP_Basic_Pck>();
-        if bytes.len() < header_size + 1 {
-            return Err("JCMPDNSResponsePacket::from_bytes(): header too small".to_string());
+    /// Packet code carried in the first byte
+    pub fn code(&self) -> u8 {
+        match self {
+            JcmpMessage::NeighbourSolicitation => 0,
+            JcmpMessage::NeighbourAdvertisement { .. } => 1,
+            JcmpMessage::RouterSolicitation => 2,
+            JcmpMessage::RouterAdvertisement { .. } => 3,
+            JcmpMessage::DnsFqdnQuery { .. } => 4,
+            JcmpMessage::DnsFqdnResponse { .. } => 5,
+            JcmpMessage::DnsIlvQuery => 6,
+            JcmpMessage::DnsIlvResponse { .. } => 7,
+            JcmpMessage::RouterRequest { .. } => 8,
+            JcmpMessage::RouterResponse { .. } => 9,
+            JcmpMessage::DuplicateNidProbe { .. } => 10,
+            JcmpMessage::DuplicateNidDefend { .. } => 11,
+            JcmpMessage::DestinationUnreachable { .. } => 12
         }
+    }
 
-        let header_array: [u8; 1] = match bytes[..header_size].try_into() {
-            Ok(header_array) => {
-                header_array
-            },
-            Err(err) => {
-                return Err(format!("JCMPDNSResponsePacket: header converting issue: {}", err));
+    /// Decode a JCMP payload
+    ///     - fixed size messages must be exactly their size
+    ///     - FQDN messages take the rest of the payload
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (code, body) = match bytes.split_first() {
+            Some((code, body)) => (*code, body),
+            None => {
+                return Err(DecodeError::Empty);
             }
         };
 
-        let header = JCMP_Basic_Pck::from_bytes(header_array);
-        let ttl = bytes[header_size];
-        let fqdn = bytes[(header_size + 1)..].to_vec();
-        Ok(JCMP_DNS_FQDN_Response_Packet { header, ttl, fqdn })
-
-    }
-}
-impl JCMP_Pck for JCMP_DNS_FQDN_Response_Packet {
-    fn into_bytes(&self) -> Vec<u8> {
-        let header_bytes = self.header.into_bytes();
-        let mut bytes = Vec::with_capacity(header_bytes.len() + 1 + self.fqdn.len());
-        bytes.extend_from_slice(&header_bytes);
-        bytes.push(self.ttl);
-        bytes.extend_from_slice(&self.fqdn);
-        bytes
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.header.packet_code()
-    }
-}
-
-
-/// JCMP DNS ILV Response Packet
-/// This packet is used to respond to the fake DNS by ILV
-///     - DNS ILV Response (0x07)
-#[bitfield]
-#[derive(Debug, Clone, Copy)]
-pub struct JCMP_DNS_ILV_Response_Packet {
-    pub packet_code: B8,
-    pub ttl: B8
-}
-impl JCMP_Pck for JCMP_DNS_ILV_Response_Packet {
-    fn into_bytes(&self) -> Vec<u8> {
-        self.bytes.to_vec()
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.packet_code()
-    }
-}
+        // check the body is exactly the size of the message
+        let exact = |size: usize| -> Result<&[u8], DecodeError> {
+            if bytes.len() < size + 1 {
+                Err(DecodeError::TooShort { code, expected: size + 1, actual: bytes.len() })
+            } else if bytes.len() > size + 1 {
+                Err(DecodeError::TrailingBytes { code, expected: size + 1, actual: bytes.len() })
+            } else {
+                Ok(body)
+            }
+        };
+        let fqdn = |fqdn: &[u8]| -> Result<String, DecodeError> {
+            match String::from_utf8(fqdn.to_vec()) {
+                Ok(fqdn) => Ok(fqdn),
+                Err(_) => Err(DecodeError::InvalidFqdn { code })
+            }
+        };
 
-/// JCMP RREQ Router Request Packet
-/// This packet is used to request backwards learning across routers
-///     - RREQ Request (0x08)
-#[bitfield]
-#[derive(Debug, Clone, Copy)]
-pub struct JCMP_Router_Request {
-    pub packet_code: B8,
-    pub hop_count: B8,
-    pub destination_locator: B64
-}
-impl JCMP_Pck for JCMP_Router_Request {
-    fn into_bytes(&self) -> Vec<u8> {
-        self.bytes.to_vec()
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.packet_code()
+        match code {
+            0 => {
+                exact(0)?;
+                Ok(JcmpMessage::NeighbourSolicitation)
+            },
+            1 => {
+                let body = exact(2)?;
+                Ok(JcmpMessage::NeighbourAdvertisement { destination_port: u16::from_be_bytes([body[0], body[1]]) })
+            },
+            2 => {
+                exact(0)?;
+                Ok(JcmpMessage::RouterSolicitation)
+            },
+            3 => {
+                if body.len() < 2 {
+                    return Err(DecodeError::TooShort { code, expected: 3, actual: bytes.len() });
+                }
+                let (lifetime, locators) = body.split_at(2);
+                if locators.len() % 8 != 0 {
+                    return Err(DecodeError::TrailingBytes { code, expected: bytes.len() - locators.len() % 8, actual: bytes.len() });
+                }
+                Ok(JcmpMessage::RouterAdvertisement {
+                    lifetime: u16::from_be_bytes([lifetime[0], lifetime[1]]),
+                    locators: locators.chunks_exact(8).map(|locator| u64::from_be_bytes([locator[0], locator[1], locator[2], locator[3], locator[4], locator[5], locator[6], locator[7]])).collect()
+                })
+            },
+            4 => {
+                Ok(JcmpMessage::DnsFqdnQuery { fqdn: fqdn(body)? })
+            },
+            5 => {
+                match body.split_first() {
+                    Some((ttl, name)) => Ok(JcmpMessage::DnsFqdnResponse { ttl: *ttl, fqdn: fqdn(name)? }),
+                    None => Err(DecodeError::TooShort { code, expected: 2, actual: bytes.len() })
+                }
+            },
+            6 => {
+                exact(0)?;
+                Ok(JcmpMessage::DnsIlvQuery)
+            },
+            7 => {
+                let body = exact(1)?;
+                Ok(JcmpMessage::DnsIlvResponse { ttl: body[0] })
+            },
+            8 => {
+                let body = exact(9)?;
+                Ok(JcmpMessage::RouterRequest {
+                    hop_count: body[0],
+                    destination_locator: u64::from_be_bytes([body[1], body[2], body[3], body[4], body[5], body[6], body[7], body[8]])
+                })
+            },
+            9 => {
+                let body = exact(10)?;
+                Ok(JcmpMessage::RouterResponse {
+                    hop_count: body[0],
+                    destination_locator: u64::from_be_bytes([body[1], body[2], body[3], body[4], body[5], body[6], body[7], body[8]]),
+                    ttl: body[9]
+                })
+            },
+            10 => {
+                let body = exact(4)?;
+                Ok(JcmpMessage::DuplicateNidProbe { nonce: u32::from_be_bytes([body[0], body[1], body[2], body[3]]) })
+            },
+            11 => {
+                let body = exact(4)?;
+                Ok(JcmpMessage::DuplicateNidDefend { nonce: u32::from_be_bytes([body[0], body[1], body[2], body[3]]) })
+            },
+            12 => {
+                let body = exact(16)?;
+                Ok(JcmpMessage::DestinationUnreachable {
+                    destination_locator: u64::from_be_bytes([body[0], body[1], body[2], body[3], body[4], body[5], body[6], body[7]]),
+                    destination_nid: u64::from_be_bytes([body[8], body[9], body[10], body[11], body[12], body[13], body[14], body[15]])
+                })
+            },
+            code => Err(DecodeError::UnknownCode(code))
+        }
     }
-}
 
-/// JCMP RRES Router Response Packet
-/// This packet is used to respond to RREQ
-///     - RREQ Response (0x09)
-#[bitfield]
-#[derive(Debug, Clone, Copy)]
-pub struct JCMP_Router_Response {
-    pub packet_code: B8,
-    pub hop_count: B8,
-    pub destination_locator: B64,
-    pub ttl: B8
-}
-impl JCMP_Pck for JCMP_Router_Response {
-    fn into_bytes(&self) -> Vec<u8> {
-        self.bytes.to_vec()
-    }
-    fn get_packet_code(&self) -> u8 {
-        self.packet_code()
+    /// Encode into a JCMP payload
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.code()];
+        match self {
+            JcmpMessage::NeighbourSolicitation
+            | JcmpMessage::RouterSolicitation
+            | JcmpMessage::DnsIlvQuery => {},
+            JcmpMessage::RouterAdvertisement { lifetime, locators } => {
+                bytes.extend_from_slice(&lifetime.to_be_bytes());
+                for locator in locators {
+                    bytes.extend_from_slice(&locator.to_be_bytes());
+                }
+            },
+            JcmpMessage::NeighbourAdvertisement { destination_port } => {
+                bytes.extend_from_slice(&destination_port.to_be_bytes());
+            },
+            JcmpMessage::DnsFqdnQuery { fqdn } => {
+                bytes.extend_from_slice(fqdn.as_bytes());
+            },
+            JcmpMessage::DnsFqdnResponse { ttl, fqdn } => {
+                bytes.push(*ttl);
+                bytes.extend_from_slice(fqdn.as_bytes());
+            },
+            JcmpMessage::DnsIlvResponse { ttl } => {
+                bytes.push(*ttl);
+            },
+            JcmpMessage::RouterRequest { hop_count, destination_locator } => {
+                bytes.push(*hop_count);
+                bytes.extend_from_slice(&destination_locator.to_be_bytes());
+            },
+            JcmpMessage::RouterResponse { hop_count, destination_locator, ttl } => {
+                bytes.push(*hop_count);
+                bytes.extend_from_slice(&destination_locator.to_be_bytes());
+                bytes.push(*ttl);
+            },
+            JcmpMessage::DuplicateNidProbe { nonce }
+            | JcmpMessage::DuplicateNidDefend { nonce } => {
+                bytes.extend_from_slice(&nonce.to_be_bytes());
+            },
+            JcmpMessage::DestinationUnreachable { destination_locator, destination_nid } => {
+                bytes.extend_from_slice(&destination_locator.to_be_bytes());
+                bytes.extend_from_slice(&destination_nid.to_be_bytes());
+            }
+        }
+        bytes
     }
 }
 
-/*******************************************/
\ No newline at end of file
+/*******************************************/
@@ -1,9 +1,8 @@
 #![allow(non_camel_case_types)]
 
-use serde::Serialize;
-use serde_json;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct ILNP_PCB_S {
     
     // start / end
@@ -16,12 +15,23 @@ pub struct ILNP_PCB_S {
     pub data_request_tx: u64,
     pub data_request_forward_rx: u64,
     pub data_request_forward_tx: u64,
+    pub data_request_held: u64,
+    pub ilnp_queue_backpressure: u64,
 
     // jcmp neighbour discovery
     pub nd_solicitation_jcmp_rx: u64,
     pub nd_solicitation_jcmp_tx: u64,
     pub nd_advertisement_jcmp_rx: u64,
     pub nd_advertisement_jcmp_tx: u64,
+    pub nd_unsolicited_advertisement_jcmp_tx: u64,
+    pub nd_probe_jcmp_tx: u64,
+    pub nd_unreachable: u64,
+
+    // jcmp router discovery
+    pub router_solicitation_jcmp_rx: u64,
+    pub router_solicitation_jcmp_tx: u64,
+    pub router_advertisement_jcmp_rx: u64,
+    pub router_advertisement_jcmp_tx: u64,
 
     // jcmp dns fqdn lookup
     pub dns_fqdn_query_jcmp_rx: u64,
@@ -39,51 +49,195 @@ pub struct ILNP_PCB_S {
     pub router_request_jcmp_rx: u64,
     pub router_request_jcmp_tx: u64,
     pub router_response_jcmp_rx: u64,
-    pub router_response_jcmp_tx: u64
+    pub router_response_jcmp_tx: u64,
+
+    // jcmp duplicate nid detection
+    pub duplicate_nid_probe_jcmp_rx: u64,
+    pub duplicate_nid_probe_jcmp_tx: u64,
+    pub duplicate_nid_defend_jcmp_rx: u64,
+    pub duplicate_nid_defend_jcmp_tx: u64,
+    pub duplicate_nid_detected: u64,
+
+    // jcmp destination unreachable
+    pub destination_unreachable_jcmp_rx: u64,
+    pub destination_unreachable_jcmp_tx: u64,
+
+    // dropped packets
+    pub drops: ILNP_DROPS_S,
+
+    // header validation failures
+    pub validation: ILNP_VALIDATION_S
 
 }
 
-impl ILNP_PCB_S {
-    pub fn default() 
-        -> Self 
+
+/// Drop Reason
+///     - every place a received packet is discarded has a reason
+///     - each reason maps to a counter in ILNP_DROPS_S
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    Ipv4Source,
+    MulticastTooShort,
+    MulticastNotJcmp,
+    MulticastForeignLocator,
+    JcmpMissingCode,
+    JcmpMalformed,
+    JcmpUnsupported,
+    UnicastTooShort,
+    HeaderMalformed,
+    UnicastWrongHeader,
+    UnicastNotForUs,
+    IlnpQueueFailed,
+    JtpQueueFull,
+    ForwardFailed,
+    LengthMismatch,
+    TrailingBytes,
+    WrongVersion,
+    InvalidSource,
+    WireMalformed,
+    ChecksumMismatch,
+    NonceMissing,
+    NonceMismatch,
+    NonceUnverified,
+    JcmpAuthMissing,
+    JcmpAuthInvalid,
+    JcmpReplay,
+    JtpNotEncrypted,
+    JtpNoSession,
+    JtpDecryptFailed,
+    JtpReplay,
+    JtpHandshakeInvalid,
+    JcmpHandlerLimit,
+    JcmpRateLimited,
+    HoldQueueFull,
+    HoldExpired,
+    IlnpQueueFull,
+    HopLimitExceeded
+}
+
+/// Dropped packet counters
+///     - one counter per DropReason
+///     - serialised with the PCB so the logs explain where packets went
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ILNP_DROPS_S {
+    pub ipv4_source: u64,
+    pub multicast_too_short: u64,
+    pub multicast_not_jcmp: u64,
+    pub multicast_foreign_locator: u64,
+    pub jcmp_missing_code: u64,
+    pub jcmp_malformed: u64,
+    pub jcmp_unsupported: u64,
+    pub unicast_too_short: u64,
+    pub header_malformed: u64,
+    pub unicast_wrong_header: u64,
+    pub unicast_not_for_us: u64,
+    pub ilnp_queue_failed: u64,
+    pub jtp_queue_full: u64,
+    pub forward_failed: u64,
+    pub length_mismatch: u64,
+    pub trailing_bytes: u64,
+    pub wrong_version: u64,
+    pub invalid_source: u64,
+    pub wire_malformed: u64,
+    pub checksum_mismatch: u64,
+    pub nonce_missing: u64,
+    pub nonce_mismatch: u64,
+    pub nonce_unverified: u64,
+    pub jcmp_auth_missing: u64,
+    pub jcmp_auth_invalid: u64,
+    pub jcmp_replay: u64,
+    pub jtp_not_encrypted: u64,
+    pub jtp_no_session: u64,
+    pub jtp_decrypt_failed: u64,
+    pub jtp_replay: u64,
+    pub jtp_handshake_invalid: u64,
+    pub jcmp_handler_limit: u64,
+    pub jcmp_rate_limited: u64,
+    pub hold_queue_full: u64,
+    pub hold_expired: u64,
+    pub ilnp_queue_full: u64,
+    pub hop_limit_exceeded: u64
+}
+
+impl ILNP_DROPS_S {
+    pub fn count(&mut self, reason: DropReason)
     {
-        Self {
-            start_time: 0,
-            ready_time: 0,
-            finish_time: 0,
-            data_request_rx: 0,
-            data_request_tx: 0,
-            data_request_forward_rx: 0,
-            data_request_forward_tx: 0,
-            nd_solicitation_jcmp_rx: 0,
-            nd_solicitation_jcmp_tx: 0,
-            nd_advertisement_jcmp_rx: 0,
-            nd_advertisement_jcmp_tx: 0,
-            dns_fqdn_query_jcmp_rx: 0,
-            dns_fqdn_query_jcmp_tx: 0,
-            dns_fqdn_response_jcmp_rx: 0,
-            dns_fqdn_response_jcmp_tx: 0,
-            dns_ilv_query_jcmp_rx: 0,
-            dns_ilv_query_jcmp_tx: 0,
-            dns_ilv_response_jcmp_rx: 0,
-            dns_ilv_response_jcmp_tx: 0,
-            router_request_jcmp_rx: 0,
-            router_request_jcmp_tx: 0,
-            router_response_jcmp_rx: 0,
-            router_response_jcmp_tx: 0
+        match reason {
+            DropReason::Ipv4Source => self.ipv4_source += 1,
+            DropReason::MulticastTooShort => self.multicast_too_short += 1,
+            DropReason::MulticastNotJcmp => self.multicast_not_jcmp += 1,
+            DropReason::MulticastForeignLocator => self.multicast_foreign_locator += 1,
+            DropReason::JcmpMissingCode => self.jcmp_missing_code += 1,
+            DropReason::JcmpMalformed => self.jcmp_malformed += 1,
+            DropReason::JcmpUnsupported => self.jcmp_unsupported += 1,
+            DropReason::UnicastTooShort => self.unicast_too_short += 1,
+            DropReason::HeaderMalformed => self.header_malformed += 1,
+            DropReason::UnicastWrongHeader => self.unicast_wrong_header += 1,
+            DropReason::UnicastNotForUs => self.unicast_not_for_us += 1,
+            DropReason::IlnpQueueFailed => self.ilnp_queue_failed += 1,
+            DropReason::JtpQueueFull => self.jtp_queue_full += 1,
+            DropReason::ForwardFailed => self.forward_failed += 1,
+            DropReason::LengthMismatch => self.length_mismatch += 1,
+            DropReason::TrailingBytes => self.trailing_bytes += 1,
+            DropReason::WrongVersion => self.wrong_version += 1,
+            DropReason::InvalidSource => self.invalid_source += 1,
+            DropReason::WireMalformed => self.wire_malformed += 1,
+            DropReason::ChecksumMismatch => self.checksum_mismatch += 1,
+            DropReason::NonceMissing => self.nonce_missing += 1,
+            DropReason::NonceMismatch => self.nonce_mismatch += 1,
+            DropReason::NonceUnverified => self.nonce_unverified += 1,
+            DropReason::JcmpAuthMissing => self.jcmp_auth_missing += 1,
+            DropReason::JcmpAuthInvalid => self.jcmp_auth_invalid += 1,
+            DropReason::JcmpReplay => self.jcmp_replay += 1,
+            DropReason::JtpNotEncrypted => self.jtp_not_encrypted += 1,
+            DropReason::JtpNoSession => self.jtp_no_session += 1,
+            DropReason::JtpDecryptFailed => self.jtp_decrypt_failed += 1,
+            DropReason::JtpReplay => self.jtp_replay += 1,
+            DropReason::JtpHandshakeInvalid => self.jtp_handshake_invalid += 1,
+            DropReason::JcmpHandlerLimit => self.jcmp_handler_limit += 1,
+            DropReason::JcmpRateLimited => self.jcmp_rate_limited += 1,
+            DropReason::HoldQueueFull => self.hold_queue_full += 1,
+            DropReason::HoldExpired => self.hold_expired += 1,
+            DropReason::IlnpQueueFull => self.ilnp_queue_full += 1,
+            DropReason::HopLimitExceeded => self.hop_limit_exceeded += 1
         }
     }
+}
+
+
+/// Header Check
+///     - checks applied to every received ILNP header
+///     - the action for each is set in the [validation] config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderCheck {
+    LengthMismatch,
+    TrailingBytes,
+    WrongVersion,
+    UnknownNextHeader,
+    InvalidSource
+}
+
+/// Header validation counters
+///     - every failed check is counted here whatever the configured action
+///     - packets actually discarded are also counted in ILNP_DROPS_S
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ILNP_VALIDATION_S {
+    pub length_mismatch: u64,
+    pub trailing_bytes: u64,
+    pub wrong_version: u64,
+    pub unknown_next_header: u64,
+    pub invalid_source: u64
+}
 
-    pub fn to_json_string(&self) 
-        ->  Result<String, String> 
+impl ILNP_VALIDATION_S {
+    pub fn count(&mut self, check: HeaderCheck)
     {
-        match serde_json::to_string(self) {
-            Ok(json_string)  => {
-                Ok(json_string)
-            },
-            Err(err) => {
-                Err(format!("ILNP_PCB_S::to_json_string(): failed to serialise PCB: {}", err))
-            }
+        match check {
+            HeaderCheck::LengthMismatch => self.length_mismatch += 1,
+            HeaderCheck::TrailingBytes => self.trailing_bytes += 1,
+            HeaderCheck::WrongVersion => self.wrong_version += 1,
+            HeaderCheck::UnknownNextHeader => self.unknown_next_header += 1,
+            HeaderCheck::InvalidSource => self.invalid_source += 1
         }
     }
-}
\ No newline at end of file
+}
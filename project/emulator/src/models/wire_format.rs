@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use super::config_models::WireFormat;
+use super::network_packets::INLPv6Packet;
+
+/*******************************************/
+/// Native next header values
+pub const NEXT_HEADER_JCMP: u8 = 150;
+pub const NEXT_HEADER_JTP: u8 = 151;
+
+/// RFC 6741 next header values
+///     - destination options (60) carries the ILNP Nonce option
+///     - ICMPv6 (58) carries JCMP
+///     - experimental (253, RFC 3692) carries JTP
+pub const NEXT_HEADER_DESTINATION_OPTIONS: u8 = 60;
+pub const NEXT_HEADER_ICMPV6: u8 = 58;
+pub const NEXT_HEADER_EXPERIMENTAL: u8 = 253;
+
+/// ICMPv6 type used for JCMP
+///     - private experimentation (RFC 4443), the ICMPv6 code is the JCMP code
+pub const ICMPV6_TYPE_JCMP: u8 = 200;
+
+/// Destination options
+///     - Nonce (RFC 6744) is 0x8B, skipped by nodes that don't know it
+pub const OPTION_PAD1: u8 = 0x00;
+pub const OPTION_PADN: u8 = 0x01;
+pub const OPTION_NONCE: u8 = 0x8B;
+
+const HEADER_SIZE: usize = 40;
+const PAYLOAD_LENGTH_OFFSET: usize = 4;
+const HOP_LIMIT_OFFSET: usize = 7;
+const ICMPV6_HEADER_SIZE: usize = 4;
+/*******************************************/
+
+
+/// Wire format decoding errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    TooShort { expected: usize, actual: usize },
+    NonceLength(u8),
+    UnknownOption(u8),
+    UnknownIcmpType(u8),
+    ChecksumMismatch { expected: u16, actual: u16 }
+}
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::TooShort { expected, actual } => write!(f, "expected at least {} bytes, got {}", expected, actual),
+            WireError::NonceLength(length) => write!(f, "unsupported nonce length {}", length),
+            WireError::UnknownOption(option) => write!(f, "unknown destination option 0x{:02X}", option),
+            WireError::UnknownIcmpType(icmp_type) => write!(f, "unknown icmpv6 type {}", icmp_type),
+            WireError::ChecksumMismatch { expected, actual } => write!(f, "checksum 0x{:04X} should be 0x{:04X}", actual, expected)
+        }
+    }
+}
+
+/// A received packet in the native layout
+///     - header has next header 150/151 and payload_length of the native payload
+///     - nonce is set if the packet carried the Nonce option
+pub struct WirePacket<'a> {
+    pub header: INLPv6Packet,
+    pub nonce: Option<u32>,
+    pub payload: Cow<'a, [u8]>
+}
+
+
+/// Encode a packet for the wire
+///     - header and payload are in the native layout (next header 150/151)
+///     - rfc6741 adds the Nonce option when given one and carries JCMP as ICMPv6
+pub fn encode_packet(format: WireFormat, header: INLPv6Packet, nonce: Option<u32>, payload: &[u8])
+    -> Vec<u8>
+{
+    if format == WireFormat::Native {
+        let mut pck = header.into_bytes().to_vec();
+        pck.extend_from_slice(payload);
+        return pck;
+    }
+
+    // upper layer header
+    let (upper_next_header, upper) = match header.next_header() {
+        NEXT_HEADER_JCMP => {
+            let mut icmp = Vec::with_capacity(payload.len() + ICMPV6_HEADER_SIZE - 1);
+            icmp.push(ICMPV6_TYPE_JCMP);
+            icmp.push(payload.first().copied().unwrap_or(0));
+            icmp.extend_from_slice(&[0, 0]);
+            icmp.extend_from_slice(payload.get(1..).unwrap_or(&[]));
+
+            let checksum = upper_layer_checksum(&header, NEXT_HEADER_ICMPV6, &icmp);
+            icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+            (NEXT_HEADER_ICMPV6, Cow::Owned(icmp))
+        },
+        NEXT_HEADER_JTP => (NEXT_HEADER_EXPERIMENTAL, Cow::Borrowed(payload)),
+        next_header => (next_header, Cow::Borrowed(payload))
+    };
+
+    // destination options with the nonce, 8 bytes so no padding
+    let mut extension = Vec::new();
+    let next_header = match nonce {
+        Some(nonce) => {
+            extension.push(upper_next_header);
+            extension.push(0);
+            extension.push(OPTION_NONCE);
+            extension.push(4);
+            extension.extend_from_slice(&nonce.to_be_bytes());
+            NEXT_HEADER_DESTINATION_OPTIONS
+        },
+        None => upper_next_header
+    };
+
+    let header = header
+        .with_next_header(next_header)
+        .with_payload_length((extension.len() + upper.len()) as u16);
+
+    let mut pck = Vec::with_capacity(HEADER_SIZE + extension.len() + upper.len());
+    pck.extend_from_slice(&header.into_bytes());
+    pck.extend_from_slice(&extension);
+    pck.extend_from_slice(&upper);
+    pck
+}
+
+/// Rewrite the hop limit of an encoded packet in place
+///     - both formats start with the ILNPv6 header, nothing else has to change
+///     - the rfc6741 checksum pseudo-header doesn't cover the hop limit
+pub fn rewrite_hop_limit(pck: &mut [u8], hop_limit: u8)
+{
+    if let Some(byte) = pck.get_mut(HOP_LIMIT_OFFSET) {
+        *byte = hop_limit;
+    }
+}
+
+/// Set the payload length of an encoded packet
+///     - everything after the ILNPv6 header, extension headers included
+///     - lets a header encoded once with an empty payload (e.g. a JTP flow) be reused for any payload
+pub fn set_payload_length(pck: &mut [u8])
+{
+    if let Some(length) = pck.len().checked_sub(HEADER_SIZE) {
+        pck[PAYLOAD_LENGTH_OFFSET..PAYLOAD_LENGTH_OFFSET + 2].copy_from_slice(&(length as u16).to_be_bytes());
+    }
+}
+
+/// Decode a packet from the wire
+///     - native packets are returned as they are
+///     - rfc6741 packets have the destination options and ICMPv6 header removed, the ICMPv6 checksum is checked
+///     - bytes past payload_length are kept in the payload so header validation still sees them
+pub fn decode_packet(buf: &[u8])
+    -> Result<WirePacket<'_>, WireError>
+{
+    let header_bytes: [u8; HEADER_SIZE] = match buf.get(..HEADER_SIZE).and_then(|bytes| bytes.try_into().ok()) {
+        Some(header_bytes) => header_bytes,
+        None => {
+            return Err(WireError::TooShort { expected: HEADER_SIZE, actual: buf.len() });
+        }
+    };
+    let header = INLPv6Packet::from_bytes(header_bytes);
+    let declared_length = header.payload_length() as usize;
+    let mut rest = &buf[HEADER_SIZE..];
+    let mut next_header = header.next_header();
+    let mut overhead = 0;
+    let mut nonce = None;
+
+    // destination options
+    if next_header == NEXT_HEADER_DESTINATION_OPTIONS {
+        if rest.len() < 2 {
+            return Err(WireError::TooShort { expected: 2, actual: rest.len() });
+        }
+        let extension_length = (rest[1] as usize + 1) * 8;
+        if rest.len() < extension_length {
+            return Err(WireError::TooShort { expected: extension_length, actual: rest.len() });
+        }
+        nonce = decode_options(&rest[2..extension_length])?;
+        next_header = rest[0];
+        rest = &rest[extension_length..];
+        overhead += extension_length;
+    }
+
+    let (native_next_header, payload) = match next_header {
+        NEXT_HEADER_ICMPV6 => {
+            if rest.len() < ICMPV6_HEADER_SIZE {
+                return Err(WireError::TooShort { expected: ICMPV6_HEADER_SIZE, actual: rest.len() });
+            }
+            if rest[0] != ICMPV6_TYPE_JCMP {
+                return Err(WireError::UnknownIcmpType(rest[0]));
+            }
+
+            // checksum covers the upper layer as declared by payload_length
+            let upper_length = declared_length.saturating_sub(overhead).min(rest.len());
+            if upper_length < ICMPV6_HEADER_SIZE {
+                return Err(WireError::TooShort { expected: ICMPV6_HEADER_SIZE, actual: upper_length });
+            }
+            let upper = &rest[..upper_length];
+            let mut icmp = upper.to_vec();
+            let actual = u16::from_be_bytes([icmp[2], icmp[3]]);
+            icmp[2] = 0;
+            icmp[3] = 0;
+            let expected = upper_layer_checksum(&header, NEXT_HEADER_ICMPV6, &icmp);
+            if expected != actual {
+                return Err(WireError::ChecksumMismatch { expected, actual });
+            }
+
+            // JCMP is the code followed by the ICMPv6 body
+            let mut jcmp = Vec::with_capacity(upper_length - ICMPV6_HEADER_SIZE + 1);
+            jcmp.push(upper[1]);
+            jcmp.extend_from_slice(&upper[ICMPV6_HEADER_SIZE..]);
+            overhead += ICMPV6_HEADER_SIZE - 1;
+            (NEXT_HEADER_JCMP, Cow::Owned(jcmp))
+        },
+        NEXT_HEADER_EXPERIMENTAL => (NEXT_HEADER_JTP, Cow::Borrowed(rest)),
+        next_header => (next_header, Cow::Borrowed(rest))
+    };
+
+    let header = header
+        .with_next_header(native_next_header)
+        .with_payload_length(declared_length.saturating_sub(overhead) as u16);
+
+    Ok(WirePacket { header, nonce, payload })
+}
+
+/// Walk the destination options
+///     - unknown options are skipped when the action bits allow it (RFC 8200)
+fn decode_options(mut options: &[u8])
+    -> Result<Option<u32>, WireError>
+{
+    let mut nonce = None;
+    while let Some(&option) = options.first() {
+        if option == OPTION_PAD1 {
+            options = &options[1..];
+            continue;
+        }
+        if options.len() < 2 {
+            return Err(WireError::TooShort { expected: 2, actual: options.len() });
+        }
+        let length = options[1] as usize;
+        if options.len() < 2 + length {
+            return Err(WireError::TooShort { expected: 2 + length, actual: options.len() });
+        }
+        let value = &options[2..2 + length];
+
+        match option {
+            OPTION_PADN => {},
+            OPTION_NONCE => {
+                match value.try_into() {
+                    Ok(bytes) => nonce = Some(u32::from_be_bytes(bytes)),
+                    Err(_) => {
+                        return Err(WireError::NonceLength(length as u8));
+                    }
+                }
+            },
+            option if option >> 6 == 0 => {},
+            option => {
+                return Err(WireError::UnknownOption(option));
+            }
+        }
+        options = &options[2 + length..];
+    }
+    Ok(nonce)
+}
+
+/// Upper layer checksum (RFC 8200 pseudo-header)
+///     - locators are zeroed so the checksum only covers the identifiers (RFC 6741)
+pub fn upper_layer_checksum(header: &INLPv6Packet, next_header: u8, upper: &[u8])
+    -> u16
+{
+    let mut pseudo_header = [0u8; 40];
+    pseudo_header[8..16].copy_from_slice(&header.source_identifier().to_be_bytes());
+    pseudo_header[24..32].copy_from_slice(&header.destination_identifier().to_be_bytes());
+    pseudo_header[32..36].copy_from_slice(&(upper.len() as u32).to_be_bytes());
+    pseudo_header[39] = next_header;
+
+    let mut sum: u32 = 0;
+    for chunk in pseudo_header.chunks(2).chain(upper.chunks(2)) {
+        let word = match chunk {
+            [high, low] => u16::from_be_bytes([*high, *low]),
+            [high] => u16::from_be_bytes([*high, 0]),
+            _ => 0
+        };
+        sum += word as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
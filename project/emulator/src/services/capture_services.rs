@@ -0,0 +1,229 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use once_cell::sync::Lazy;
+
+use crate::layers::overlay_network::CONFIG;
+use crate::models::config_models::WireFormat;
+use crate::models::network_models::EmulatorLocalNetwork;
+
+use super::time_services::get_current_timestamp;
+
+/// Link type used for overlay packets
+///     - LINKTYPE_USER0, the frame starts directly with the ILNPv6 header
+pub const LINKTYPE_ILNP: u16 = 147;
+
+/// Link type used for rfc6741 overlay packets
+///     - the packets are valid IPv6 so Wireshark dissects them directly
+pub const LINKTYPE_IPV6: u16 = 229;
+
+/// pcapng block types
+pub const PCAPNG_SHB: u32 = 0x0A0D0D0A;
+pub const PCAPNG_IDB: u32 = 0x00000001;
+pub const PCAPNG_EPB: u32 = 0x00000006;
+pub const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+/// Direction of a captured packet
+///     - stored in the pcapng epb_flags option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Inbound,
+    Outbound
+}
+
+/// pcapng writer
+///     - one section and one interface per file
+///     - timestamps are in microseconds (default if_tsresol)
+pub struct PcapngWriter {
+    file: BufWriter<File>
+}
+impl PcapngWriter {
+
+    pub fn create(path: &str, interface_name: &str, link_type: u16)
+        -> Result<Self, String>
+    {
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                if let Err(err) = fs::create_dir_all(parent) {
+                    return Err(format!("PcapngWriter::create(): failed to create {:?}: {}", parent, err));
+                }
+            }
+        }
+
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(err) => {
+                return Err(format!("PcapngWriter::create(): failed to create {}: {}", path, err));
+            }
+        };
+        let mut writer = PcapngWriter { file: BufWriter::new(file) };
+
+        // section header block, section length unknown
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        push_option(&mut shb, 4, b"ilnp emulator");
+        push_option(&mut shb, 0, &[]);
+        writer.write_block(PCAPNG_SHB, &shb)?;
+
+        // interface description block
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&link_type.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut idb, 2, interface_name.as_bytes());
+        push_option(&mut idb, 0, &[]);
+        writer.write_block(PCAPNG_IDB, &idb)?;
+
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Write an enhanced packet block
+    pub fn write_packet(&mut self, direction: CaptureDirection, timestamp_us: u64, pck: &[u8])
+        -> Result<(), String>
+    {
+        let mut epb = Vec::with_capacity(pck.len() + 40);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((timestamp_us >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(timestamp_us as u32).to_le_bytes());
+        epb.extend_from_slice(&(pck.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(pck.len() as u32).to_le_bytes());
+        epb.extend_from_slice(pck);
+        pad_to_32(&mut epb);
+
+        // epb_flags, bits 0-1 are the direction (1 inbound, 2 outbound)
+        let flags: u32 = match direction {
+            CaptureDirection::Inbound => 1,
+            CaptureDirection::Outbound => 2
+        };
+        push_option(&mut epb, 2, &flags.to_le_bytes());
+        push_option(&mut epb, 0, &[]);
+
+        self.write_block(PCAPNG_EPB, &epb)
+    }
+
+    pub fn flush(&mut self)
+        -> Result<(), String>
+    {
+        match self.file.flush() {
+            Ok(()) => Ok(()),
+            Err(err) => Err(format!("PcapngWriter::flush(): {}", err))
+        }
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8])
+        -> Result<(), String>
+    {
+        let total_length = (12 + body.len()) as u32;
+        let mut block = Vec::with_capacity(total_length as usize);
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&total_length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&total_length.to_le_bytes());
+
+        match self.file.write_all(&block) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(format!("PcapngWriter::write_block(): {}", err))
+        }
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8])
+{
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad_to_32(buf);
+}
+
+fn pad_to_32(buf: &mut Vec<u8>)
+{
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+
+/// Capture file
+///     - open when capture.enabled is set
+///     - every overlay packet sent or received by the node is written to it
+static CAPTURE: Lazy<Mutex<Option<PcapngWriter>>> = Lazy::new(|| Mutex::new(None));
+
+/// Open the capture file
+///     - does nothing unless capture.enabled is set
+///     - path defaults to logs/<node name>.pcapng
+pub fn open_capture(local_network: &EmulatorLocalNetwork)
+    -> Result<(), String>
+{
+    if !CONFIG.capture.enabled {
+        return Ok(());
+    }
+
+    let path = match &CONFIG.capture.path {
+        Some(path) => path.clone(),
+        None => format!("logs/{}.pcapng", local_network.local_fqdn)
+    };
+
+    let link_type = match CONFIG.wire.format {
+        WireFormat::Native => LINKTYPE_ILNP,
+        WireFormat::Rfc6741 => LINKTYPE_IPV6
+    };
+    let writer = PcapngWriter::create(&path, &local_network.local_fqdn, link_type)?;
+    match CAPTURE.lock() {
+        Ok(mut capture) => {
+            *capture = Some(writer);
+            Ok(())
+        },
+        Err(err) => {
+            Err(format!("open_capture(): failed to lock CAPTURE: {}", err))
+        }
+    }
+}
+
+/// Capture an overlay packet
+///     - pck should start with the ILNPv6 header
+pub fn capture_packet(direction: CaptureDirection, pck: &[u8])
+{
+    if !CONFIG.capture.enabled {
+        return;
+    }
+
+    let timestamp_us = get_current_timestamp().unwrap_or(0);
+    if let Ok(mut capture) = CAPTURE.lock() {
+        if let Some(writer) = capture.as_mut() {
+            if let Err(err) = writer.write_packet(direction, timestamp_us, pck) {
+                eprintln!("SYSTEMLOG: capture_packet(): {}", err);
+            }
+        }
+    }
+}
+
+/// Flush the capture file
+///     - called every capture.flush_ms while the node runs
+pub fn flush_capture()
+{
+    if let Ok(mut capture) = CAPTURE.lock() {
+        if let Some(writer) = capture.as_mut() {
+            if let Err(err) = writer.flush() {
+                eprintln!("SYSTEMLOG: flush_capture(): {}", err);
+            }
+        }
+    }
+}
+
+/// Close the capture file
+///     - flush anything still buffered
+pub fn close_capture()
+{
+    if let Ok(mut capture) = CAPTURE.lock() {
+        if let Some(mut writer) = capture.take() {
+            if let Err(err) = writer.flush() {
+                eprintln!("SYSTEMLOG: close_capture(): {}", err);
+            }
+        }
+    }
+}
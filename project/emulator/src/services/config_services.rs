@@ -1,19 +1,31 @@
+use std::env;
 use std::fs;
 use std::process::Command;
 
 use crate::models::config_models::Config;
 
 /// Function to retrieve config from config.toml
+///     - EMULATOR_CONFIG overrides the path (used by tests and fuzz targets)
 pub fn get_config() 
     -> Result<Config, String> 
 {
+    let config_path = env::var("EMULATOR_CONFIG").unwrap_or("config/Config.toml".to_string());
+
     // open config file
-    match fs::read_to_string("config/Config.toml") {
+    match fs::read_to_string(&config_path) {
         Ok(config_content) => {
 
             // parse the config file
             match toml::from_str::<Config>(&config_content) {
-                Ok(config) => {
+                Ok(mut config) => {
+
+                    // NID left unset, the logger doesn't need one
+                    if config.node.nid == 0 && !config.app.logger {
+                        if !config.dad.auto_nid {
+                            return Err(format!("get_config(): {}: node.nid is not set and dad.auto_nid is disabled", config_path));
+                        }
+                        config.node.nid = generate_nid();
+                    }
 
                     // return config
                     Ok(config)
@@ -25,7 +37,21 @@ pub fn get_config()
             }
         }, 
         Err(err) => {
-            Err(format!("get_config(): {}", err))
+            Err(format!("get_config(): {}: {}", config_path, err))
+        }
+    }
+}
+
+
+/// Random NID
+///     - never unspecified (0), all ones or one of the DNS, all nodes and all routers placeholders
+fn generate_nid()
+    -> u64
+{
+    loop {
+        let nid: u64 = rand::random();
+        if nid != 0 && nid != u64::MAX && nid != 0x0000000053535353 && nid != 0x00000000ff02ff01 && nid != 0x00000000ff02ff02 {
+            return nid;
         }
     }
 }
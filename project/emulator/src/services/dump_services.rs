@@ -0,0 +1,372 @@
+
+use std::fs;
+
+use crate::models::jcmp_auth::JCMP_AUTH_TRAILER_SIZE;
+use crate::models::network_packets::{DecodeError, INLPv6Packet, JcmpMessage};
+use crate::models::wire_format::{decode_packet, NEXT_HEADER_DESTINATION_OPTIONS, NEXT_HEADER_EXPERIMENTAL, NEXT_HEADER_ICMPV6, NEXT_HEADER_JCMP, NEXT_HEADER_JTP};
+
+use super::capture_services::{CaptureDirection, LINKTYPE_ILNP, LINKTYPE_IPV6, PCAPNG_BYTE_ORDER_MAGIC, PCAPNG_EPB, PCAPNG_IDB, PCAPNG_SHB};
+
+/// Underlay link types the reader can strip down to the overlay packet
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+/// pcapng simple packet block
+const PCAPNG_SPB: u32 = 0x00000003;
+
+/// Captured packet
+///     - data starts with the ILNPv6 header
+///     - direction is only known for pcapng files written by a node
+#[derive(Debug, Clone)]
+pub struct CapturedPacket {
+    pub timestamp_us: u64,
+    pub direction: Option<CaptureDirection>,
+    pub data: Vec<u8>
+}
+
+
+/// Read a pcap or pcapng file
+///     - LINKTYPE_ILNP frames are returned as they are
+///     - Ethernet, raw IP and Linux cooked frames are stripped down to the UDP payload
+///     - frames that aren't IPv6/UDP are skipped
+pub fn read_capture(path: &str)
+    -> Result<Vec<CapturedPacket>, String>
+{
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return Err(format!("read_capture(): failed to read {}: {}", path, err));
+        }
+    };
+
+    if bytes.len() < 4 {
+        return Err("read_capture(): file too small".to_string());
+    }
+
+    let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    match magic {
+        PCAPNG_SHB => read_pcapng(&bytes),
+        0xA1B2C3D4 | 0xD4C3B2A1 | 0xA1B23C4D | 0x4D3CB2A1 => read_pcap(&bytes),
+        _ => Err(format!("read_capture(): unknown file format (magic 0x{:08X})", magic))
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u16>
+{
+    let slice: [u8; 2] = bytes.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian { u16::from_be_bytes(slice) } else { u16::from_le_bytes(slice) })
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u32>
+{
+    let slice: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian { u32::from_be_bytes(slice) } else { u32::from_le_bytes(slice) })
+}
+
+fn read_pcap(bytes: &[u8])
+    -> Result<Vec<CapturedPacket>, String>
+{
+    if bytes.len() < 24 {
+        return Err("read_pcap(): header too small".to_string());
+    }
+
+    // magic tells both the byte order and the timestamp resolution
+    let big_endian = bytes[0] == 0xA1;
+    let nanoseconds = matches!(read_u32(bytes, 0, big_endian), Some(0xA1B23C4D));
+    let link_type = read_u32(bytes, 20, big_endian).unwrap_or(0) as u16;
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset + 16 <= bytes.len() {
+        let seconds = read_u32(bytes, offset, big_endian).unwrap_or(0) as u64;
+        let fraction = read_u32(bytes, offset + 4, big_endian).unwrap_or(0) as u64;
+        let captured_length = read_u32(bytes, offset + 8, big_endian).unwrap_or(0) as usize;
+        offset += 16;
+
+        let frame = match bytes.get(offset..offset + captured_length) {
+            Some(frame) => frame,
+            None => {
+                return Err(format!("read_pcap(): truncated packet at offset {}", offset));
+            }
+        };
+        offset += captured_length;
+
+        let timestamp_us = seconds * 1_000_000 + if nanoseconds { fraction / 1000 } else { fraction };
+        if let Some(data) = strip_underlay(link_type, frame) {
+            packets.push(CapturedPacket { timestamp_us, direction: None, data: data.to_vec() });
+        }
+    }
+
+    Ok(packets)
+}
+
+fn read_pcapng(bytes: &[u8])
+    -> Result<Vec<CapturedPacket>, String>
+{
+    let mut packets = Vec::new();
+
+    // interfaces of the current section (link type, ticks per microsecond divisor)
+    let mut interfaces: Vec<(u16, u64, bool)> = Vec::new();
+    let mut big_endian = false;
+    let mut offset = 0;
+
+    while offset + 12 <= bytes.len() {
+
+        // byte order is only known once the section header is read
+        let block_type = read_u32(bytes, offset, false).unwrap_or(0);
+        if block_type == PCAPNG_SHB {
+            big_endian = read_u32(bytes, offset + 8, false) != Some(PCAPNG_BYTE_ORDER_MAGIC);
+            interfaces.clear();
+        }
+        let block_type = read_u32(bytes, offset, big_endian).unwrap_or(0);
+        let block_length = read_u32(bytes, offset + 4, big_endian).unwrap_or(0) as usize;
+        if block_length < 12 || offset + block_length > bytes.len() {
+            return Err(format!("read_pcapng(): bad block length at offset {}", offset));
+        }
+        let body = &bytes[offset + 8..offset + block_length - 4];
+
+        match block_type {
+            PCAPNG_IDB => {
+                let link_type = read_u16(body, 0, big_endian).unwrap_or(0);
+                let (divisor, multiply) = read_tsresol(body.get(8..).unwrap_or(&[]), big_endian);
+                interfaces.push((link_type, divisor, multiply));
+            },
+            PCAPNG_EPB => {
+                let interface_id = read_u32(body, 0, big_endian).unwrap_or(0) as usize;
+                let high = read_u32(body, 4, big_endian).unwrap_or(0) as u64;
+                let low = read_u32(body, 8, big_endian).unwrap_or(0) as u64;
+                let captured_length = read_u32(body, 12, big_endian).unwrap_or(0) as usize;
+                let frame = match body.get(20..20 + captured_length) {
+                    Some(frame) => frame,
+                    None => {
+                        return Err(format!("read_pcapng(): truncated packet at offset {}", offset));
+                    }
+                };
+
+                // epb_flags follow the padded packet data
+                let options_offset = 20 + captured_length.div_ceil(4) * 4;
+                let direction = read_epb_direction(body.get(options_offset..).unwrap_or(&[]), big_endian);
+
+                let (link_type, divisor, multiply) = interfaces.get(interface_id).copied().unwrap_or((LINKTYPE_ILNP, 1, false));
+                let ticks = (high << 32) | low;
+                let timestamp_us = if multiply {
+                    match ticks.checked_mul(divisor) {
+                        Some(timestamp_us) => timestamp_us,
+                        None => {
+                            return Err(format!("read_pcapng(): timestamp out of range at offset {}", offset));
+                        }
+                    }
+                } else {
+                    ticks / divisor
+                };
+
+                if let Some(data) = strip_underlay(link_type, frame) {
+                    packets.push(CapturedPacket { timestamp_us, direction, data: data.to_vec() });
+                }
+            },
+            PCAPNG_SPB => {
+                let (link_type, _, _) = interfaces.first().copied().unwrap_or((LINKTYPE_ILNP, 1, false));
+                let original_length = read_u32(body, 0, big_endian).unwrap_or(0) as usize;
+
+                // the packet is cut short when it's larger than the snap length
+                let data = body.get(4..).unwrap_or(&[]);
+                let frame = &data[..original_length.min(data.len())];
+                if let Some(data) = strip_underlay(link_type, frame) {
+                    packets.push(CapturedPacket { timestamp_us: 0, direction: None, data: data.to_vec() });
+                }
+            },
+            _ => {}
+        }
+
+        offset += block_length;
+    }
+
+    Ok(packets)
+}
+
+/// Walk options looking for a given code
+fn find_option(options: &[u8], code: u16, big_endian: bool) -> Option<&[u8]>
+{
+    let mut offset = 0;
+    while offset + 4 <= options.len() {
+        let option_code = read_u16(options, offset, big_endian)?;
+        let option_length = read_u16(options, offset + 2, big_endian)? as usize;
+        if option_code == 0 {
+            return None;
+        }
+        if option_code == code {
+            return options.get(offset + 4..offset + 4 + option_length);
+        }
+        offset += 4 + option_length.div_ceil(4) * 4;
+    }
+    None
+}
+
+/// if_tsresol converted to a divisor (or multiplier) to get microseconds
+fn read_tsresol(options: &[u8], big_endian: bool) -> (u64, bool)
+{
+    match find_option(options, 9, big_endian).and_then(|value| value.first().copied()) {
+        Some(tsresol) if tsresol & 0x80 == 0 => {
+            let exponent = tsresol as i32;
+            if exponent >= 6 {
+                (10u64.saturating_pow((exponent - 6) as u32), false)
+            } else {
+                (10u64.pow((6 - exponent) as u32), true)
+            }
+        },
+        Some(tsresol) => {
+            // power of two resolution, close enough for pretty printing
+            let exponent = (tsresol & 0x7F) as u32;
+            let ticks_per_second = 1u64.checked_shl(exponent).unwrap_or(u64::MAX);
+            ((ticks_per_second / 1_000_000).max(1), false)
+        },
+        None => (1, false)
+    }
+}
+
+fn read_epb_direction(options: &[u8], big_endian: bool) -> Option<CaptureDirection>
+{
+    let flags = find_option(options, 2, big_endian)?;
+    let flags = read_u32(flags, 0, big_endian)?;
+    match flags & 0x3 {
+        1 => Some(CaptureDirection::Inbound),
+        2 => Some(CaptureDirection::Outbound),
+        _ => None
+    }
+}
+
+/// Strip the underlay headers off a captured frame
+///     - returns the UDP payload (the overlay packet)
+fn strip_underlay(link_type: u16, frame: &[u8]) -> Option<&[u8]>
+{
+    let ip_packet = match link_type {
+        LINKTYPE_ILNP => {
+            return Some(frame);
+        },
+        LINKTYPE_ETHERNET => {
+            let ether_type = read_u16(frame, 12, true)?;
+            if ether_type != 0x86DD { return None; }
+            frame.get(14..)?
+        },
+        LINKTYPE_LINUX_SLL => {
+            let protocol = read_u16(frame, 14, true)?;
+            if protocol != 0x86DD { return None; }
+            frame.get(16..)?
+        },
+        LINKTYPE_LINUX_SLL2 => {
+            let protocol = read_u16(frame, 0, true)?;
+            if protocol != 0x86DD { return None; }
+            frame.get(20..)?
+        },
+        LINKTYPE_RAW | LINKTYPE_IPV6 => frame,
+        _ => {
+            return None;
+        }
+    };
+
+    // rfc6741 captures are the overlay packet itself
+    if link_type == LINKTYPE_IPV6 && matches!(*ip_packet.get(6)?, NEXT_HEADER_DESTINATION_OPTIONS | NEXT_HEADER_ICMPV6 | NEXT_HEADER_EXPERIMENTAL) {
+        return Some(ip_packet);
+    }
+
+    // IPv6 carrying UDP directly
+    if ip_packet.first()? >> 4 != 6 || *ip_packet.get(6)? != 17 {
+        return None;
+    }
+    ip_packet.get(40 + 8..)
+}
+
+
+/// Describe an overlay packet
+///     - ILNPv6 header fields
+///     - JCMP code and body
+///     - JTP payload length
+///     - rfc6741 packets also show the nonce
+pub fn describe_packet(pck: &[u8])
+    -> String
+{
+    let header_size = std::mem::size_of::<INLPv6Packet>();
+    let header_bytes: [u8; 40] = match pck.get(..header_size).and_then(|bytes| bytes.try_into().ok()) {
+        Some(header_bytes) => header_bytes,
+        None => {
+            return format!("  truncated packet ({} bytes)", pck.len());
+        }
+    };
+
+    let header = INLPv6Packet::from_bytes(header_bytes);
+    let payload = &pck[header_size..];
+    let mut lines = vec![
+        format!("  ILNPv6  version={} traffic_class={} flow_label={} payload_length={} next_header={} hop_limit={}",
+            header.version(), header.traffic_class(), header.flow_label(), header.payload_length(), header.next_header(), header.hop_limit()),
+        format!("          src=0x{:016X}:0x{:016X}", header.source_locator(), header.source_identifier()),
+        format!("          dst=0x{:016X}:0x{:016X}", header.destination_locator(), header.destination_identifier())
+    ];
+
+    match header.next_header() {
+        150 => lines.push(format!("  JCMP    {}", describe_jcmp(payload))),
+        151 => lines.push(format!("  JTP     payload_length={}", payload.len())),
+        NEXT_HEADER_DESTINATION_OPTIONS | NEXT_HEADER_ICMPV6 | NEXT_HEADER_EXPERIMENTAL => {
+            match decode_packet(pck) {
+                Ok(packet) => {
+                    if let Some(nonce) = packet.nonce {
+                        lines.push(format!("  DSTOPT  nonce=0x{:08X}", nonce));
+                    }
+                    match packet.header.next_header() {
+                        NEXT_HEADER_JCMP => lines.push(format!("  ICMPv6  JCMP {}", describe_jcmp(&packet.payload))),
+                        NEXT_HEADER_JTP => lines.push(format!("  JTP     payload_length={}", packet.payload.len())),
+                        next_header => lines.push(format!("  unknown next header {} ({} bytes)", next_header, packet.payload.len()))
+                    }
+                },
+                Err(err) => lines.push(format!("  malformed rfc6741 packet: {}", err))
+            }
+        },
+        next_header => lines.push(format!("  unknown next header {} ({} bytes)", next_header, payload.len()))
+    }
+
+    lines.join("\n")
+}
+
+fn describe_jcmp(payload: &[u8])
+    -> String
+{
+    match JcmpMessage::decode(payload) {
+        Ok(JcmpMessage::NeighbourSolicitation) => "code=0 ND Solicitation".to_string(),
+        Ok(JcmpMessage::NeighbourAdvertisement { destination_port }) => format!("code=1 ND Advertisement port={}", destination_port),
+        Ok(JcmpMessage::RouterSolicitation) => "code=2 Router Solicitation".to_string(),
+        Ok(JcmpMessage::RouterAdvertisement { lifetime, locators }) => {
+            let locators: Vec<String> = locators.iter().map(|locator| format!("0x{:016X}", locator)).collect();
+            format!("code=3 Router Advertisement lifetime={} locators=[{}]", lifetime, locators.join(", "))
+        },
+        Ok(JcmpMessage::DnsFqdnQuery { fqdn }) => format!("code=4 DNS FQDN Query fqdn={:?}", fqdn),
+        Ok(JcmpMessage::DnsFqdnResponse { ttl, fqdn }) => format!("code=5 DNS FQDN Response ttl={} fqdn={:?}", ttl, fqdn),
+        Ok(JcmpMessage::DnsIlvQuery) => "code=6 DNS ILV Query".to_string(),
+        Ok(JcmpMessage::DnsIlvResponse { ttl }) => format!("code=7 DNS ILV Response ttl={}", ttl),
+        Ok(JcmpMessage::RouterRequest { hop_count, destination_locator }) => {
+            format!("code=8 Router Request hop_count={} locator=0x{:016X}", hop_count, destination_locator)
+        },
+        Ok(JcmpMessage::RouterResponse { hop_count, destination_locator, ttl }) => {
+            format!("code=9 Router Response hop_count={} locator=0x{:016X} ttl={}", hop_count, destination_locator, ttl)
+        },
+        Ok(JcmpMessage::DuplicateNidProbe { nonce }) => format!("code=10 Duplicate NID Probe nonce=0x{:08X}", nonce),
+        Ok(JcmpMessage::DuplicateNidDefend { nonce }) => format!("code=11 Duplicate NID Defend nonce=0x{:08X}", nonce),
+        Ok(JcmpMessage::DestinationUnreachable { destination_locator, destination_nid }) => {
+            format!("code=12 Destination Unreachable locator=0x{:016X} nid=0x{:016X}", destination_locator, destination_nid)
+        },
+        Err(DecodeError::UnknownCode(code)) => format!("code={} unknown ({} bytes)", code, payload.len()),
+        Err(err) => {
+
+            // authenticated messages end with the trailer (not verified here)
+            if payload.len() > JCMP_AUTH_TRAILER_SIZE {
+                let (message, trailer) = payload.split_at(payload.len() - JCMP_AUTH_TRAILER_SIZE);
+                if JcmpMessage::decode(message).is_ok() {
+                    let timestamp_us = u64::from_be_bytes(trailer[..8].try_into().unwrap_or_default());
+                    let counter = u32::from_be_bytes(trailer[8..12].try_into().unwrap_or_default());
+                    return format!("{} auth ts={} counter={}", describe_jcmp(message), timestamp_us, counter);
+                }
+            }
+            format!("malformed: {}", err)
+        }
+    }
+}
@@ -1,50 +1,503 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use tokio::time::{timeout, Instant};
+
+use crate::layers::overlay_network::CONFIG;
 use crate::layers::underlay_network::underlay_multi_tx;
+use crate::models::config_models::LogTransport;
+use crate::models::log_models::{LogControl, LogEvent, LogLevel, LogRecord, LOG_PROTOCOL_VERSION, MAX_LOG_DATAGRAM};
 use crate::models::network_models::EmulatorSocket;
 
 use super::time_services::get_current_timestamp;
 
-pub async fn log_info(emulator_socket: &EmulatorSocket, info_message: &str) 
+/// Log session
+///     - the node's start time, distinguishes a restarted node from a retransmission
+static LOG_SESSION: Lazy<u64> = Lazy::new(|| get_current_timestamp().unwrap_or(0));
+
+/// Log sequence number
+///     - incremented for every record sent by this node, starting at 1
+static LOG_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+/// Log spool
+///     - records not yet acknowledged by the logger (reliable transport only)
+///     - maps seq to (last time sent, JSON line)
+///     - bounded by logging.spool_size, the oldest record is dropped when full
+static LOG_SPOOL: Lazy<Mutex<BTreeMap<u64, (Instant, String)>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Send a structured log record
+///     - record is stamped with the node name, NID and current time
+///     - encoded as a single JSON line and sent to the log multicast group
+pub async fn log_event(emulator_socket: &EmulatorSocket, level: LogLevel, event: LogEvent)
 {
-    
+
+    // filtered here so records below the level are never sent, spooled or sequenced
+    if level < CONFIG.logging.level {
+        return;
+    }
+
     // get current timestamp
     match get_current_timestamp() {
         Ok(current_timestamp) => {
 
-            // send info log with NID and time
-            let message = format!("INFO;0x{:016X};{};{}", emulator_socket.local_network.local_nid, current_timestamp, info_message);
-            match underlay_multi_tx(emulator_socket, &"log".to_string(), message.as_bytes()).await {
-                Ok(()) => {},
+            // build the record
+            let seq = LOG_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+            let record = LogRecord {
+                v: LOG_PROTOCOL_VERSION,
+                level,
+                node: emulator_socket.local_network.local_fqdn.clone(),
+                nid: emulator_socket.local_network.local_nid,
+                session: *LOG_SESSION,
+                seq,
+                ts: current_timestamp,
+                event
+            };
+
+            // send the JSON line to the logger
+            match record.to_json_string() {
+                Ok(message) => {
+
+                    // keep the record until the logger acknowledges it
+                    if CONFIG.logging.transport == LogTransport::Reliable {
+                        spool_record(seq, &message);
+                    }
+
+                    match underlay_multi_tx(emulator_socket, &"log".to_string(), message.as_bytes()).await {
+                        Ok(()) => {},
+                        Err(err) => {
+                            eprintln!("SYSTEMLOG: log_event(): failed to send log: {}", err);
+                        }
+                    }
+                },
                 Err(err) => {
-                    eprintln!("SYSTEMLOG: log_info(): failed to send log: {}", err);
+                    eprintln!("SYSTEMLOG: log_event(): failed to send log: {}", err);
                 }
             }
 
         },
         Err(err) => {
-            eprintln!("SYSTEMLOG: log_info(): failed to send log: {}", err);
+            eprintln!("SYSTEMLOG: log_event(): failed to send log: {}", err);
         }
     }
 }
 
-pub async fn log_error(emulator_socket: &EmulatorSocket, error_message: &str) 
+pub async fn log_info(emulator_socket: &EmulatorSocket, info_message: &str)
 {
+    log_event(emulator_socket, LogLevel::Info, LogEvent::Message { text: info_message.to_string() }).await;
+}
 
-    // get current timestamp
-    match get_current_timestamp() {
-        Ok(current_timestamp) => {
+pub async fn log_error(emulator_socket: &EmulatorSocket, error_message: &str)
+{
+    log_event(emulator_socket, LogLevel::Error, LogEvent::Message { text: error_message.to_string() }).await;
+}
 
-            // send error log with NID and time
-            let message = format!("ERROR;0x{:016X};{};{}", emulator_socket.local_network.local_nid, current_timestamp, error_message);
-            match underlay_multi_tx(emulator_socket, &"log".to_string(), message.as_bytes()).await {
-                Ok(()) => {},
+
+/// Log filter
+///     - records below the minimum level are rejected
+///     - nodes lists node names or NIDs (0x...), an empty list accepts every node
+pub fn log_filter_accepts(record: &LogRecord, level: LogLevel, nodes: &[String])
+    -> bool
+{
+    if record.level < level {
+        return false;
+    }
+
+    if nodes.is_empty() {
+        return true;
+    }
+
+    let nid_hex = format!("0x{:016X}", record.nid);
+    nodes.iter().any(|node| {
+        node == &record.node || node.eq_ignore_ascii_case(&nid_hex)
+    })
+}
+
+
+/// Insert a record in the spool
+///     - drop the oldest record if the spool is full
+fn spool_record(seq: u64, message: &str)
+{
+    match LOG_SPOOL.lock() {
+        Ok(mut spool) => {
+            spool.insert(seq, (Instant::now(), message.to_string()));
+            while spool.len() > CONFIG.logging.spool_size {
+                if let Some((dropped, _)) = spool.pop_first() {
+                    eprintln!("SYSTEMLOG: spool_record(): spool full, dropped record {}", dropped);
+                }
+            }
+        },
+        Err(err) => {
+            eprintln!("SYSTEMLOG: spool_record(): failed to lock LOG_SPOOL: {}", err);
+        }
+    }
+}
+
+/// Handle a datagram received on the log multicast group
+///     - log records from other nodes are ignored
+///     - acknowledgements for this node release the acknowledged records from the spool
+pub fn handle_log_datagram(emulator_socket: &EmulatorSocket, buf: &[u8])
+{
+    if let Ok(LogControl::Ack { nid, session, seq, .. }) = LogControl::from_json_bytes(buf) {
+        if nid == emulator_socket.local_network.local_nid && session == *LOG_SESSION {
+            match LOG_SPOOL.lock() {
+                Ok(mut spool) => {
+                    let unacked = spool.split_off(&(seq + 1));
+                    *spool = unacked;
+                },
                 Err(err) => {
-                    eprintln!("SYSTEMLOG: log_error(): failed to send log: {}", err);
+                    eprintln!("SYSTEMLOG: handle_log_datagram(): failed to lock LOG_SPOOL: {}", err);
                 }
             }
+        }
+    }
+}
 
+/// Check if a multicast datagram is log traffic
+///     - log records and controls are JSON objects, ILNP packets start with the version nibble
+pub fn is_log_datagram(buf: &[u8])
+    -> bool
+{
+    buf.first() == Some(&b'{')
+}
+
+/// Retransmit unacknowledged records
+///     - resend every spooled record last sent more than logging.retransmit_ms ago
+pub async fn log_retransmit(emulator_socket: &EmulatorSocket)
+{
+    let retransmit = Duration::from_millis(CONFIG.logging.retransmit_ms);
+
+    // collect due records without holding the lock across the send
+    let due: Vec<String> = match LOG_SPOOL.lock() {
+        Ok(mut spool) => {
+            let now = Instant::now();
+            spool.values_mut()
+                .filter(|(sent_at, _)| now.duration_since(*sent_at) >= retransmit)
+                .map(|(sent_at, message)| {
+                    *sent_at = now;
+                    message.clone()
+                })
+                .collect()
         },
         Err(err) => {
-            eprintln!("SYSTEMLOG: log_error(): failed to send log: {}", err);
+            eprintln!("SYSTEMLOG: log_retransmit(): failed to lock LOG_SPOOL: {}", err);
+            return;
+        }
+    };
+
+    for message in due {
+        if let Err(err) = underlay_multi_tx(emulator_socket, &"log".to_string(), message.as_bytes()).await {
+            eprintln!("SYSTEMLOG: log_retransmit(): failed to resend log: {}", err);
+        }
+    }
+}
+
+/// Flush the spool before closing
+///     - retransmit and wait for acknowledgements until empty or logging.flush_timeout_ms
+///     - reads the multicast socket directly, the receiver must be stopped first
+pub async fn log_flush(emulator_socket: &EmulatorSocket)
+{
+    if CONFIG.logging.transport != LogTransport::Reliable {
+        return;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(CONFIG.logging.flush_timeout_ms);
+    // acknowledgements share the socket with records of any size
+    let mut buf = vec![0; MAX_LOG_DATAGRAM];
+    while Instant::now() < deadline {
+
+        // done once everything is acknowledged
+        match LOG_SPOOL.lock() {
+            Ok(spool) => {
+                if spool.is_empty() { return; }
+            },
+            Err(_) => { return; }
+        }
+
+        log_retransmit(emulator_socket).await;
+
+        // wait for acknowledgements
+        let wait = Duration::from_millis(CONFIG.logging.retransmit_ms);
+        if let Ok(Ok((len, _))) = timeout(wait, emulator_socket.mulcast_socket.recv_from(&mut buf)).await {
+            if is_log_datagram(&buf[..len]) {
+                handle_log_datagram(emulator_socket, &buf[..len]);
+            }
         }
     }
+
+    if let Ok(spool) = LOG_SPOOL.lock() {
+        if !spool.is_empty() {
+            eprintln!("SYSTEMLOG: log_flush(): {} records were not acknowledged", spool.len());
+        }
+    }
+}
+
+
+/// Log stream tracker
+///     - used by the logger to detect missing and duplicate records
+///     - one stream per (NID, session)
+#[derive(Default)]
+pub struct LogStreamTracker {
+    streams: HashMap<(u64, u64), LogStream>
+}
+
+struct LogStream {
+    next_expected: u64,
+    highest: u64,
+    ahead: BTreeSet<u64>,
+
+    // gaps not reported yet as (from, to, detected), retransmissions may still fill them
+    pending: Vec<(u64, u64, Instant)>,
+
+    // ranges given up on when the window overflowed
+    given_up: Vec<(u64, u64)>
+}
+
+/// Result of observing a record
+///     - Accepted may report a newly detected gap (from, to inclusive)
+#[derive(Debug, PartialEq, Eq)]
+pub enum LogObservation {
+    Accepted { gap: Option<(u64, u64)> },
+    Duplicate
+}
+
+/// Records held out of order per stream before the oldest gap is given up on
+const LOG_STREAM_WINDOW: usize = 4096;
+
+/// Retransmissions a gap is given before the logger reports it (reliable transport only)
+pub const LOG_GAP_RETRANSMITS: u64 = 4;
+
+impl LogStreamTracker {
+    pub fn new()
+        -> Self
+    {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, record: &LogRecord)
+        -> LogObservation
+    {
+        // unsequenced records can't be tracked
+        if record.seq == 0 {
+            return LogObservation::Accepted { gap: None };
+        }
+
+        let stream = self.streams.entry((record.nid, record.session)).or_insert(LogStream {
+            next_expected: 1,
+            highest: 0,
+            ahead: BTreeSet::new(),
+            pending: Vec::new(),
+            given_up: Vec::new()
+        });
+
+        // already received
+        if record.seq < stream.next_expected || stream.ahead.contains(&record.seq) {
+            return LogObservation::Duplicate;
+        }
+
+        // records skipped since the highest one seen
+        let gap = if record.seq > stream.highest + 1 {
+            Some((stream.highest + 1, record.seq - 1))
+        } else {
+            None
+        };
+        stream.highest = stream.highest.max(record.seq);
+        if let Some((from, to)) = gap {
+            stream.pending.push((from, to, Instant::now()));
+        }
+
+        // advance the contiguous window
+        if record.seq == stream.next_expected {
+            stream.next_expected += 1;
+            while stream.ahead.remove(&stream.next_expected) {
+                stream.next_expected += 1;
+            }
+        } else {
+            stream.ahead.insert(record.seq);
+        }
+
+        // give up on the oldest gap if too many records are waiting behind it
+        while stream.ahead.len() > LOG_STREAM_WINDOW {
+            if let Some(first) = stream.ahead.pop_first() {
+                if first > stream.next_expected {
+                    stream.given_up.push((stream.next_expected, first - 1));
+                }
+                stream.next_expected = first + 1;
+                while stream.ahead.remove(&stream.next_expected) {
+                    stream.next_expected += 1;
+                }
+            }
+        }
+
+        LogObservation::Accepted { gap }
+    }
+
+    /// Gaps still open after the given window, as (NID, session, from, to)
+    ///     - each gap is reported once, only the ranges retransmissions didn't fill
+    ///     - ranges given up on are reported whatever their age
+    ///     - a zero window reports every open gap, used when the logger stops
+    pub fn expired_gaps(&mut self, window: Duration)
+        -> Vec<(u64, u64, u64, u64)>
+    {
+        let now = Instant::now();
+        let mut gaps = Vec::new();
+        for ((nid, session), stream) in self.streams.iter_mut() {
+            for (from, to) in stream.given_up.drain(..) {
+                gaps.push((*nid, *session, from, to));
+            }
+
+            let mut pending = Vec::new();
+            for (from, to, detected) in stream.pending.drain(..) {
+                if now.duration_since(detected) < window {
+                    pending.push((from, to, detected));
+                    continue;
+                }
+
+                // what is still missing, received and given up records are skipped
+                let mut start = from.max(stream.next_expected);
+                if start > to {
+                    continue;
+                }
+                for seq in stream.ahead.range(start..=to) {
+                    if *seq > start {
+                        gaps.push((*nid, *session, start, seq - 1));
+                    }
+                    start = seq + 1;
+                }
+                if start <= to {
+                    gaps.push((*nid, *session, start, to));
+                }
+            }
+            stream.pending = pending;
+        }
+        gaps.sort();
+        gaps
+    }
+
+    /// Highest sequence number received contiguously for a stream
+    pub fn acknowledged(&self, nid: u64, session: u64)
+        -> u64
+    {
+        match self.streams.get(&(nid, session)) {
+            Some(stream) => stream.next_expected - 1,
+            None => 0
+        }
+    }
+
+    /// Ranges still missing, as (NID, session, from, to)
+    pub fn outstanding_gaps(&self)
+        -> Vec<(u64, u64, u64, u64)>
+    {
+        let mut gaps = Vec::new();
+        for ((nid, session), stream) in self.streams.iter() {
+            let mut from = stream.next_expected;
+            for seq in stream.ahead.iter() {
+                if *seq > from {
+                    gaps.push((*nid, *session, from, seq - 1));
+                }
+                from = seq + 1;
+            }
+        }
+        gaps.sort();
+        gaps
+    }
+}
+
+
+/// Rotating file sink
+///     - appends JSON lines to a file
+///     - once the file reaches max_bytes it is renamed to file.1 (file.1 to file.2, ...)
+///     - only max_files old files are kept
+pub struct RotatingFileSink {
+    path: String,
+    max_bytes: u64,
+    max_files: u32,
+    file: File,
+    written: u64
+}
+
+impl RotatingFileSink {
+    pub fn open(path: &str, max_bytes: u64, max_files: u32)
+        -> Result<Self, String>
+    {
+        // create the parent directory if needed
+        if let Some(parent) = Path::new(path).parent() {
+            if !parent.as_os_str().is_empty() {
+                if let Err(err) = fs::create_dir_all(parent) {
+                    return Err(format!("RotatingFileSink::open(): failed to create {:?}: {}", parent, err));
+                }
+            }
+        }
+
+        let file = Self::open_file(path)?;
+        let written = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            max_bytes,
+            max_files,
+            file,
+            written
+        })
+    }
+
+    fn open_file(path: &str)
+        -> Result<File, String>
+    {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => {
+                Ok(file)
+            },
+            Err(err) => {
+                Err(format!("RotatingFileSink::open_file(): failed to open {}: {}", path, err))
+            }
+        }
+    }
+
+    pub fn write_line(&mut self, line: &str)
+        -> Result<(), String>
+    {
+        // rotate before the file grows past the limit
+        if self.max_bytes > 0 && self.written + line.len() as u64 + 1 > self.max_bytes && self.written > 0 {
+            self.rotate()?;
+        }
+
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => {
+                self.written += line.len() as u64 + 1;
+                Ok(())
+            },
+            Err(err) => {
+                Err(format!("RotatingFileSink::write_line(): failed to write to {}: {}", self.path, err))
+            }
+        }
+    }
+
+    fn rotate(&mut self)
+        -> Result<(), String>
+    {
+        // shift file.N-1 -> file.N, dropping the oldest
+        if self.max_files > 0 {
+            let _ = fs::remove_file(format!("{}.{}", self.path, self.max_files));
+            for index in (1..self.max_files).rev() {
+                let _ = fs::rename(format!("{}.{}", self.path, index), format!("{}.{}", self.path, index + 1));
+            }
+            if let Err(err) = fs::rename(&self.path, format!("{}.1", self.path)) {
+                return Err(format!("RotatingFileSink::rotate(): failed to rotate {}: {}", self.path, err));
+            }
+        } else {
+            let _ = fs::remove_file(&self.path);
+        }
+
+        self.file = Self::open_file(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
 }
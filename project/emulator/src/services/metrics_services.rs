@@ -0,0 +1,310 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use crate::layers::overlay_network::CONFIG;
+use crate::models::config_models::MetricsFormat;
+use crate::models::log_models::{LogEvent, LogLevel, LogRecord, LOG_PROTOCOL_VERSION};
+use crate::models::network_models::{EmulatorLocalNetwork, EmulatorSocket};
+use crate::models::protocol_control_block::ILNP_PCB_S;
+
+use super::log_services::log_event;
+use super::time_services::get_current_timestamp;
+
+/// Metrics writer
+///     - open when metrics.enabled is set
+///     - records are sent to a dedicated thread so file writes never block the runtime
+///     - the thread owns the sink and stops once the sender is dropped by close_metrics
+static METRICS_WRITER: Lazy<Mutex<Option<MetricsWriter>>> = Lazy::new(|| Mutex::new(None));
+
+/// Queue to the writer thread and its handle
+type MetricsWriter = (Sender<MetricsRecord>, JoinHandle<()>);
+
+/// Metrics sink
+///     - jsonl: every line is a LogRecord so the logger output and the file parse the same way
+///     - csv: snapshots go to one file with a column per PCB counter, events to a second file
+struct MetricsSink {
+    format: MetricsFormat,
+    snapshots: File,
+    events: Option<File>,
+    columns: Vec<String>
+}
+
+/// Record queued for the writer thread
+struct MetricsRecord {
+    node: String,
+    nid: u64,
+    timestamp: u64,
+    event: LogEvent
+}
+
+
+/// Open the metrics file
+///     - does nothing unless metrics.enabled is set
+pub fn open_metrics(local_network: &EmulatorLocalNetwork)
+    -> Result<(), String>
+{
+    if !CONFIG.metrics.enabled {
+        return Ok(());
+    }
+
+    let format = CONFIG.metrics.format;
+    let path = match &CONFIG.metrics.path {
+        Some(path) => path.clone(),
+        None => {
+            let extension = match format {
+                MetricsFormat::Jsonl => "jsonl",
+                MetricsFormat::Csv => "csv"
+            };
+            format!("logs/{}.metrics.{}", local_network.local_fqdn, extension)
+        }
+    };
+
+    open_metrics_file(&path, format)
+}
+
+/// Open a metrics file and start the writer thread
+///     - csv events go to <path>.events.csv
+///     - a writer already running is closed first
+pub fn open_metrics_file(path: &str, format: MetricsFormat)
+    -> Result<(), String>
+{
+    // PCB counters become csv columns
+    let columns: Vec<String> = flatten_pcb(&ILNP_PCB_S::default())
+        .into_iter()
+        .map(|(column, _)| column)
+        .collect();
+
+    let mut snapshots = open_append(path)?;
+    let events = match format {
+        MetricsFormat::Jsonl => None,
+        MetricsFormat::Csv => {
+            let events_path = format!("{}.events.csv", path.trim_end_matches(".csv"));
+            let mut events = open_append(&events_path)?;
+            write_csv_header(&mut events, "ts,nid,kind,fields")?;
+            write_csv_header(&mut snapshots, &format!("ts,nid,{}", columns.join(",")))?;
+            Some(events)
+        }
+    };
+
+    close_metrics();
+
+    let (tx, rx) = channel();
+    let sink = MetricsSink { format, snapshots, events, columns };
+    let writer = match thread::Builder::new().name("metrics".to_string()).spawn(move || metrics_writer(sink, rx)) {
+        Ok(writer) => writer,
+        Err(err) => {
+            return Err(format!("open_metrics_file(): failed to start the metrics writer: {}", err));
+        }
+    };
+
+    match METRICS_WRITER.lock() {
+        Ok(mut metrics_writer) => {
+            *metrics_writer = Some((tx, writer));
+            Ok(())
+        },
+        Err(err) => {
+            Err(format!("open_metrics_file(): failed to lock METRICS_WRITER: {}", err))
+        }
+    }
+}
+
+/// Close the metrics file
+///     - waits for the writer thread to write everything queued
+///     - blocks, call it from spawn_blocking inside the runtime
+pub fn close_metrics()
+{
+    let metrics_writer = match METRICS_WRITER.lock() {
+        Ok(mut metrics_writer) => metrics_writer.take(),
+        Err(_) => None
+    };
+
+    // dropping the sender ends the writer loop
+    if let Some((tx, writer)) = metrics_writer {
+        drop(tx);
+        if writer.join().is_err() {
+            eprintln!("SYSTEMLOG: close_metrics(): metrics writer panicked");
+        }
+    }
+}
+
+fn open_append(path: &str)
+    -> Result<File, String>
+{
+    if let Some(parent) = Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                return Err(format!("open_append(): failed to create {:?}: {}", parent, err));
+            }
+        }
+    }
+
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => {
+            Ok(file)
+        },
+        Err(err) => {
+            Err(format!("open_append(): failed to open {}: {}", path, err))
+        }
+    }
+}
+
+/// Write the csv header only when starting a new file
+fn write_csv_header(file: &mut File, header: &str)
+    -> Result<(), String>
+{
+    let empty = match file.metadata() {
+        Ok(metadata) => metadata.len() == 0,
+        Err(_) => true
+    };
+    if empty {
+        if let Err(err) = writeln!(file, "{}", header) {
+            return Err(format!("write_csv_header(): {}", err));
+        }
+    }
+    Ok(())
+}
+
+/// Flatten the PCB into (column, value) pairs
+///     - nested counters are prefixed with their parent (e.g. drops.jtp_queue_full)
+fn flatten_pcb(pcb: &ILNP_PCB_S)
+    -> Vec<(String, Value)>
+{
+    fn flatten(prefix: &str, value: Value, out: &mut Vec<(String, Value)>) {
+        match value {
+            Value::Object(map) => {
+                for (key, inner) in map {
+                    let column = if prefix.is_empty() { key } else { format!("{}.{}", prefix, key) };
+                    flatten(&column, inner, out);
+                }
+            },
+            other => out.push((prefix.to_string(), other))
+        }
+    }
+
+    let mut out = Vec::new();
+    if let Ok(value) = serde_json::to_value(pcb) {
+        flatten("", value, &mut out);
+    }
+    out
+}
+
+
+/// Write a PCB snapshot to the metrics file
+pub fn write_snapshot(local_network: &EmulatorLocalNetwork, pcb: &ILNP_PCB_S)
+{
+    write_record(local_network, LogEvent::PcbSnapshot { pcb: *pcb });
+}
+
+/// Write an event to the metrics file
+pub fn write_event(local_network: &EmulatorLocalNetwork, event: &LogEvent)
+{
+    write_record(local_network, event.clone());
+}
+
+fn write_record(local_network: &EmulatorLocalNetwork, event: LogEvent)
+{
+    let record = MetricsRecord {
+        node: local_network.local_fqdn.clone(),
+        nid: local_network.local_nid,
+        timestamp: get_current_timestamp().unwrap_or(0),
+        event
+    };
+
+    match METRICS_WRITER.lock() {
+        Ok(metrics_writer) => {
+            if let Some((tx, _)) = metrics_writer.as_ref() {
+                if let Err(err) = tx.send(record) {
+                    eprintln!("SYSTEMLOG: write_record(): failed to queue metrics: {}", err);
+                }
+            }
+        },
+        Err(err) => {
+            eprintln!("SYSTEMLOG: write_record(): failed to lock METRICS_WRITER: {}", err);
+        }
+    }
+}
+
+/// Metrics writer thread
+///     - writes queued records until every sender is dropped
+fn metrics_writer(mut sink: MetricsSink, rx: Receiver<MetricsRecord>)
+{
+    for record in rx {
+        let result = match sink.format {
+            MetricsFormat::Jsonl => {
+                let record = LogRecord {
+                    v: LOG_PROTOCOL_VERSION,
+                    level: LogLevel::Info,
+                    node: record.node,
+                    nid: record.nid,
+                    session: 0,
+                    seq: 0,
+                    ts: record.timestamp,
+                    event: record.event
+                };
+                match record.to_json_string() {
+                    Ok(line) => writeln!(sink.snapshots, "{}", line).map_err(|err| err.to_string()),
+                    Err(err) => Err(err)
+                }
+            },
+            MetricsFormat::Csv => {
+                write_csv_record(&mut sink, record.nid, record.timestamp, record.event)
+            }
+        };
+        if let Err(err) = result {
+            eprintln!("SYSTEMLOG: metrics_writer(): failed to write metrics: {}", err);
+        }
+    }
+}
+
+fn write_csv_record(sink: &mut MetricsSink, nid: u64, timestamp: u64, event: LogEvent)
+    -> Result<(), String>
+{
+    // snapshots are one row of counters
+    if let LogEvent::PcbSnapshot { pcb } = event {
+        let values = flatten_pcb(&pcb);
+        let row: Vec<String> = sink.columns.iter()
+            .map(|column| {
+                values.iter()
+                    .find(|(name, _)| name == column)
+                    .map(|(_, value)| value.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+        return writeln!(sink.snapshots, "{},0x{:016X},{}", timestamp, nid, row.join(",")).map_err(|err| err.to_string());
+    }
+
+    // events are kind plus the typed fields as quoted JSON
+    let (kind, fields) = match serde_json::to_value(&event) {
+        Ok(Value::Object(mut map)) => {
+            let kind = map.remove("kind").map(|kind| kind.as_str().unwrap_or_default().to_string()).unwrap_or_default();
+            let fields = map.remove("fields").map(|fields| fields.to_string()).unwrap_or_default();
+            (kind, fields)
+        },
+        Ok(_) => (String::new(), String::new()),
+        Err(err) => {
+            return Err(err.to_string());
+        }
+    };
+    match sink.events.as_mut() {
+        Some(events) => {
+            writeln!(events, "{},0x{:016X},{},\"{}\"", timestamp, nid, kind, fields.replace('"', "\"\"")).map_err(|err| err.to_string())
+        },
+        None => Ok(())
+    }
+}
+
+
+/// Record an event
+///     - written to the node's own metrics file (if enabled)
+///     - sent to the logger as well
+pub async fn record_event(emulator_socket: &EmulatorSocket, event: LogEvent)
+{
+    write_event(&emulator_socket.local_network, &event);
+    log_event(emulator_socket, LogLevel::Info, event).await;
+}
@@ -1,4 +1,7 @@
+pub mod capture_services;
 pub mod config_services;
+pub mod dump_services;
 pub mod network_services;
 pub mod log_services;
+pub mod metrics_services;
 pub mod time_services;
\ No newline at end of file
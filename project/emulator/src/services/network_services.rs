@@ -1,16 +1,16 @@
 use pnet::datalink::{self};
 use pnet::ipnetwork::IpNetwork;
-use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::net::Ipv6Addr;
-use std::time::Duration;
-use std::hash::{Hash, Hasher};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use ttl_cache::TtlCache;
 
-use crate::layers::overlay_network::{CONFIG, NAME_ILV_TABLE, NID_ILV_TABLE};
+use crate::layers::overlay_network::{CONFIG, DEFAULT_ROUTER_TABLE, LOCAL_NONCE, NAME_ILV_TABLE, NEIGHBOUR_ADDRESS_INDEX, NID_ADDRESS_RESOLUTION_TABLE, NID_ILV_TABLE, NONCE_SESSION_TABLE, PENDING_PATH_TABLE, ROUTE_EPOCH};
 use crate::layers::underlay_network::INTERFACES;
 use crate::layers::overlay_network::LOCATOR_FORWARDING_TABLE;
 
-use crate::models::network_models::EmulatorLocalNetwork;
+use crate::models::network_models::{EmulatorLocalNetwork, NeighbourEntry, NeighbourState, NonceCheck};
 use crate::services::config_services::get_uid;
 
 /// Create network configurations based on the number of networks needed.
@@ -67,6 +67,28 @@ pub fn get_under_interface_by_name(interface_name: &String)
 }
 
 
+/// Current IPv6 of the underlay interface
+///     - used to notice the address changing under a running node
+pub fn get_under_ipv6_by_index(index: u32)
+    -> Result<Ipv6Addr, String>
+{
+    for interface in datalink::interfaces() {
+        if interface.index != index { continue; }
+
+        for ip_info in interface.ips {
+            match ip_info {
+                IpNetwork::V6(addr) => {
+                    return Ok(addr.ip());
+                },
+                _ => { continue; }
+            }
+        }
+    }
+
+    Err(format!("get_under_ipv6_by_index(): couldn't find an ipv6 on interface {}", index))
+}
+
+
 /// INTERFACES Action
 /// ******************************************************
 pub fn get_over_interface_by_name(interface_name: &String)
@@ -142,147 +164,468 @@ pub fn get_over_locators()
 // ******************************************************
 
 
-/// DNS TABLES Action
+/// NEIGHBOUR CACHE Action
 /// ******************************************************
-pub fn insert_into_name_ilv_table(entry: (String, u64, u64), ttl:u64) 
-    -> Result<(), String>
+/// Insert a neighbour from its advertisement
+///     - an answer to our solicitation confirms the neighbour (Reachable)
+///     - an unsolicited one leaves a known binding as it is, a new or changed binding is Stale until confirmed
+///     - returns true if the binding is new or changed
+pub fn insert_into_neighbour_cache(nid: &u64, interface_name: &String, address: Ipv6Addr, port: u16, solicited: bool)
+    -> Result<bool, String>
 {
-    // generate key
-    let mut hasher = DefaultHasher::new();
-    let key = (entry.1, entry.2);
-    key.hash(&mut hasher);
-    let hash = hasher.finish();
-
-    // insert into forwarding table
-    match NAME_ILV_TABLE.lock() {
+    match NID_ADDRESS_RESOLUTION_TABLE.lock() {
         Ok(mut map) => {
-            map.insert(hash, entry, Duration::from_secs(ttl));
-            Ok(())
+            let previous = map.get(nid).cloned();
+            let changed = match &previous {
+                Some(entry) => &entry.interface != interface_name || entry.address != address || entry.port != port,
+                None => true
+            };
+
+            let entry = match previous {
+                Some(previous) if !solicited && !changed => previous,
+                _ => NeighbourEntry {
+                    interface: interface_name.clone(),
+                    address,
+                    port,
+                    state: if solicited { NeighbourState::Reachable } else { NeighbourState::Stale },
+                    updated: Instant::now()
+                }
+            };
+            if changed {
+                update_neighbour_address_index(&mut map, nid, &entry)?;
+            }
+            map.insert(*nid, entry, Duration::from_secs(CONFIG.network.ND_TTL_S));
+            Ok(changed)
         },
         Err(err) => {
-            Err(format!("insert_into_name_ilv_table(): failed to lock NAME_ILV_TABLE: {}", err))
+            Err(format!("insert_into_neighbour_cache(): failed to lock NID_ADDRESS_RESOLUTION_TABLE: {}", err))
         }
     }
 }
-pub fn lookup_name_ilv_table(destination_fqdn: &String)
-    -> Result<Vec<(String, u64, u64)>, String>
+/// Look up a neighbour
+///     - a Reachable entry not confirmed for reachable_time_ms is Stale
+pub fn lookup_neighbour_cache(nid: &u64)
+    -> Result<Option<NeighbourEntry>, String>
+{
+    match NID_ADDRESS_RESOLUTION_TABLE.lock() {
+        Ok(mut map) => {
+            match map.get_mut(nid) {
+                Some(entry) => {
+                    let reachable_time = Duration::from_millis(CONFIG.neighbour_discovery.reachable_time_ms);
+                    if entry.state == NeighbourState::Reachable && entry.updated.elapsed() > reachable_time {
+                        entry.state = NeighbourState::Stale;
+                        entry.updated = Instant::now();
+                    }
+                    Ok(Some(entry.clone()))
+                },
+                None => Ok(None)
+            }
+        },
+        Err(err) => {
+            Err(format!("lookup_neighbour_cache(): failed to lock NID_ADDRESS_RESOLUTION_TABLE: {}", err))
+        }
+    }
+}
+/// Look up the NID of the neighbour at an address
+///     - through NEIGHBOUR_ADDRESS_INDEX, the entry has to still be bound to the address
+pub fn lookup_neighbour_nid(address: &Ipv6Addr, port: u16)
+    -> Result<Option<u64>, String>
 {
-    match NAME_ILV_TABLE.lock() {
+    match NID_ADDRESS_RESOLUTION_TABLE.lock() {
         Ok(map) => {
-            let mut result: Vec<(String, u64, u64)> = Vec::new();
-            for (_, (fqdn, nid, loc)) in map.clone().iter() {
-                if fqdn == destination_fqdn {
-                    result.push((fqdn.clone(), nid.clone(), loc.clone()));
+            let nid = match NEIGHBOUR_ADDRESS_INDEX.lock() {
+                Ok(index) => index.get(&(*address, port)).copied(),
+                Err(err) => {
+                    return Err(format!("lookup_neighbour_nid(): failed to lock NEIGHBOUR_ADDRESS_INDEX: {}", err));
                 }
+            };
+            Ok(nid.filter(|nid| matches!(map.get(nid), Some(entry) if &entry.address == address && entry.port == port)))
+        },
+        Err(err) => {
+            Err(format!("lookup_neighbour_nid(): failed to lock NID_ADDRESS_RESOLUTION_TABLE: {}", err))
+        }
+    }
+}
+/// Move a Stale neighbour to Probe
+///     - returns true if the caller has to send the probes
+pub fn probe_neighbour_cache(nid: &u64)
+    -> Result<bool, String>
+{
+    match NID_ADDRESS_RESOLUTION_TABLE.lock() {
+        Ok(mut map) => {
+            match map.get_mut(nid) {
+                Some(entry) if entry.state == NeighbourState::Stale => {
+                    entry.state = NeighbourState::Probe;
+                    entry.updated = Instant::now();
+                    Ok(true)
+                },
+                _ => Ok(false)
             }
-            Ok(result)
         },
         Err(err) => {
-            Err(format!("lookup_name_ilv_table(): failed to lock NAME_ILV_TABLE: {}", err))
+            Err(format!("probe_neighbour_cache(): failed to lock NID_ADDRESS_RESOLUTION_TABLE: {}", err))
         }
     }
 }
-pub fn insert_into_nid_ilv_table(entry: (u64, u64), ttl:u64) 
+/// Confirm the neighbour at an address
+///     - upper layer hint, traffic received from a neighbour's (IPv6, port) shows it's still there
+///     - the entry is only updated once it's no longer Reachable, so most packets don't touch the table
+pub fn confirm_neighbour_cache(address: &Ipv6Addr, port: u16)
     -> Result<(), String>
 {
-    // generate key
-    let mut hasher = DefaultHasher::new();
-    entry.hash(&mut hasher);
-    let hash = hasher.finish();
+    match NID_ADDRESS_RESOLUTION_TABLE.lock() {
+        Ok(mut map) => {
+            let nid = match NEIGHBOUR_ADDRESS_INDEX.lock() {
+                Ok(index) => index.get(&(*address, port)).copied(),
+                Err(err) => {
+                    return Err(format!("confirm_neighbour_cache(): failed to lock NEIGHBOUR_ADDRESS_INDEX: {}", err));
+                }
+            };
+            let Some(nid) = nid else {
+                return Ok(());
+            };
 
-    // insert into forwarding table
-    match NID_ILV_TABLE.lock() {
+            let reachable_time = Duration::from_millis(CONFIG.neighbour_discovery.reachable_time_ms);
+            let confirmed = match map.get(&nid) {
+                Some(entry) if &entry.address == address && entry.port == port => {
+                    if entry.state == NeighbourState::Reachable && entry.updated.elapsed() <= reachable_time {
+                        return Ok(());
+                    }
+                    let mut entry = entry.clone();
+                    entry.state = NeighbourState::Reachable;
+                    entry.updated = Instant::now();
+                    Some(entry)
+                },
+                _ => None
+            };
+            match confirmed {
+                Some(entry) => {
+                    map.insert(nid, entry, Duration::from_secs(CONFIG.network.ND_TTL_S));
+                },
+                None => {
+                    // the entry expired or moved, the index is stale
+                    if let Ok(mut index) = NEIGHBOUR_ADDRESS_INDEX.lock() {
+                        index.remove(&(*address, port));
+                    }
+                }
+            }
+            Ok(())
+        },
+        Err(err) => {
+            Err(format!("confirm_neighbour_cache(): failed to lock NID_ADDRESS_RESOLUTION_TABLE: {}", err))
+        }
+    }
+}
+pub fn remove_from_neighbour_cache(nid: &u64)
+    -> Result<Option<NeighbourEntry>, String>
+{
+    match NID_ADDRESS_RESOLUTION_TABLE.lock() {
         Ok(mut map) => {
-            map.insert(hash, entry, Duration::from_secs(ttl));
+            let removed = map.remove(nid);
+            if let Some(entry) = &removed {
+                if let Ok(mut index) = NEIGHBOUR_ADDRESS_INDEX.lock() {
+                    if index.get(&(entry.address, entry.port)) == Some(nid) {
+                        index.remove(&(entry.address, entry.port));
+                    }
+                }
+            }
+            ROUTE_EPOCH.fetch_add(1, Ordering::Relaxed);
+            Ok(removed)
+        },
+        Err(err) => {
+            Err(format!("remove_from_neighbour_cache(): failed to lock NID_ADDRESS_RESOLUTION_TABLE: {}", err))
+        }
+    }
+}
+/// Point the neighbour's (IPv6, port) at its NID
+///     - called with NID_ADDRESS_RESOLUTION_TABLE locked, before the new entry is inserted
+///     - drops the previous binding and prunes entries that expired out of the table
+fn update_neighbour_address_index(map: &mut TtlCache<u64, NeighbourEntry>, nid: &u64, entry: &NeighbourEntry)
+    -> Result<(), String>
+{
+    match NEIGHBOUR_ADDRESS_INDEX.lock() {
+        Ok(mut index) => {
+            if let Some(previous) = map.get(nid) {
+                if index.get(&(previous.address, previous.port)) == Some(nid) {
+                    index.remove(&(previous.address, previous.port));
+                }
+            }
+            if index.len() >= CONFIG.network.ND_CACHE_SIZE {
+                index.retain(|(address, port), nid| {
+                    matches!(map.get(nid), Some(entry) if &entry.address == address && entry.port == *port)
+                });
+            }
+            index.insert((entry.address, entry.port), *nid);
             Ok(())
         },
         Err(err) => {
-            Err(format!("insert_into_nid_ilv_table(): failed to lock NID_ILV_TABLE: {}", err))
+            Err(format!("update_neighbour_address_index(): failed to lock NEIGHBOUR_ADDRESS_INDEX: {}", err))
         }
     }
 }
+// ******************************************************
+
+
+/// DNS TABLES Action
+/// ******************************************************
+/// Insert into the name resolution table
+///     - returns true if the (NID, L64) binding wasn't already known
+pub fn insert_into_name_ilv_table(entry: (String, u64, u64), ttl:u64) 
+    -> Result<bool, String>
+{
+    let (fqdn, nid, loc) = entry;
+    let previous = NAME_ILV_TABLE.insert((nid, loc), fqdn, Duration::from_secs(ttl));
+    Ok(previous.is_none())
+}
+pub fn lookup_name_ilv_table(destination_fqdn: &String)
+    -> Result<Vec<(String, u64, u64)>, String>
+{
+    let result = NAME_ILV_TABLE.lookup(destination_fqdn).into_iter()
+        .map(|((nid, loc), fqdn)| (fqdn, nid, loc))
+        .collect();
+    Ok(result)
+}
+pub fn insert_into_nid_ilv_table(entry: (u64, u64), ttl:u64) 
+    -> Result<(), String>
+{
+    NID_ILV_TABLE.insert(entry, (), Duration::from_secs(ttl));
+    Ok(())
+}
 pub fn lookup_nid_ilv_table(destination_nid: &u64)
     -> Result<Vec<(u64, u64)>, String>
 {
-    match NID_ILV_TABLE.lock() {
+    let result = NID_ILV_TABLE.lookup(destination_nid).into_iter()
+        .map(|(entry, _)| entry)
+        .collect();
+    Ok(result)
+}
+// ******************************************************
+
+
+/// NONCE SESSIONS Action
+/// ******************************************************
+/// Get the nonce to send to a correspondent
+///     - LOCAL_NONCE unless nonce sessions are enabled, or when the destination is a placeholder NID
+///     - a new random nonce is picked the first time we send to the correspondent
+///     - every send refreshes the session, the nonce is never rotated while the correspondent may still hold it
+pub fn get_session_nonce(nid: &u64)
+    -> Result<u32, String>
+{
+    if !CONFIG.nonce.enabled || is_placeholder_nid(nid) {
+        return Ok(*LOCAL_NONCE);
+    }
+
+    match NONCE_SESSION_TABLE.lock() {
+        Ok(mut map) => {
+            let session = match map.get(nid) {
+                Some(session) => *session,
+                None => (rand::random(), None)
+            };
+            map.insert(*nid, session, session_retention());
+            Ok(session.0)
+        },
+        Err(err) => {
+            Err(format!("get_session_nonce(): failed to lock NONCE_SESSION_TABLE: {}", err))
+        }
+    }
+}
+
+/// Check the nonce a correspondent sent us
+///     - the first nonce received establishes the session
+///     - verified packets refresh the session
+///     - the correspondent's nonce is forgotten session_ttl_s after the last verified packet
+pub fn check_session_nonce(nid: &u64, nonce: Option<u32>)
+    -> Result<NonceCheck, String>
+{
+    match NONCE_SESSION_TABLE.lock() {
+        Ok(mut map) => {
+            let (local_nonce, remote_nonce) = match map.get(nid) {
+                Some((local_nonce, remote)) => (*local_nonce, live_remote_nonce(remote)),
+                None => (rand::random(), None)
+            };
+
+            match (remote_nonce, nonce) {
+                (_, None) => Ok(NonceCheck::Missing),
+                (None, Some(nonce)) => {
+                    map.insert(*nid, (local_nonce, Some((nonce, Instant::now()))), session_retention());
+                    Ok(NonceCheck::Established)
+                },
+                (Some(remote_nonce), Some(nonce)) if remote_nonce == nonce => {
+                    map.insert(*nid, (local_nonce, Some((nonce, Instant::now()))), session_retention());
+                    Ok(NonceCheck::Verified)
+                },
+                (Some(_), Some(_)) => Ok(NonceCheck::Mismatch)
+            }
+        },
+        Err(err) => {
+            Err(format!("check_session_nonce(): failed to lock NONCE_SESSION_TABLE: {}", err))
+        }
+    }
+}
+
+/// Check if a correspondent's nonce is known
+pub fn has_session_nonce(nid: &u64)
+    -> Result<bool, String>
+{
+    match NONCE_SESSION_TABLE.lock() {
         Ok(map) => {
-            let mut result: Vec<(u64, u64)> = Vec::new();
-            for (_, (nid, loc)) in map.clone().iter() {
-                if nid == destination_nid {
-                    result.push((nid.clone(), loc.clone()));
-                }
+            Ok(map.get(nid).and_then(|(_, remote)| live_remote_nonce(remote)).is_some())
+        },
+        Err(err) => {
+            Err(format!("has_session_nonce(): failed to lock NONCE_SESSION_TABLE: {}", err))
+        }
+    }
+}
+
+/// Forget a correspondent's nonce
+///     - our nonce is kept, the next packet the correspondent addresses to us establishes the session again
+///     - returns true if the correspondent's nonce was known
+pub fn reset_session_nonce(nid: &u64)
+    -> Result<bool, String>
+{
+    match NONCE_SESSION_TABLE.lock() {
+        Ok(mut map) => {
+            match map.get(nid).copied() {
+                Some((local_nonce, remote)) => {
+                    map.insert(*nid, (local_nonce, None), session_retention());
+                    Ok(live_remote_nonce(&remote).is_some())
+                },
+                None => Ok(false)
             }
-            Ok(result)
         },
         Err(err) => {
-            Err(format!("lookup_nid_ilv_table(): failed to lock NID_ILV_TABLE: {}", err))
+            Err(format!("reset_session_nonce(): failed to lock NONCE_SESSION_TABLE: {}", err))
         }
     }
 }
+
+/// Check if a NID is a placeholder (DNS, all nodes, all routers) rather than a correspondent
+pub fn is_placeholder_nid(nid: &u64)
+    -> bool
+{
+    matches!(*nid, 0x0000000053535353 | 0x00000000ff02ff01 | 0x00000000ff02ff02)
+}
+
+fn live_remote_nonce(remote: &Option<(u32, Instant)>)
+    -> Option<u32>
+{
+    match remote {
+        Some((nonce, verified_at)) if verified_at.elapsed() < Duration::from_secs(CONFIG.nonce.session_ttl_s) => Some(*nonce),
+        _ => None
+    }
+}
+
+fn session_retention()
+    -> Duration
+{
+    Duration::from_secs(CONFIG.nonce.session_ttl_s.saturating_mul(2))
+}
 // ******************************************************
 
 
 /// ROUTING TABLES Action
 /// ******************************************************
+/// Insert a route
+///     - wakes anyone discovering a path to the route's locator
 pub fn insert_into_forwarding_table(entry: (u64, u64, String, u8), ttl:u64) 
     -> Result<(), String>
 {
-    // generate key
-    let mut hasher = DefaultHasher::new();
-    let key = (entry.0, entry.1);
-    key.hash(&mut hasher);
-    let hash = hasher.finish();
-
-    // insert into forwarding table
-    match LOCATOR_FORWARDING_TABLE.lock() {
+    let (next_hop, locator, interface_name, hop_count) = entry;
+    LOCATOR_FORWARDING_TABLE.insert((next_hop, locator), (interface_name, hop_count), Duration::from_secs(ttl));
+    PENDING_PATH_TABLE.resolve_matching(|(pending_locator, _)| *pending_locator == locator);
+    Ok(())
+}
+pub fn lookup_forwarding_table(identifier: &u64, locator: &u64)
+    -> Result<(u64, u64, String, u8), String>
+{
+    match LOCATOR_FORWARDING_TABLE.get(&(*identifier, *locator)) {
+        Some((interface_name, hop_count)) => Ok((*identifier, *locator, interface_name, hop_count)),
+        None => Err("lookup_forwarding_table(): failed to entry in forwarding table".to_string())
+    }
+}
+/// Remove the routes through a next hop
+///     - every forwarding table entry and default router when no locator is given
+///     - only the forwarding table entry for the locator otherwise
+pub fn invalidate_routes_via(next_hop: &u64, locator: Option<&u64>)
+    -> Result<(), String>
+{
+    LOCATOR_FORWARDING_TABLE.remove_where(|(route_next_hop, route_locator), _| route_next_hop == next_hop && locator.is_none_or(|locator| route_locator == locator));
+    ROUTE_EPOCH.fetch_add(1, Ordering::Relaxed);
+    if locator.is_some() {
+        return Ok(());
+    }
+    match DEFAULT_ROUTER_TABLE.lock() {
         Ok(mut map) => {
-            map.insert(hash, entry, Duration::from_secs(ttl));
+            let keys: Vec<(u64, String)> = map.iter()
+                .filter(|((router_nid, _), _)| router_nid == next_hop)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                map.remove(&key);
+            }
             Ok(())
         },
         Err(err) => {
-            Err(format!("insert_into_forwarding_table(): failed to lock LOCATOR_FORWARDING_TABLE: {}", err))
+            Err(format!("invalidate_routes_via(): failed to lock DEFAULT_ROUTER_TABLE: {}", err))
         }
     }
 }
-pub fn lookup_forwarding_table(identifier: &u64, locator: &u64)
+/// Best route to a locator
+///     - the route with the fewest hops
+pub fn lookup_forwarding_table_route(locator: &u64)
     -> Result<(u64, u64, String, u8), String>
 {
-    match LOCATOR_FORWARDING_TABLE.lock() {
-        Ok(map) => {
-            for (_, entry) in map.clone().iter() {
-                if &entry.0 == identifier && &entry.1 == locator {
-                    return Ok(entry.clone());
-                }
+    match LOCATOR_FORWARDING_TABLE.lookup(locator).into_iter().min_by_key(|(_, (_, hop_count))| *hop_count) {
+        Some(((next_hop, locator), (interface_name, hop_count))) => Ok((next_hop, locator, interface_name, hop_count)),
+        None => Err("lookup_forwarding_table(): failed to entry in forwarding table".to_string())
+    }
+}
+// ******************************************************
+
+/// DEFAULT ROUTERS Action
+/// ******************************************************
+/// Insert a router from its advertisement
+///     - a lifetime of 0 withdraws the router
+///     - returns true if the router wasn't already known on that interface
+pub fn insert_into_default_router_table(router_nid: &u64, interface_name: &str, locators: Vec<u64>, lifetime: u16)
+    -> Result<bool, String>
+{
+    match DEFAULT_ROUTER_TABLE.lock() {
+        Ok(mut map) => {
+            let key = (*router_nid, interface_name.to_string());
+            if lifetime == 0 {
+                map.remove(&key);
+                return Ok(false);
             }
-            Err(format!("lookup_forwarding_table(): failed to entry in forwarding table"))
+            let previous = map.insert(key, locators, Duration::from_secs(lifetime as u64));
+            Ok(previous.is_none())
         },
         Err(err) => {
-            Err(format!("lookup_forwarding_table(): failed to lock LOCATOR_FORWARDING_TABLE: {}", err))
+            Err(format!("insert_into_default_router_table(): failed to lock DEFAULT_ROUTER_TABLE: {}", err))
         }
     }
 }
-pub fn lookup_forwarding_table_route(locator: &u64)
-    -> Result<(u64, u64, String, u8), String>
+/// Pick a default router for a locator
+///     - a router serving the locator is preferred, otherwise any router will do
+///     - returns (router NID, interface)
+pub fn lookup_default_router(locator: &u64)
+    -> Result<(u64, String), String>
 {
-    let mut result: (u64, u64, String, u8) = (0, 0, "".to_string(), 0);
-    match LOCATOR_FORWARDING_TABLE.lock() {
-        Ok(map) => {
-            for (_, entry) in map.clone().iter() {
-                if &entry.1 == locator && result.1 == 0 {
-                    result = entry.clone();
+    match DEFAULT_ROUTER_TABLE.lock() {
+        Ok(mut map) => {
+            let mut result: Option<(u64, String)> = None;
+            for ((router_nid, interface_name), locators) in map.iter() {
+                if locators.contains(locator) {
+                    return Ok((*router_nid, interface_name.clone()));
                 }
-                else if &entry.1 == locator && entry.3 < result.3 {
-                    result = entry.clone();
+                if result.is_none() {
+                    result = Some((*router_nid, interface_name.clone()));
                 }
             }
-            if result.0 != 0 && result.1 != 0 {
-                Ok(result)
-            } else {
-                Err(format!("lookup_forwarding_table(): failed to entry in forwarding table"))
-            }
+            result.ok_or("lookup_default_router(): no default router".to_string())
         },
         Err(err) => {
-            Err(format!("lookup_forwarding_table(): failed to lock LOCATOR_FORWARDING_TABLE: {}", err))
+            Err(format!("lookup_default_router(): failed to lock DEFAULT_ROUTER_TABLE: {}", err))
         }
     }
 }
@@ -290,13 +633,9 @@ pub fn lookup_forwarding_table_route(locator: &u64)
 
 /* 
 pub fn print_forwarding_table() {
-    match LOCATOR_FORWARDING_TABLE.lock() {
-        Ok(map) => {
-            println!();
-            for (key, value) in map.clone().iter() {
-                println!("{:?} => (0x{:016X}, 0x{:016X}, {:?}, {:?})", key, value.0, value.1, value.2, value.3);
-            }
-        },
-        Err(_) => {}
+    for locator in get_over_locators().unwrap_or_default() {
+        for ((next_hop, locator), (interface_name, hop_count)) in LOCATOR_FORWARDING_TABLE.lookup(&locator) {
+            println!("(0x{:016X}, 0x{:016X}, {:?}, {:?})", next_hop, locator, interface_name, hop_count);
+        }
     }
-} */
\ No newline at end of file
+} */
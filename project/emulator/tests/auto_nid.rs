@@ -0,0 +1,25 @@
+//! NID left unset (tests/fixtures/Config.toml without node.nid)
+
+mod harness;
+
+use std::env;
+
+use emulator::services::config_services::get_config;
+
+const AUTO_NID: &str = r#"
+[dad]
+auto_nid = true
+"#;
+
+#[test]
+fn unset_nid_is_generated_only_with_auto_nid() {
+    env::set_var("EMULATOR_CONFIG", harness::config_file(AUTO_NID, &["node.nid"]));
+    let first = get_config().unwrap().node.nid;
+    let second = get_config().unwrap().node.nid;
+    assert_ne!(first, 0);
+    assert_ne!(first, u64::MAX);
+    assert_ne!(first, second);
+
+    env::set_var("EMULATOR_CONFIG", harness::config_file("", &["node.nid"]));
+    assert!(get_config().unwrap_err().contains("node.nid is not set"));
+}
@@ -0,0 +1,119 @@
+//! Batched unicast I/O (recvmmsg/sendmmsg, GSO and GRO) over loopback
+
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use bytes::BytesMut;
+use emulator::layers::underlay_network::batch_io::{enable_gro, gso_groups, recv_batch, send_batch, MAX_SEGMENTS};
+use tokio::net::UdpSocket;
+
+fn destination(port: u16) -> SocketAddrV6 {
+    SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0, 0)
+}
+
+#[test]
+fn runs_of_equal_packets_to_one_destination_are_grouped() {
+    let full = [0u8; 100];
+    let short = [0u8; 40];
+    let packets = vec![
+        (destination(1), &full[..]),
+        (destination(1), &full[..]),
+        (destination(1), &short[..]),
+        (destination(1), &full[..]),
+        (destination(2), &full[..]),
+        (destination(2), &short[..])
+    ];
+
+    // a shorter packet ends its group, a new destination starts one
+    assert_eq!(gso_groups(&packets, true), vec![0..3, 3..4, 4..6]);
+
+    // without gso every packet goes on its own
+    assert_eq!(gso_groups(&packets, false), (0..6).map(|index| index..index + 1).collect::<Vec<_>>());
+}
+
+#[test]
+fn groups_never_exceed_the_segment_limits() {
+    let small = [0u8; 10];
+    let packets = vec![(destination(1), &small[..]); MAX_SEGMENTS + 1];
+    assert_eq!(gso_groups(&packets, true), vec![0..MAX_SEGMENTS, MAX_SEGMENTS..MAX_SEGMENTS + 1]);
+
+    // nor the largest UDP payload
+    let large = [0u8; 30000];
+    let packets = vec![(destination(1), &large[..]); 3];
+    assert_eq!(gso_groups(&packets, true), vec![0..2, 2..3]);
+
+    // a larger packet can't follow
+    let packets = vec![(destination(1), &small[..]), (destination(1), &large[..])];
+    assert_eq!(gso_groups(&packets, true), vec![0..1, 1..2]);
+}
+
+/// Send packets of the given sizes in one batch and receive them all back
+fn round_trip(sizes: &[usize], gso: bool, gro: bool) -> Vec<(Vec<u8>, SocketAddr)> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let sender = UdpSocket::bind(destination(0)).await.unwrap();
+        let receiver = UdpSocket::bind(destination(0)).await.unwrap();
+        if gro {
+            let _ = enable_gro(&receiver);
+        }
+        let to = destination(receiver.local_addr().unwrap().port());
+
+        let payloads: Vec<Vec<u8>> = sizes.iter().enumerate().map(|(index, size)| vec![index as u8; *size]).collect();
+        let packets: Vec<(SocketAddrV6, &[u8])> = payloads.iter().map(|payload| (to, payload.as_slice())).collect();
+        send_batch(&sender, &packets, gso).await.unwrap();
+
+        let mut buf = BytesMut::new();
+        let mut received = Vec::new();
+        while received.len() < sizes.len() {
+            let mut datagrams = Vec::new();
+            tokio::time::timeout(std::time::Duration::from_secs(2), recv_batch(&receiver, &mut buf, 1500, 8, gro, &mut datagrams)).await
+                .expect("batch never arrived")
+                .unwrap();
+            received.extend(datagrams.into_iter().map(|(datagram, addr)| (datagram.to_vec(), addr)));
+        }
+        received
+    })
+}
+
+#[test]
+fn a_batch_arrives_in_order_and_intact() {
+    let sizes = [1000, 1000, 1000, 300, 1000, 64];
+    for (gso, gro) in [(false, false), (true, false), (true, true)] {
+        let received = round_trip(&sizes, gso, gro);
+        assert_eq!(received.len(), sizes.len(), "gso {} gro {}", gso, gro);
+        for (index, (datagram, addr)) in received.iter().enumerate() {
+            assert_eq!(datagram, &vec![index as u8; sizes[index]], "gso {} gro {}", gso, gro);
+            assert_eq!(addr.ip(), Ipv6Addr::LOCALHOST);
+        }
+    }
+}
+
+#[test]
+fn a_batch_larger_than_the_receive_batch_takes_several_calls() {
+    let sizes = vec![200; 20];
+    let received = round_trip(&sizes, false, false);
+    assert_eq!(received.len(), 20);
+    assert!(received.iter().enumerate().all(|(index, (datagram, _))| datagram[0] == index as u8));
+}
+
+#[test]
+fn gro_still_takes_a_full_batch_per_call() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let sender = UdpSocket::bind(destination(0)).await.unwrap();
+        let receiver = UdpSocket::bind(destination(0)).await.unwrap();
+        let _ = enable_gro(&receiver);
+        let to = destination(receiver.local_addr().unwrap().port());
+
+        // growing sizes so the kernel can't coalesce them
+        let payloads: Vec<Vec<u8>> = (1..=8).map(|index| vec![index as u8; index * 100]).collect();
+        let packets: Vec<(SocketAddrV6, &[u8])> = payloads.iter().map(|payload| (to, payload.as_slice())).collect();
+        send_batch(&sender, &packets, false).await.unwrap();
+
+        let mut buf = BytesMut::new();
+        let mut datagrams = Vec::new();
+        tokio::time::timeout(std::time::Duration::from_secs(2), recv_batch(&receiver, &mut buf, 1500, 8, true, &mut datagrams)).await
+            .expect("batch never arrived")
+            .unwrap();
+        assert_eq!(datagrams.len(), 8);
+    });
+}
@@ -0,0 +1,144 @@
+//! pcapng captures written by the node and read back by ilnp-dump
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use emulator::services::capture_services::{CaptureDirection, PcapngWriter, LINKTYPE_ILNP, PCAPNG_BYTE_ORDER_MAGIC, PCAPNG_EPB, PCAPNG_IDB, PCAPNG_SHB};
+use emulator::services::dump_services::read_capture;
+
+// a fresh file per test
+fn capture_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("emulator-capture-{}-{}.pcapng", std::process::id(), name))
+}
+
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let total_length = (12 + body.len()) as u32;
+    let mut block = Vec::new();
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&total_length.to_le_bytes());
+    block.extend_from_slice(body);
+    block.extend_from_slice(&total_length.to_le_bytes());
+    block
+}
+
+// section header and one interface, tsresol as an if_tsresol option if given
+fn header(tsresol: Option<u8>) -> Vec<u8> {
+    let mut shb = Vec::new();
+    shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+    shb.extend_from_slice(&1u16.to_le_bytes());
+    shb.extend_from_slice(&0u16.to_le_bytes());
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+
+    let mut idb = Vec::new();
+    idb.extend_from_slice(&LINKTYPE_ILNP.to_le_bytes());
+    idb.extend_from_slice(&0u16.to_le_bytes());
+    idb.extend_from_slice(&0u32.to_le_bytes());
+    if let Some(tsresol) = tsresol {
+        idb.extend_from_slice(&9u16.to_le_bytes());
+        idb.extend_from_slice(&1u16.to_le_bytes());
+        idb.extend_from_slice(&[tsresol, 0, 0, 0]);
+    }
+
+    let mut bytes = block(PCAPNG_SHB, &shb);
+    bytes.extend(block(PCAPNG_IDB, &idb));
+    bytes
+}
+
+fn epb(ticks: u64, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((ticks >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(ticks as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(data);
+    while body.len() % 4 != 0 {
+        body.push(0);
+    }
+    block(PCAPNG_EPB, &body)
+}
+
+fn read(name: &str, bytes: &[u8]) -> Result<Vec<(u64, Vec<u8>)>, String> {
+    let path = capture_path(name);
+    fs::write(&path, bytes).unwrap();
+    let result = read_capture(path.to_str().unwrap());
+    let _ = fs::remove_file(&path);
+    result.map(|packets| packets.into_iter().map(|packet| (packet.timestamp_us, packet.data)).collect())
+}
+
+#[test]
+fn written_packets_read_back_unchanged() {
+    let path = capture_path("round-trip");
+    let packets = [
+        (CaptureDirection::Outbound, 1_700_000_000_000_001, vec![0x60; 40]),
+        (CaptureDirection::Inbound, 1_700_000_000_000_002, vec![0x61; 43]),
+        (CaptureDirection::Inbound, 1_700_000_000_000_003, Vec::new())
+    ];
+
+    let mut writer = PcapngWriter::create(path.to_str().unwrap(), "node1", LINKTYPE_ILNP).unwrap();
+    for (direction, timestamp_us, data) in &packets {
+        writer.write_packet(*direction, *timestamp_us, data).unwrap();
+    }
+    writer.flush().unwrap();
+
+    let read = read_capture(path.to_str().unwrap()).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(read.len(), packets.len());
+    for (packet, (direction, timestamp_us, data)) in read.iter().zip(&packets) {
+        assert_eq!(packet.direction, Some(*direction));
+        assert_eq!(packet.timestamp_us, *timestamp_us);
+        assert_eq!(&packet.data, data);
+    }
+}
+
+#[test]
+fn timestamps_follow_the_interface_resolution() {
+    // nanoseconds and milliseconds
+    let mut bytes = header(Some(9));
+    bytes.extend(epb(5_000_123_456, &[1; 4]));
+    assert_eq!(read("tsresol-ns", &bytes).unwrap(), vec![(5_000_123, vec![1; 4])]);
+
+    let mut bytes = header(Some(3));
+    bytes.extend(epb(5_000, &[2; 4]));
+    assert_eq!(read("tsresol-ms", &bytes).unwrap(), vec![(5_000_000, vec![2; 4])]);
+}
+
+#[test]
+fn timestamps_out_of_range_are_an_error() {
+    // seconds that don't fit in microseconds
+    let mut bytes = header(Some(0));
+    bytes.extend(epb(u64::MAX / 2, &[1; 4]));
+    assert!(read("tsresol-overflow", &bytes).is_err());
+}
+
+#[test]
+fn truncated_blocks_are_an_error() {
+    // block length past the end of the file
+    let mut bytes = header(None);
+    let packet = epb(1, &[3; 40]);
+    bytes.extend_from_slice(&packet[..packet.len() - 8]);
+    assert!(read("truncated-file", &bytes).is_err());
+
+    // captured length past the end of the block
+    let mut bytes = header(None);
+    let mut packet = epb(1, &[3; 8]);
+    packet[20..24].copy_from_slice(&100u32.to_le_bytes());
+    bytes.extend(packet);
+    assert!(read("truncated-epb", &bytes).is_err());
+}
+
+#[test]
+fn simple_packet_blocks_never_read_past_the_block() {
+    // empty body
+    let mut bytes = header(None);
+    bytes.extend(block(0x00000003, &[]));
+
+    // original length larger than the captured data
+    let mut body = 1000u32.to_le_bytes().to_vec();
+    body.extend_from_slice(&[4; 8]);
+    bytes.extend(block(0x00000003, &body));
+
+    let packets = read("spb", &bytes).unwrap();
+    assert_eq!(packets.last().unwrap().1, vec![4; 8]);
+}
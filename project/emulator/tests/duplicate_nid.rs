@@ -0,0 +1,48 @@
+//! Duplicate NID detection (tests/fixtures/Config.toml)
+
+mod harness;
+
+use std::sync::atomic::Ordering;
+
+use emulator::layers::overlay_network::{DAD_NONCE, DUPLICATE_NID};
+use emulator::models::network_packets::JcmpMessage;
+
+#[test]
+fn probes_and_defences_for_our_nid_are_conflicts() {
+
+    // our own probe looping back
+    let before = harness::pcb();
+    harness::fuzz_multicast(&harness::jcmp(harness::LOCAL_NID, harness::LOCAL_NID, &JcmpMessage::DuplicateNidProbe { nonce: *DAD_NONCE }));
+    let after = harness::pcb();
+    assert_eq!(after.duplicate_nid_probe_jcmp_rx, before.duplicate_nid_probe_jcmp_rx);
+    assert_eq!(after.duplicate_nid_detected, before.duplicate_nid_detected);
+
+    // someone else probing for another NID
+    let before = after;
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::PEER_NID, &JcmpMessage::DuplicateNidProbe { nonce: DAD_NONCE.wrapping_add(1) }));
+    let after = harness::pcb();
+    assert_eq!(after.duplicate_nid_probe_jcmp_rx, before.duplicate_nid_probe_jcmp_rx);
+    assert!(!DUPLICATE_NID.load(Ordering::Relaxed));
+
+    // someone else probing for ours is defended
+    let before = after;
+    harness::fuzz_multicast(&harness::jcmp(harness::LOCAL_NID, harness::LOCAL_NID, &JcmpMessage::DuplicateNidProbe { nonce: DAD_NONCE.wrapping_add(1) }));
+    let after = harness::pcb();
+    assert_eq!(after.duplicate_nid_probe_jcmp_rx, before.duplicate_nid_probe_jcmp_rx + 1);
+    assert_eq!(after.duplicate_nid_defend_jcmp_tx, before.duplicate_nid_defend_jcmp_tx + 1);
+    assert_eq!(after.duplicate_nid_detected, before.duplicate_nid_detected + 1);
+    assert!(DUPLICATE_NID.load(Ordering::Relaxed));
+
+    // a defence of another node's probe isn't ours
+    let before = after;
+    harness::fuzz_multicast(&harness::jcmp(harness::LOCAL_NID, harness::LOCAL_NID, &JcmpMessage::DuplicateNidDefend { nonce: DAD_NONCE.wrapping_add(1) }));
+    let after = harness::pcb();
+    assert_eq!(after.duplicate_nid_defend_jcmp_rx, before.duplicate_nid_defend_jcmp_rx);
+
+    // a defence of our probe
+    let before = after;
+    harness::fuzz_multicast(&harness::jcmp(harness::LOCAL_NID, harness::LOCAL_NID, &JcmpMessage::DuplicateNidDefend { nonce: *DAD_NONCE }));
+    let after = harness::pcb();
+    assert_eq!(after.duplicate_nid_defend_jcmp_rx, before.duplicate_nid_defend_jcmp_rx + 1);
+    assert_eq!(after.duplicate_nid_detected, before.duplicate_nid_detected + 1);
+}
@@ -0,0 +1,66 @@
+//! Routers forward the received packet in place, only the hop limit changes
+
+mod harness;
+
+use std::net::{Ipv6Addr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use emulator::models::network_packets::JcmpMessage;
+
+// a node on our network 1
+const NEIGHBOUR_NID: u64 = 0x0000000000000050;
+
+// JTP from the harness peer to the neighbour, through us
+fn data(hop_limit: u8, payload: &[u8]) -> Vec<u8> {
+    let header = harness::header(151, harness::PEER_NID, NEIGHBOUR_NID)
+        .with_flow_label(0x12345)
+        .with_hop_limit(hop_limit);
+    harness::packet(header, payload)
+}
+
+// the neighbour advertises a port on [::1] we listen on
+fn neighbour() -> UdpSocket {
+    let sink = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+    sink.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let port = sink.local_addr().unwrap().port();
+    harness::fuzz_multicast(&harness::jcmp(NEIGHBOUR_NID, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: port }));
+    sink
+}
+
+// the tests compare counters
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn the_received_packet_is_sent_on_with_the_hop_limit_decremented() {
+    let _serial = SERIAL.lock().unwrap();
+    let sink = neighbour();
+
+    let sent = data(64, &[0x5A; 300]);
+    let before = harness::pcb();
+    harness::fuzz_unicast(&sent);
+
+    let mut buf = [0; 1500];
+    let len = sink.recv(&mut buf).unwrap();
+    let mut expected = sent.clone();
+    expected[7] = 63;
+    assert_eq!(&buf[..len], &expected[..]);
+    assert_eq!(harness::pcb().data_request_forward_tx - before.data_request_forward_tx, 1);
+}
+
+#[test]
+fn packets_out_of_hops_are_dropped() {
+    let _serial = SERIAL.lock().unwrap();
+    let sink = neighbour();
+    sink.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+
+    let before = harness::pcb();
+    for hop_limit in [0, 1] {
+        harness::fuzz_unicast(&data(hop_limit, &[0x5A; 8]));
+    }
+
+    let after = harness::pcb();
+    assert_eq!(after.drops.hop_limit_exceeded - before.drops.hop_limit_exceeded, 2);
+    assert_eq!(after.data_request_forward_tx, before.data_request_forward_tx);
+    assert!(sink.recv(&mut [0; 1500]).is_err());
+}
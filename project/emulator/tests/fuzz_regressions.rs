@@ -0,0 +1,24 @@
+//! Replays every checked in fuzz reproducer (fuzz/regressions/<target>/*) through its harness
+//!     - native wire format, tests/fuzz_regressions_rfc6741.rs replays them in the rfc6741 format
+
+mod harness;
+
+#[test]
+fn multicast_buffer_regressions() {
+    harness::replay("multicast_buffer", harness::fuzz_multicast);
+}
+
+#[test]
+fn unicast_buffer_regressions() {
+    harness::replay("unicast_buffer", harness::fuzz_unicast);
+}
+
+#[test]
+fn jcmp_packet_regressions() {
+    harness::replay("jcmp_packet", harness::fuzz_jcmp);
+}
+
+#[test]
+fn jcmp_decode_regressions() {
+    harness::replay("jcmp_decode", harness::fuzz_jcmp_decode);
+}
@@ -0,0 +1,27 @@
+//! Replays every checked in fuzz reproducer (fuzz/regressions/<target>/*) with the rfc6741 wire format
+
+mod harness;
+
+#[test]
+fn multicast_buffer_regressions() {
+    harness::use_config(harness::RFC6741);
+    harness::replay("multicast_buffer", harness::fuzz_multicast);
+}
+
+#[test]
+fn unicast_buffer_regressions() {
+    harness::use_config(harness::RFC6741);
+    harness::replay("unicast_buffer", harness::fuzz_unicast);
+}
+
+#[test]
+fn jcmp_packet_regressions() {
+    harness::use_config(harness::RFC6741);
+    harness::replay("jcmp_packet", harness::fuzz_jcmp);
+}
+
+#[test]
+fn jcmp_decode_regressions() {
+    harness::use_config(harness::RFC6741);
+    harness::replay("jcmp_decode", harness::fuzz_jcmp_decode);
+}
@@ -0,0 +1,302 @@
+// shared by the fuzz targets (fuzz/fuzz_targets) and the integration tests (tests/*.rs)
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use once_cell::sync::Lazy;
+use tokio::runtime::Runtime;
+
+use emulator::layers::jtp_network::{jtp_connect, JtpFlow};
+use emulator::layers::overlay_network::{CONFIG, PCB};
+use emulator::layers::overlay_network::overlay_handlers::{handle_destination_nid, handle_ilnp_multicast_buffer, handle_ilnp_unicast_buffer, handle_path_discovery};
+use emulator::layers::underlay_network::open_loopback_underlay_socket;
+use emulator::models::config_models::WireFormat;
+use emulator::models::network_models::EmulatorSocket;
+use emulator::models::network_packets::{INLPv6Packet, JcmpMessage};
+use emulator::models::protocol_control_block::ILNP_PCB_S;
+use emulator::models::wire_format::encode_packet;
+use emulator::services::network_services::{get_over_interface_by_locator, get_over_interfaces, get_over_locators};
+
+/// NID of the node under test (tests/fixtures/Config.toml)
+pub const LOCAL_NID: u64 = 0x0000000000000001;
+
+/// NID used for the peer sending the packets
+pub const PEER_NID: u64 = 0x0000000000000002;
+
+/// Config overrides for the rfc6741 wire format
+pub const RFC6741: &str = r#"
+[wire]
+format = "rfc6741"
+"#;
+
+/// Point the node at the fixture config (tests/fixtures/Config.toml) with some keys changed
+///     - overrides is TOML merged over the fixture, tables are merged key by key
+///     - has to run before CONFIG is first used, the first call wins
+pub fn use_config(overrides: &str) {
+    if env::var("EMULATOR_CONFIG").is_ok() {
+        return;
+    }
+    env::set_var("EMULATOR_CONFIG", config_file(overrides, &[]));
+}
+
+/// Write the fixture config (tests/fixtures/Config.toml) with some keys changed to a new file
+///     - overrides is TOML merged over the fixture, tables are merged key by key
+///     - removed are dotted keys (table.key) left out of the file
+pub fn config_file(overrides: &str, removed: &[&str]) -> PathBuf {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let fixture = ["tests/fixtures/Config.toml", "../tests/fixtures/Config.toml"].iter()
+        .map(|candidate| manifest_dir.join(candidate))
+        .find(|path| path.exists())
+        .expect("failed to find tests/fixtures/Config.toml");
+
+    let mut config: toml::Table = fs::read_to_string(&fixture).unwrap().parse().unwrap();
+    let overrides: toml::Table = overrides.parse().unwrap_or_else(|err| panic!("invalid config overrides: {}", err));
+    merge_config(&mut config, overrides);
+    for key in removed {
+        remove_config_key(&mut config, key);
+    }
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!("emulator-config-{}-{}.toml", std::process::id(), FILES.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+    path
+}
+
+fn remove_config_key(config: &mut toml::Table, key: &str) {
+    match key.split_once('.') {
+        Some((table, key)) => {
+            if let Some(toml::Value::Table(table)) = config.get_mut(table) {
+                remove_config_key(table, key);
+            }
+        },
+        None => {
+            config.remove(key);
+        }
+    }
+}
+
+fn merge_config(config: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (config.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(value)) => merge_config(table, value),
+            (_, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
+}
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+
+    // the fixture as it is unless a test picked overrides already
+    use_config("");
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime")
+});
+
+static SOCKET: Lazy<(EmulatorSocket, Vec<u64>)> = Lazy::new(|| {
+    RUNTIME.block_on(async {
+        let socket = open_loopback_underlay_socket().await.expect("failed to open loopback underlay");
+        let connected_locators = get_over_locators().expect("failed to get locators");
+        (socket, connected_locators)
+    })
+});
+
+fn peer_address() -> SocketAddr {
+    SocketAddr::from((Ipv6Addr::LOCALHOST, 9))
+}
+
+/// ILNPv6 header between two NIDs on locator 1
+///     - hop limit 1, anything else is changed with the with_* setters
+pub fn header(next_header: u8, source_identifier: u64, destination_identifier: u64) -> INLPv6Packet {
+    INLPv6Packet::new()
+        .with_version(6)
+        .with_next_header(next_header)
+        .with_hop_limit(1)
+        .with_source_locator(1)
+        .with_source_identifier(source_identifier)
+        .with_destination_locator(1)
+        .with_destination_identifier(destination_identifier)
+}
+
+/// Header followed by the payload, payload_length covers the payload
+pub fn packet(header: INLPv6Packet, payload: &[u8]) -> Vec<u8> {
+    let mut pck = header.with_payload_length(payload.len() as u16).into_bytes().to_vec();
+    pck.extend_from_slice(payload);
+    pck
+}
+
+/// Header followed by the payload laid out in the rfc6741 wire format
+pub fn rfc6741_packet(header: INLPv6Packet, nonce: Option<u32>, payload: &[u8]) -> Vec<u8> {
+    encode_packet(WireFormat::Rfc6741, header.with_payload_length(payload.len() as u16), nonce, payload)
+}
+
+/// JCMP message between two NIDs on locator 1
+pub fn jcmp(source_identifier: u64, destination_identifier: u64, message: &JcmpMessage) -> Vec<u8> {
+    packet(header(150, source_identifier, destination_identifier), &message.encode())
+}
+
+/// Snapshot of the node's PCB counters
+pub fn pcb() -> ILNP_PCB_S {
+    *PCB.lock().unwrap()
+}
+
+/// Raw bytes on the multicast socket
+pub fn fuzz_multicast(data: &[u8]) {
+    let (socket, connected_locators) = &*SOCKET;
+    RUNTIME.block_on(handle_ilnp_multicast_buffer(socket, connected_locators, data, data.len(), peer_address()));
+}
+
+/// Raw bytes on the unicast socket
+pub fn fuzz_unicast(data: &[u8]) {
+    let (socket, _) = &*SOCKET;
+    let mut buf = data.to_vec();
+    RUNTIME.block_on(handle_ilnp_unicast_buffer(socket, &mut buf, data.len(), peer_address()));
+}
+
+/// Address resolution for a NID on the network with the given locator
+///     - the (IPv6, port) the node would send to
+pub fn resolve_nid(nid: u64, locator: u64) -> Result<(Ipv6Addr, u16), String> {
+    let (socket, _) = &*SOCKET;
+    let interface_name = get_over_interface_by_locator(&locator)?;
+    RUNTIME.block_on(handle_destination_nid(socket, &nid, &interface_name))
+}
+
+/// Concurrent address resolutions for the same NID
+///     - count lookups started together
+///     - the advertisement, if any, arrives on the multicast socket after the given delay
+pub fn resolve_nid_concurrently(nid: u64, locator: u64, count: usize, advertisement: Option<(std::time::Duration, Vec<u8>)>) -> Vec<Result<(Ipv6Addr, u16), String>> {
+    let (socket, _) = &*SOCKET;
+    let interface_name = get_over_interface_by_locator(&locator).expect("no interface for locator");
+    concurrently(count, advertisement, move || {
+        let interface_name = interface_name.clone();
+        async move { handle_destination_nid(socket, &nid, &interface_name).await }
+    })
+}
+
+/// Concurrent path discoveries for the same locator
+///     - count discoveries started together
+///     - the router response, if any, arrives on the multicast socket after the given delay
+pub fn discover_path_concurrently(locator: u64, count: usize, response: Option<(std::time::Duration, Vec<u8>)>) -> Vec<Result<(u64, u64, String, u8), String>> {
+    let (socket, _) = &*SOCKET;
+    concurrently(count, response, move || async move { handle_path_discovery(socket, None, &locator, &0).await })
+}
+
+/// Concurrent path discoveries for the same locator, one per network the request came from
+pub fn discover_path_from_every_network(locator: u64) -> Vec<Result<(u64, u64, String, u8), String>> {
+    let (socket, _) = &*SOCKET;
+    let interfaces: Vec<String> = get_over_interfaces().expect("failed to get interfaces").into_iter().map(|(interface_name, _)| interface_name).collect();
+    RUNTIME.block_on(async move {
+        let local = tokio::task::LocalSet::new();
+        local.run_until(async move {
+            let tasks: Vec<_> = interfaces.into_iter()
+                .map(|interface_name| tokio::task::spawn_local(async move { handle_path_discovery(socket, Some(&interface_name), &locator, &0).await }))
+                .collect();
+
+            let mut results = Vec::new();
+            for task in tasks {
+                results.push(task.await.expect("task panicked"));
+            }
+            results
+        }).await
+    })
+}
+
+fn concurrently<F, Fut, T>(count: usize, reply: Option<(std::time::Duration, Vec<u8>)>, task: F) -> Vec<T>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = T> + 'static,
+    T: 'static,
+{
+    let (socket, connected_locators) = &*SOCKET;
+    RUNTIME.block_on(async move {
+        let local = tokio::task::LocalSet::new();
+        local.run_until(async move {
+            let tasks: Vec<_> = (0..count).map(|_| tokio::task::spawn_local(task())).collect();
+            if let Some((delay, pck)) = reply {
+                tokio::time::sleep(delay).await;
+                handle_ilnp_multicast_buffer(socket, connected_locators, &pck, pck.len(), peer_address()).await;
+            }
+
+            let mut results = Vec::new();
+            for task in tasks {
+                results.push(task.await.expect("task panicked"));
+            }
+            results
+        }).await
+    })
+}
+
+/// Open a JTP flow from the node under test
+pub fn connect(fqdn: &str) -> Result<JtpFlow, String> {
+    let (socket, _) = &*SOCKET;
+    RUNTIME.block_on(jtp_connect(socket, fqdn))
+}
+
+/// Send on a JTP flow
+pub fn send(flow: &mut JtpFlow, buf: &[u8]) -> Result<(), String> {
+    RUNTIME.block_on(flow.send(buf))
+}
+
+/// Let the tasks spawned by the handlers run
+pub fn run_for(duration: std::time::Duration) {
+    RUNTIME.block_on(async move { tokio::time::sleep(duration).await });
+}
+
+/// A sequence of JCMP payloads behind a valid ILNP header
+///     - each packet is [length][flags][payload]
+///     - flags bit 0: addressed to us (otherwise to another NID)
+///     - flags bit 1: received on the second network
+pub fn fuzz_jcmp(data: &[u8]) {
+
+    // picks the fixture config before CONFIG is read
+    Lazy::force(&RUNTIME);
+
+    let mut rest = data;
+    while let Some((&length, tail)) = rest.split_first() {
+        let Some((&flags, tail)) = tail.split_first() else { break };
+        let length = (length as usize).min(tail.len());
+        let (payload, tail) = tail.split_at(length);
+        rest = tail;
+
+        let locator = if flags & 2 == 0 { 1 } else { 2 };
+        let destination_nid = if flags & 1 == 1 { LOCAL_NID } else { 0x0000000000000003 };
+        let header = header(150, PEER_NID, destination_nid)
+            .with_payload_length(payload.len() as u16)
+            .with_source_locator(locator)
+            .with_destination_locator(locator);
+
+        // laid out in the wire format of the fixture
+        let pck = encode_packet(CONFIG.wire.format, header, Some(1), payload);
+        fuzz_multicast(&pck);
+    }
+}
+
+/// JCMP decoding on its own
+///     - anything that decodes must encode back to the same bytes
+pub fn fuzz_jcmp_decode(data: &[u8]) {
+    if let Ok(message) = JcmpMessage::decode(data) {
+        assert_eq!(message.encode(), data);
+    }
+}
+
+/// Replay every checked in reproducer of a target (fuzz/regressions/<target>/*)
+pub fn replay(target: &str, run: fn(&[u8])) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions").join(target);
+    let mut entries: Vec<_> = fs::read_dir(&directory)
+        .unwrap_or_else(|err| panic!("failed to read {:?}: {}", directory, err))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+
+    for path in entries {
+        let data = fs::read(&path).unwrap();
+        run(&data);
+    }
+}
@@ -0,0 +1,54 @@
+//! Malformed ILNP headers are counted and dropped before dispatch (validation defaults to drop)
+
+mod harness;
+
+fn unicast_packet(version: u8, payload_length: u16, source_identifier: u64, payload: &[u8]) -> Vec<u8> {
+    let header = harness::header(151, source_identifier, harness::LOCAL_NID)
+        .with_version(version)
+        .with_payload_length(payload_length);
+
+    let mut pck = header.into_bytes().to_vec();
+    pck.extend_from_slice(payload);
+    pck
+}
+
+#[test]
+fn padded_datagram_is_dropped() {
+    let before = harness::pcb();
+    harness::fuzz_unicast(&unicast_packet(6, 4, harness::PEER_NID, &[0; 12]));
+    let after = harness::pcb();
+
+    assert!(after.validation.trailing_bytes > before.validation.trailing_bytes);
+    assert!(after.drops.trailing_bytes > before.drops.trailing_bytes);
+}
+
+#[test]
+fn truncated_payload_is_dropped() {
+    let before = harness::pcb();
+    harness::fuzz_unicast(&unicast_packet(6, 64, harness::PEER_NID, &[0; 8]));
+    let after = harness::pcb();
+
+    assert!(after.validation.length_mismatch > before.validation.length_mismatch);
+    assert!(after.drops.length_mismatch > before.drops.length_mismatch);
+}
+
+#[test]
+fn wrong_version_is_dropped() {
+    let before = harness::pcb();
+    harness::fuzz_unicast(&unicast_packet(4, 0, harness::PEER_NID, &[]));
+    let after = harness::pcb();
+
+    assert!(after.validation.wrong_version > before.validation.wrong_version);
+    assert!(after.drops.wrong_version > before.drops.wrong_version);
+}
+
+#[test]
+fn unspecified_source_is_dropped() {
+    let before = harness::pcb();
+    harness::fuzz_unicast(&unicast_packet(6, 0, 0, &[]));
+    harness::fuzz_unicast(&unicast_packet(6, 0, u64::MAX, &[]));
+    let after = harness::pcb();
+
+    assert!(after.validation.invalid_source >= before.validation.invalid_source + 2);
+    assert!(after.drops.invalid_source >= before.drops.invalid_source + 2);
+}
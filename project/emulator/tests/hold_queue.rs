@@ -0,0 +1,122 @@
+//! Packets held while their route is discovered (tests/fixtures/Config.toml with a small hold queue)
+
+mod harness;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use emulator::models::network_packets::JcmpMessage;
+use emulator::services::network_services::{insert_into_forwarding_table, lookup_forwarding_table_route};
+
+const HOLD: &str = r#"
+[network]
+AD_HOC_TIMEOUT_MS = 200
+
+[forwarding]
+hold_queue_size = 2
+hold_timeout_ms = 1000
+"#;
+
+// JTP from the harness peer to a node behind an unknown locator
+fn data(locator: u64) -> Vec<u8> {
+    let header = harness::header(151, harness::PEER_NID, 0x0000000000000040)
+        .with_hop_limit(64)
+        .with_destination_locator(locator);
+    harness::packet(header, &[0x44; 8])
+}
+
+// the tests compare counters
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn packets_are_held_without_blocking_and_the_queue_is_bounded() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(HOLD);
+    const LOCATOR: u64 = 0x00000000000000F0;
+
+    let before = harness::pcb();
+    let started = Instant::now();
+    for _ in 0..3 {
+        harness::fuzz_unicast(&data(LOCATOR));
+    }
+
+    // nobody waited for the 200 ms discovery
+    assert!(started.elapsed() < Duration::from_millis(100));
+    let after = harness::pcb();
+    assert_eq!(after.data_request_held - before.data_request_held, 2);
+    assert_eq!(after.drops.hold_queue_full - before.drops.hold_queue_full, 1);
+
+    // one flood for the locator
+    harness::run_for(Duration::from_millis(300));
+    assert_eq!(harness::pcb().router_request_jcmp_tx - before.router_request_jcmp_tx, 2);
+}
+
+#[test]
+fn held_packets_are_dropped_with_one_unreachable_when_discovery_fails() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(HOLD);
+    const LOCATOR: u64 = 0x00000000000000F1;
+
+    let before = harness::pcb();
+    harness::fuzz_unicast(&data(LOCATOR));
+    harness::fuzz_unicast(&data(LOCATOR));
+    harness::run_for(Duration::from_millis(300));
+
+    let after = harness::pcb();
+    assert_eq!(after.drops.forward_failed - before.drops.forward_failed, 2);
+    assert_eq!(after.destination_unreachable_jcmp_tx - before.destination_unreachable_jcmp_tx, 1);
+    assert_eq!(after.data_request_forward_tx, before.data_request_forward_tx);
+}
+
+#[test]
+fn held_packets_are_flushed_once_the_route_is_found() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(HOLD);
+    const LOCATOR: u64 = 0x00000000000000F2;
+
+    // the peer is the next hop, it answers from [::1]:9
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: 9 }));
+
+    let before = harness::pcb();
+    harness::fuzz_unicast(&data(LOCATOR));
+    harness::fuzz_unicast(&data(LOCATOR));
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &JcmpMessage::RouterResponse { hop_count: 1, destination_locator: LOCATOR, ttl: 30 }));
+    harness::run_for(Duration::from_millis(50));
+
+    let after = harness::pcb();
+    assert_eq!(after.data_request_forward_tx - before.data_request_forward_tx, 2);
+    assert_eq!(after.drops.forward_failed, before.drops.forward_failed);
+
+    // the route is known now, no more holding
+    harness::fuzz_unicast(&data(LOCATOR));
+    let last = harness::pcb();
+    assert_eq!(last.data_request_held, after.data_request_held);
+    assert_eq!(last.data_request_forward_tx - after.data_request_forward_tx, 1);
+}
+
+#[test]
+fn unreachable_reports_move_traffic_to_another_route() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(HOLD);
+    const LOCATOR: u64 = 0x00000000000000F3;
+    const OTHER_ROUTER: u64 = 0x0000000000000041;
+
+    // both routers answer from [::1]:9, the peer is one hop closer
+    for router in [harness::PEER_NID, OTHER_ROUTER] {
+        harness::fuzz_multicast(&harness::jcmp(router, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: 9 }));
+    }
+    insert_into_forwarding_table((harness::PEER_NID, LOCATOR, String::from("lo"), 1), 30).unwrap();
+    insert_into_forwarding_table((OTHER_ROUTER, LOCATOR, String::from("lo"), 2), 30).unwrap();
+    assert_eq!(lookup_forwarding_table_route(&LOCATOR).unwrap().0, harness::PEER_NID);
+
+    // the peer can't reach the locator any more
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &JcmpMessage::DestinationUnreachable { destination_locator: LOCATOR, destination_nid: 0x0000000000000040 }));
+    assert_eq!(lookup_forwarding_table_route(&LOCATOR).unwrap().0, OTHER_ROUTER);
+
+    // traffic goes through the other router without holding
+    let before = harness::pcb();
+    harness::fuzz_unicast(&data(LOCATOR));
+    let after = harness::pcb();
+    assert_eq!(after.data_request_held, before.data_request_held);
+    assert_eq!(after.data_request_forward_tx - before.data_request_forward_tx, 1);
+}
@@ -0,0 +1,130 @@
+//! Indexed tables behind the DNS and forwarding lookups
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use emulator::models::indexed_table::IndexedTable;
+
+// (next hop, locator) -> (interface, hop count), indexed by locator like the forwarding table
+fn routes(capacity: usize) -> IndexedTable<(u64, u64), u64, (String, u8)> {
+    IndexedTable::new(capacity, |(_, locator), _| *locator)
+}
+
+fn sorted(mut entries: Vec<((u64, u64), (String, u8))>) -> Vec<((u64, u64), (String, u8))> {
+    entries.sort();
+    entries
+}
+
+#[test]
+fn lookups_only_see_their_index() {
+    let table = routes(16);
+    table.insert((1, 0xA), ("eth0".to_string(), 2), Duration::from_secs(30));
+    table.insert((2, 0xA), ("eth1".to_string(), 1), Duration::from_secs(30));
+    table.insert((1, 0xB), ("eth0".to_string(), 4), Duration::from_secs(30));
+
+    assert_eq!(sorted(table.lookup(&0xA)), vec![((1, 0xA), ("eth0".to_string(), 2)), ((2, 0xA), ("eth1".to_string(), 1))]);
+    assert_eq!(table.lookup(&0xB).len(), 1);
+    assert!(table.lookup(&0xC).is_empty());
+    assert_eq!(table.get(&(2, 0xA)), Some(("eth1".to_string(), 1)));
+}
+
+#[test]
+fn refreshing_an_entry_moves_it_between_index_values() {
+    // FQDN index like the name resolution table
+    let names: IndexedTable<(u64, u64), String, String> = IndexedTable::new(16, |_, fqdn| fqdn.clone());
+    assert_eq!(names.insert((1, 0xA), "old.local".to_string(), Duration::from_secs(30)), None);
+    assert_eq!(names.insert((1, 0xA), "new.local".to_string(), Duration::from_secs(30)), Some("old.local".to_string()));
+
+    assert!(names.lookup(&"old.local".to_string()).is_empty());
+    assert_eq!(names.lookup(&"new.local".to_string()), vec![((1, 0xA), "new.local".to_string())]);
+}
+
+#[test]
+fn expired_entries_are_not_returned_and_are_removed() {
+    let table = routes(16);
+    table.insert((1, 0xA), ("eth0".to_string(), 1), Duration::from_millis(20));
+    table.insert((2, 0xA), ("eth0".to_string(), 1), Duration::from_secs(30));
+    thread::sleep(Duration::from_millis(150));
+
+    assert_eq!(table.get(&(1, 0xA)), None);
+    assert_eq!(table.lookup(&0xA).len(), 1);
+
+    // the wheel clears it out on the next write, an expired entry isn't a previous value
+    assert_eq!(table.insert((1, 0xA), ("eth1".to_string(), 3), Duration::from_secs(30)), None);
+    assert_eq!(table.get(&(1, 0xA)), Some(("eth1".to_string(), 3)));
+}
+
+#[test]
+fn a_full_table_drops_the_entry_closest_to_expiring() {
+    let table = routes(2);
+    table.insert((1, 0xA), ("eth0".to_string(), 1), Duration::from_secs(60));
+    table.insert((2, 0xA), ("eth0".to_string(), 1), Duration::from_secs(5));
+    table.insert((3, 0xA), ("eth0".to_string(), 1), Duration::from_secs(30));
+
+    assert_eq!(table.get(&(2, 0xA)), None);
+    assert_eq!(sorted(table.lookup(&0xA)).iter().map(|(key, _)| *key).collect::<Vec<_>>(), vec![(1, 0xA), (3, 0xA)]);
+
+    // entries a wheel revolution or more away are compared by deadline too
+    let table = routes(2);
+    table.insert((1, 0xA), ("eth0".to_string(), 1), Duration::from_secs(200));
+    table.insert((2, 0xA), ("eth0".to_string(), 1), Duration::from_secs(90));
+    table.insert((3, 0xA), ("eth0".to_string(), 1), Duration::from_secs(120));
+    assert_eq!(table.get(&(2, 0xA)), None);
+
+    // refreshing a known entry never evicts
+    table.insert((1, 0xA), ("eth0".to_string(), 2), Duration::from_secs(60));
+    assert_eq!(table.lookup(&0xA).len(), 2);
+}
+
+#[test]
+fn refreshed_entries_evict_by_their_latest_deadline() {
+    let table = routes(2);
+
+    // first scheduled to expire soonest, then refreshed past the others
+    table.insert((1, 0xA), ("eth0".to_string(), 1), Duration::from_secs(5));
+    for _ in 0..8 {
+        table.insert((1, 0xA), ("eth0".to_string(), 1), Duration::from_secs(300));
+    }
+    table.insert((2, 0xA), ("eth0".to_string(), 1), Duration::from_secs(240));
+    table.insert((3, 0xA), ("eth0".to_string(), 1), Duration::from_secs(30));
+    assert_eq!(table.get(&(2, 0xA)), None);
+
+    // removed entries don't make room twice
+    table.remove_where(|(next_hop, _), _| *next_hop == 3);
+    table.insert((4, 0xA), ("eth0".to_string(), 1), Duration::from_secs(600));
+    table.insert((5, 0xA), ("eth0".to_string(), 1), Duration::from_secs(900));
+    assert_eq!(sorted(table.lookup(&0xA)).iter().map(|(key, _)| *key).collect::<Vec<_>>(), vec![(4, 0xA), (5, 0xA)]);
+}
+
+#[test]
+fn remove_where_keeps_the_index_in_step() {
+    let table = routes(16);
+    table.insert((1, 0xA), ("eth0".to_string(), 1), Duration::from_secs(30));
+    table.insert((1, 0xB), ("eth0".to_string(), 1), Duration::from_secs(30));
+    table.insert((2, 0xB), ("eth0".to_string(), 1), Duration::from_secs(30));
+
+    table.remove_where(|(next_hop, _), _| *next_hop == 1);
+    assert!(table.lookup(&0xA).is_empty());
+    assert_eq!(table.lookup(&0xB), vec![((2, 0xB), ("eth0".to_string(), 1))]);
+}
+
+#[test]
+fn concurrent_writers_and_readers_agree() {
+    let table = Arc::new(routes(0));
+    let writers: Vec<_> = (0..4u64).map(|writer| {
+        let table = table.clone();
+        thread::spawn(move || {
+            for next_hop in 0..500u64 {
+                table.insert((writer * 1000 + next_hop, next_hop % 8), ("eth0".to_string(), 1), Duration::from_secs(30));
+                assert!(!table.lookup(&(next_hop % 8)).is_empty());
+            }
+        })
+    }).collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let total: usize = (0..8u64).map(|locator| table.lookup(&locator).len()).sum();
+    assert_eq!(total, 2000);
+}
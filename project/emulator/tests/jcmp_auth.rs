@@ -0,0 +1,89 @@
+//! Authenticated JCMP control plane (tests/fixtures/Config.toml with a network key)
+
+mod harness;
+
+use emulator::models::jcmp_auth::{jcmp_auth_append, JcmpAuthTrailer};
+use emulator::models::network_packets::{INLPv6Packet, JcmpMessage};
+use emulator::services::time_services::get_current_timestamp;
+
+const KEY: &[u8] = b"lab network key";
+
+const AUTH: &str = r#"
+[jcmp_auth]
+enabled = true
+key = "lab network key"
+"#;
+
+fn header() -> INLPv6Packet {
+    harness::header(150, harness::PEER_NID, harness::LOCAL_NID)
+}
+
+fn router_response(hop_count: u8) -> Vec<u8> {
+    JcmpMessage::RouterResponse { hop_count, destination_locator: 0x00000000000000AA, ttl: 2 }.encode()
+}
+
+fn unsigned(jcmp: &[u8]) -> Vec<u8> {
+    harness::packet(header(), jcmp)
+}
+
+fn signed(key: &[u8], jcmp: Vec<u8>, timestamp_us: u64, counter: u32) -> Vec<u8> {
+    let mut jcmp = jcmp;
+    jcmp_auth_append(key, &header(), &mut jcmp, JcmpAuthTrailer { timestamp_us, counter });
+    unsigned(&jcmp)
+}
+
+#[test]
+fn only_fresh_authenticated_messages_are_handled() {
+    harness::use_config(AUTH);
+    let now = get_current_timestamp().unwrap();
+
+    // valid trailer
+    let valid = signed(KEY, router_response(1), now, 1);
+    let before = harness::pcb();
+    harness::fuzz_multicast(&valid);
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx + 1);
+
+    // the same message again is a replay
+    harness::fuzz_multicast(&valid);
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx);
+    assert_eq!(after.drops.jcmp_replay, before.drops.jcmp_replay + 1);
+
+    // same timestamp with another counter is a new message
+    harness::fuzz_multicast(&signed(KEY, router_response(1), now, 2));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx + 1);
+
+    // stale timestamp
+    harness::fuzz_multicast(&signed(KEY, router_response(1), now - 60_000_000, 3));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx);
+    assert_eq!(after.drops.jcmp_replay, before.drops.jcmp_replay + 1);
+
+    // wrong key
+    harness::fuzz_multicast(&signed(b"rogue key", router_response(1), now, 4));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx);
+    assert_eq!(after.drops.jcmp_auth_invalid, before.drops.jcmp_auth_invalid + 1);
+
+    // tampered hop count
+    let mut tampered = signed(KEY, router_response(5), now, 5);
+    tampered[40 + 1] = 0;
+    harness::fuzz_multicast(&tampered);
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx);
+    assert_eq!(after.drops.jcmp_auth_invalid, before.drops.jcmp_auth_invalid + 1);
+
+    // no trailer at all
+    harness::fuzz_multicast(&unsigned(&router_response(1)));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx);
+    assert_eq!(after.drops.jcmp_auth_missing, before.drops.jcmp_auth_missing + 1);
+}
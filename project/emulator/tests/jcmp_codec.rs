@@ -0,0 +1,90 @@
+use emulator::models::network_packets::{DecodeError, JcmpMessage};
+use proptest::prelude::*;
+
+fn jcmp_message() -> impl Strategy<Value = JcmpMessage> {
+    prop_oneof![
+        Just(JcmpMessage::NeighbourSolicitation),
+        any::<u16>().prop_map(|destination_port| JcmpMessage::NeighbourAdvertisement { destination_port }),
+        Just(JcmpMessage::RouterSolicitation),
+        (any::<u16>(), proptest::collection::vec(any::<u64>(), 0..4)).prop_map(|(lifetime, locators)| JcmpMessage::RouterAdvertisement { lifetime, locators }),
+        ".*".prop_map(|fqdn| JcmpMessage::DnsFqdnQuery { fqdn }),
+        (any::<u8>(), ".*").prop_map(|(ttl, fqdn)| JcmpMessage::DnsFqdnResponse { ttl, fqdn }),
+        Just(JcmpMessage::DnsIlvQuery),
+        any::<u8>().prop_map(|ttl| JcmpMessage::DnsIlvResponse { ttl }),
+        (any::<u8>(), any::<u64>()).prop_map(|(hop_count, destination_locator)| JcmpMessage::RouterRequest { hop_count, destination_locator }),
+        (any::<u8>(), any::<u64>(), any::<u8>()).prop_map(|(hop_count, destination_locator, ttl)| JcmpMessage::RouterResponse { hop_count, destination_locator, ttl }),
+        any::<u32>().prop_map(|nonce| JcmpMessage::DuplicateNidProbe { nonce }),
+        any::<u32>().prop_map(|nonce| JcmpMessage::DuplicateNidDefend { nonce }),
+        (any::<u64>(), any::<u64>()).prop_map(|(destination_locator, destination_nid)| JcmpMessage::DestinationUnreachable { destination_locator, destination_nid }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn encode_then_decode_round_trips(message in jcmp_message()) {
+        let bytes = message.encode();
+        prop_assert_eq!(bytes[0], message.code());
+        prop_assert_eq!(JcmpMessage::decode(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn decode_then_encode_is_identity(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+        if let Ok(message) = JcmpMessage::decode(&bytes) {
+            prop_assert_eq!(message.encode(), bytes);
+        }
+    }
+
+    #[test]
+    fn truncated_fixed_size_messages_are_rejected(message in jcmp_message(), cut in 1usize..11) {
+        let bytes = message.encode();
+        let fixed = !matches!(message, JcmpMessage::DnsFqdnQuery { .. } | JcmpMessage::DnsFqdnResponse { .. } | JcmpMessage::RouterAdvertisement { .. });
+        if fixed && cut < bytes.len() {
+            let is_too_short = matches!(JcmpMessage::decode(&bytes[..bytes.len() - cut]), Err(DecodeError::TooShort { .. }));
+            prop_assert!(is_too_short);
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_on_fixed_size_messages_are_rejected(message in jcmp_message(), extra in proptest::collection::vec(any::<u8>(), 1..8)) {
+        let fixed = !matches!(message, JcmpMessage::DnsFqdnQuery { .. } | JcmpMessage::DnsFqdnResponse { .. });
+        if fixed {
+            let mut bytes = message.encode();
+            bytes.extend_from_slice(&extra);
+            let is_trailing = matches!(JcmpMessage::decode(&bytes), Err(DecodeError::TrailingBytes { .. }));
+            prop_assert!(is_trailing);
+        }
+    }
+}
+
+#[test]
+fn wire_format_matches_the_previous_packet_layout() {
+    assert_eq!(JcmpMessage::NeighbourAdvertisement { destination_port: 0x1234 }.encode(), vec![1, 0x12, 0x34]);
+    assert_eq!(JcmpMessage::DnsFqdnResponse { ttl: 30, fqdn: "n1".to_string() }.encode(), vec![5, 30, b'n', b'1']);
+    assert_eq!(
+        JcmpMessage::RouterResponse { hop_count: 2, destination_locator: 0x0102030405060708, ttl: 9 }.encode(),
+        vec![9, 2, 1, 2, 3, 4, 5, 6, 7, 8, 9]
+    );
+}
+
+#[test]
+fn empty_and_unknown_payloads() {
+    assert_eq!(JcmpMessage::decode(&[]), Err(DecodeError::Empty));
+    assert_eq!(JcmpMessage::decode(&[13]), Err(DecodeError::UnknownCode(13)));
+    assert_eq!(JcmpMessage::decode(&[4, 0xff]), Err(DecodeError::InvalidFqdn { code: 4 }));
+    assert!(matches!(JcmpMessage::decode(&[5]), Err(DecodeError::TooShort { code: 5, .. })));
+}
+
+#[test]
+fn router_advertisement_carries_whole_locators() {
+    let message = JcmpMessage::RouterAdvertisement { lifetime: 90, locators: vec![1, 2] };
+    let bytes = message.encode();
+    assert_eq!(&bytes[..3], &[3, 0, 90]);
+    assert_eq!(bytes.len(), 3 + 16);
+
+    // dropping a whole locator is still an advertisement
+    assert_eq!(JcmpMessage::decode(&bytes[..11]), Ok(JcmpMessage::RouterAdvertisement { lifetime: 90, locators: vec![1] }));
+
+    // part of a locator isn't
+    assert!(matches!(JcmpMessage::decode(&bytes[..12]), Err(DecodeError::TrailingBytes { code: 3, .. })));
+    assert!(matches!(JcmpMessage::decode(&bytes[..2]), Err(DecodeError::TooShort { code: 3, .. })));
+}
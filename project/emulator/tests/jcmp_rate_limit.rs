@@ -0,0 +1,42 @@
+//! JCMP flood protection (tests/fixtures/Config.toml with small buckets)
+
+mod harness;
+
+use emulator::models::network_packets::JcmpMessage;
+
+const RATE_LIMIT: &str = r#"
+[jcmp_limits]
+bucket_size = 3
+refill_per_s = 1
+"#;
+
+fn router_response() -> JcmpMessage {
+    JcmpMessage::RouterResponse { hop_count: 1, destination_locator: 0x00000000000000BB, ttl: 2 }
+}
+
+#[test]
+fn floods_are_throttled_per_source_and_code() {
+    harness::use_config(RATE_LIMIT);
+
+    // a bucket holds 3 packets, the refill (1/s) is too slow to matter here
+    let before = harness::pcb();
+    for _ in 0..5 {
+        harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &router_response()));
+    }
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx + 3);
+    assert_eq!(after.drops.jcmp_rate_limited, before.drops.jcmp_rate_limited + 2);
+
+    // another code from the same source has its own bucket
+    let before = after;
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &JcmpMessage::DnsIlvQuery));
+    let after = harness::pcb();
+    assert_eq!(after.drops.jcmp_rate_limited, before.drops.jcmp_rate_limited);
+
+    // so does another source
+    let before = after;
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID + 1, harness::LOCAL_NID, &router_response()));
+    let after = harness::pcb();
+    assert_eq!(after.router_response_jcmp_rx, before.router_response_jcmp_rx + 1);
+    assert_eq!(after.drops.jcmp_rate_limited, before.drops.jcmp_rate_limited);
+}
@@ -0,0 +1,162 @@
+//! End to end JTP encryption (tests/fixtures/Config.toml with a pre-shared key)
+
+mod harness;
+
+use std::net::{Ipv6Addr, UdpSocket};
+use std::time::Duration;
+
+use rand::rngs::OsRng;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use emulator::layers::jtp_network::jtp_crypto::{handshake_message, jtp_public_key, verify_handshake, JtpCryptoError, JtpSession, JTP_CRYPTO_DATA, JTP_CRYPTO_HELLO};
+use emulator::layers::jtp_network::jtp_rx;
+use emulator::models::network_packets::{INLPv6Packet, JcmpMessage};
+
+const PSK: &[u8] = b"lab psk";
+
+const CRYPTO: &str = r#"
+[jtp_crypto]
+enabled = true
+psk = "lab psk"
+handshake_rto_ms = 1
+handshake_retransmit_limit = 1
+"#;
+
+fn jtp_packet(source_nid: u64, payload: &[u8]) -> Vec<u8> {
+    harness::packet(harness::header(151, source_nid, harness::LOCAL_NID), payload)
+}
+
+fn receive() -> Option<Vec<u8>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(jtp_rx(0)).ok().map(|response| response.payload)
+}
+
+#[test]
+fn payloads_are_encrypted_end_to_end() {
+    harness::use_config(CRYPTO);
+
+    // peer says hello with its public key
+    let peer_secret = StaticSecret::random_from_rng(OsRng);
+    let hello = handshake_message(JTP_CRYPTO_HELLO, PublicKey::from(&peer_secret).as_bytes(), harness::PEER_NID, harness::LOCAL_NID, PSK);
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &hello));
+
+    // peer side of the session
+    let mut peer = JtpSession::new(&peer_secret, &PublicKey::from(jtp_public_key()), harness::PEER_NID, harness::LOCAL_NID, PSK);
+
+    // encrypted data is delivered in clear
+    let data = peer.seal(harness::PEER_NID, harness::LOCAL_NID, b"temperature=21.5").unwrap();
+    assert!(!data.windows(11).any(|window| window == b"temperature"));
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &data));
+    assert_eq!(receive().as_deref(), Some(&b"temperature=21.5"[..]));
+
+    // replayed
+    let before = harness::pcb();
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &data));
+    let after = harness::pcb();
+    assert_eq!(after.drops.jtp_replay, before.drops.jtp_replay + 1);
+
+    // tampered
+    let mut tampered = peer.seal(harness::PEER_NID, harness::LOCAL_NID, b"temperature=99.9").unwrap();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &tampered));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.drops.jtp_decrypt_failed, before.drops.jtp_decrypt_failed + 1);
+
+    // in clear
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, b"temperature=21.5"));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.drops.jtp_not_encrypted, before.drops.jtp_not_encrypted + 1);
+
+    // hellos that aren't signed with the psk can't replace the session
+    let intruder_key = *PublicKey::from(&StaticSecret::random_from_rng(OsRng)).as_bytes();
+    let mut unsigned = vec![JTP_CRYPTO_HELLO];
+    unsigned.extend_from_slice(&intruder_key);
+    unsigned.extend_from_slice(&[0u8; 32]);
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &unsigned));
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &handshake_message(JTP_CRYPTO_HELLO, &intruder_key, harness::PEER_NID, harness::LOCAL_NID, b"other psk")));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.drops.jtp_handshake_invalid, before.drops.jtp_handshake_invalid + 2);
+    let data = peer.seal(harness::PEER_NID, harness::LOCAL_NID, b"temperature=21.6").unwrap();
+    harness::fuzz_unicast(&jtp_packet(harness::PEER_NID, &data));
+    assert_eq!(receive().as_deref(), Some(&b"temperature=21.6"[..]));
+
+    // from a node without a session
+    let data = peer.seal(0x0000000000000004, harness::LOCAL_NID, b"temperature=21.5").unwrap();
+    harness::fuzz_unicast(&jtp_packet(0x0000000000000004, &data));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.drops.jtp_no_session, before.drops.jtp_no_session + 1);
+
+    assert_eq!(receive(), None);
+}
+
+#[test]
+fn sessions_accept_reordered_but_not_repeated_counters() {
+    let first = StaticSecret::random_from_rng(OsRng);
+    let second = StaticSecret::random_from_rng(OsRng);
+    let mut sender = JtpSession::new(&first, &PublicKey::from(&second), 1, 2, PSK);
+    let mut receiver = JtpSession::new(&second, &PublicKey::from(&first), 2, 1, PSK);
+
+    let messages: Vec<Vec<u8>> = (0..3u8).map(|n| sender.seal(1, 2, &[n]).unwrap()).collect();
+    assert_eq!(receiver.open(1, 2, &messages[2]), Ok(vec![2]));
+    assert_eq!(receiver.open(1, 2, &messages[0]), Ok(vec![0]));
+    assert_eq!(receiver.open(1, 2, &messages[1]), Ok(vec![1]));
+    assert!(matches!(receiver.open(1, 2, &messages[1]), Err(JtpCryptoError::Replay { .. })));
+
+    // bound to the NIDs
+    let message = sender.seal(1, 2, b"x").unwrap();
+    assert_eq!(receiver.open(3, 2, &message), Err(JtpCryptoError::DecryptFailed));
+
+    // both sides need the same psk
+    let mut other = JtpSession::new(&second, &PublicKey::from(&first), 2, 1, b"other psk");
+    let message = sender.seal(1, 2, b"x").unwrap();
+    assert_eq!(other.open(1, 2, &message), Err(JtpCryptoError::DecryptFailed));
+}
+
+#[test]
+fn handshakes_are_bound_to_the_psk_and_nids() {
+    let key = *PublicKey::from(&StaticSecret::random_from_rng(OsRng)).as_bytes();
+    let hello = handshake_message(JTP_CRYPTO_HELLO, &key, 1, 2, PSK);
+    assert_eq!(verify_handshake(&hello, 1, 2, PSK), Ok(key));
+    assert_eq!(verify_handshake(&hello, 1, 2, b"other psk"), Err(JtpCryptoError::InvalidHandshake));
+    assert_eq!(verify_handshake(&hello, 3, 2, PSK), Err(JtpCryptoError::InvalidHandshake));
+    assert_eq!(verify_handshake(&hello[..33], 1, 2, PSK), Err(JtpCryptoError::TooShort { actual: 33 }));
+}
+
+#[test]
+fn flows_seal_into_the_cached_header() {
+    harness::use_config(CRYPTO);
+    const NID: u64 = 0x0000000000000062;
+
+    // a node answering on a port on [::1] we listen on
+    let sink = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+    sink.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let port = sink.local_addr().unwrap().port();
+    harness::fuzz_multicast(&harness::jcmp(NID, harness::LOCAL_NID, &JcmpMessage::DnsFqdnResponse { ttl: 60, fqdn: "node62".to_string() }));
+    harness::fuzz_multicast(&harness::jcmp(NID, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: port }));
+
+    // the node says hello first
+    let node_secret = StaticSecret::random_from_rng(OsRng);
+    harness::fuzz_unicast(&jtp_packet(NID, &handshake_message(JTP_CRYPTO_HELLO, PublicKey::from(&node_secret).as_bytes(), NID, harness::LOCAL_NID, PSK)));
+    let mut node = JtpSession::new(&node_secret, &PublicKey::from(jtp_public_key()), NID, harness::LOCAL_NID, PSK);
+
+    let mut flow = harness::connect("node62").unwrap();
+    harness::send(&mut flow, b"temperature=21.5").unwrap();
+
+    // skip the Hello Ack
+    let mut buf = [0; 1500];
+    let pck = loop {
+        let len = sink.recv(&mut buf).unwrap();
+        if buf[40] == JTP_CRYPTO_DATA {
+            break buf[..len].to_vec();
+        }
+    };
+    let header = INLPv6Packet::from_bytes(pck[..40].try_into().unwrap());
+    assert_eq!(header.destination_identifier(), NID);
+    assert_eq!(header.payload_length() as usize, pck.len() - 40);
+    assert_eq!(node.open(harness::LOCAL_NID, NID, &pck[40..]), Ok(b"temperature=21.5".to_vec()));
+}
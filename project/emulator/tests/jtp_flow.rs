@@ -0,0 +1,80 @@
+//! JTP flows resolve their destination once and reuse the route
+
+mod harness;
+
+use std::net::{Ipv6Addr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use emulator::models::network_packets::{INLPv6Packet, JcmpMessage};
+
+// a node on our network 1, answering on a port on [::1] we listen on
+fn advertise(nid: u64) -> UdpSocket {
+    let sink = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+    sink.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let port = sink.local_addr().unwrap().port();
+    harness::fuzz_multicast(&harness::jcmp(nid, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: port }));
+    sink
+}
+
+fn name(nid: u64, fqdn: &str) {
+    harness::fuzz_multicast(&harness::jcmp(nid, harness::LOCAL_NID, &JcmpMessage::DnsFqdnResponse { ttl: 60, fqdn: fqdn.to_string() }));
+}
+
+fn recv(sink: &UdpSocket) -> Vec<u8> {
+    let mut buf = [0; 1500];
+    let len = sink.recv(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+// the tests compare counters
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn every_packet_gets_the_header_with_its_own_length() {
+    let _serial = SERIAL.lock().unwrap();
+    const NID: u64 = 0x0000000000000060;
+    name(NID, "node60");
+    let sink = advertise(NID);
+
+    let before = harness::pcb();
+    let mut flow = harness::connect("node60").unwrap();
+    for payload in [vec![0x11; 100], vec![0x22; 7], vec![0x33; 1000]] {
+        harness::send(&mut flow, &payload).unwrap();
+
+        let pck = recv(&sink);
+        let header = INLPv6Packet::from_bytes(pck[..40].try_into().unwrap());
+        assert_eq!(header.version(), 6);
+        assert_eq!(header.next_header(), 151);
+        assert_eq!(header.hop_limit(), 64);
+        assert_eq!(header.payload_length() as usize, payload.len());
+        assert_eq!(header.source_identifier(), harness::LOCAL_NID);
+        assert_eq!(header.source_locator(), 1);
+        assert_eq!(header.destination_identifier(), NID);
+        assert_eq!(header.destination_locator(), 1);
+        assert_eq!(&pck[40..], &payload[..]);
+    }
+
+    // counted once the flow is done with
+    drop(flow);
+    assert_eq!(harness::pcb().data_request_tx - before.data_request_tx, 3);
+}
+
+#[test]
+fn the_route_is_cached_until_it_goes_bad() {
+    let _serial = SERIAL.lock().unwrap();
+    const NID: u64 = 0x0000000000000061;
+    name(NID, "node61");
+    let first = advertise(NID);
+    let mut flow = harness::connect("node61").unwrap();
+
+    // the neighbour moves, the flow doesn't look it up again
+    let second = advertise(NID);
+    harness::send(&mut flow, &[0x44; 8]).unwrap();
+    assert_eq!(&recv(&first)[40..], &[0x44; 8]);
+
+    // a router reports a route went bad
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &JcmpMessage::DestinationUnreachable { destination_locator: 5, destination_nid: 0x0000000000000070 }));
+    harness::send(&mut flow, &[0x55; 8]).unwrap();
+    assert_eq!(&recv(&second)[40..], &[0x55; 8]);
+}
@@ -0,0 +1,143 @@
+//! Log filter, gap tracking and the logger's rotating file sink
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use emulator::models::log_models::{LogEvent, LogLevel, LogRecord, LOG_PROTOCOL_VERSION};
+use emulator::services::log_services::{log_filter_accepts, LogObservation, LogStreamTracker, RotatingFileSink};
+
+fn record(level: LogLevel, node: &str, nid: u64) -> LogRecord {
+    LogRecord {
+        v: LOG_PROTOCOL_VERSION,
+        level,
+        node: node.to_string(),
+        nid,
+        session: 1,
+        seq: 1,
+        ts: 0,
+        event: LogEvent::Message { text: "test".to_string() }
+    }
+}
+
+fn sequenced(nid: u64, seq: u64) -> LogRecord {
+    LogRecord { seq, ..record(LogLevel::Info, "node1", nid) }
+}
+
+// a fresh directory per test
+fn log_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("emulator-logging-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn read(path: &PathBuf) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn records_below_the_level_are_rejected() {
+    assert!(!log_filter_accepts(&record(LogLevel::Debug, "node1", 1), LogLevel::Info, &[]));
+    assert!(log_filter_accepts(&record(LogLevel::Info, "node1", 1), LogLevel::Info, &[]));
+    assert!(log_filter_accepts(&record(LogLevel::Error, "node1", 1), LogLevel::Info, &[]));
+}
+
+#[test]
+fn nodes_are_matched_by_name_or_nid() {
+    let nodes = vec!["node1".to_string(), "0x00000000000000aa".to_string()];
+    assert!(log_filter_accepts(&record(LogLevel::Info, "node1", 1), LogLevel::Debug, &nodes));
+    assert!(log_filter_accepts(&record(LogLevel::Info, "node2", 0xAA), LogLevel::Debug, &nodes));
+    assert!(!log_filter_accepts(&record(LogLevel::Info, "node3", 3), LogLevel::Debug, &nodes));
+
+    // the level still applies to listed nodes
+    assert!(!log_filter_accepts(&record(LogLevel::Debug, "node1", 1), LogLevel::Warn, &nodes));
+}
+
+#[test]
+fn the_file_is_rotated_before_it_passes_the_limit() {
+    let dir = log_dir("rotate");
+    let path = dir.join("ilnp.jsonl");
+    let mut sink = RotatingFileSink::open(path.to_str().unwrap(), 20, 2).unwrap();
+
+    // 10 bytes per line with the newline, two lines per file
+    for line in ["aaaaaaaaa", "bbbbbbbbb", "ccccccccc", "ddddddddd", "eeeeeeeee"] {
+        sink.write_line(line).unwrap();
+    }
+
+    assert_eq!(read(&path), "eeeeeeeee\n");
+    assert_eq!(read(&dir.join("ilnp.jsonl.1")), "ccccccccc\nddddddddd\n");
+    assert_eq!(read(&dir.join("ilnp.jsonl.2")), "aaaaaaaaa\nbbbbbbbbb\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn only_max_files_old_files_are_kept() {
+    let dir = log_dir("keep");
+    let path = dir.join("ilnp.jsonl");
+    let mut sink = RotatingFileSink::open(path.to_str().unwrap(), 10, 2).unwrap();
+
+    // one line per file
+    for line in ["111111111", "222222222", "333333333", "444444444"] {
+        sink.write_line(line).unwrap();
+    }
+
+    assert_eq!(read(&path), "444444444\n");
+    assert_eq!(read(&dir.join("ilnp.jsonl.1")), "333333333\n");
+    assert_eq!(read(&dir.join("ilnp.jsonl.2")), "222222222\n");
+    assert!(!dir.join("ilnp.jsonl.3").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn an_existing_file_counts_towards_the_limit() {
+    let dir = log_dir("reopen");
+    let path = dir.join("ilnp.jsonl");
+    RotatingFileSink::open(path.to_str().unwrap(), 20, 1).unwrap().write_line("aaaaaaaaa").unwrap();
+
+    // reopened after a restart, the second line still fits, the third rotates
+    let mut sink = RotatingFileSink::open(path.to_str().unwrap(), 20, 1).unwrap();
+    sink.write_line("bbbbbbbbb").unwrap();
+    sink.write_line("ccccccccc").unwrap();
+
+    assert_eq!(read(&path), "ccccccccc\n");
+    assert_eq!(read(&dir.join("ilnp.jsonl.1")), "aaaaaaaaa\nbbbbbbbbb\n");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn gaps_are_reported_once_the_window_passes() {
+    let mut tracker = LogStreamTracker::new();
+    tracker.observe(&sequenced(1, 1));
+    assert_eq!(tracker.observe(&sequenced(1, 4)), LogObservation::Accepted { gap: Some((2, 3)) });
+
+    // retransmissions still have time
+    assert!(tracker.expired_gaps(Duration::from_secs(60)).is_empty());
+
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(tracker.expired_gaps(Duration::from_millis(10)), vec![(1, 1, 2, 3)]);
+
+    // only once
+    assert!(tracker.expired_gaps(Duration::ZERO).is_empty());
+}
+
+#[test]
+fn retransmitted_records_fill_the_gap_before_it_is_reported() {
+    let mut tracker = LogStreamTracker::new();
+    tracker.observe(&sequenced(1, 1));
+    tracker.observe(&sequenced(1, 6));
+
+    // 2, 3 and 5 are resent, 4 never arrives
+    for seq in [2, 3, 5] {
+        assert_eq!(tracker.observe(&sequenced(1, seq)), LogObservation::Accepted { gap: None });
+    }
+    assert_eq!(tracker.expired_gaps(Duration::ZERO), vec![(1, 1, 4, 4)]);
+
+    // a completely filled gap is never reported
+    tracker.observe(&sequenced(2, 1));
+    tracker.observe(&sequenced(2, 3));
+    tracker.observe(&sequenced(2, 2));
+    assert!(tracker.expired_gaps(Duration::ZERO).is_empty());
+    assert_eq!(tracker.acknowledged(2, 1), 3);
+}
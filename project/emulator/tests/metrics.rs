@@ -0,0 +1,134 @@
+//! Per-node metrics files written by the metrics writer thread
+
+use std::env;
+use std::fs;
+use std::net::Ipv6Addr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use emulator::models::config_models::MetricsFormat;
+use emulator::models::log_models::{LogEvent, LogRecord};
+use emulator::models::network_models::EmulatorLocalNetwork;
+use emulator::models::protocol_control_block::{ILNP_DROPS_S, ILNP_PCB_S};
+use emulator::services::metrics_services::{close_metrics, open_metrics_file, write_event, write_snapshot};
+
+// one metrics writer per process
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn local_network() -> EmulatorLocalNetwork {
+    EmulatorLocalNetwork {
+        local_uid: 0,
+        local_index: 0,
+        local_nid: 0x00000000000000AB,
+        local_fqdn: "node1".to_string(),
+        local_ipv6: Ipv6Addr::LOCALHOST,
+        local_port: 0
+    }
+}
+
+fn snapshot() -> ILNP_PCB_S {
+    ILNP_PCB_S {
+        data_request_tx: 7,
+        drops: ILNP_DROPS_S { forward_failed: 3, ..Default::default() },
+        ..Default::default()
+    }
+}
+
+fn event() -> LogEvent {
+    LogEvent::NameResolved { fqdn: "node2".to_string(), nid: 2, locator: 1 }
+}
+
+// a fresh directory per test
+fn metrics_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("emulator-metrics-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn lines(path: &PathBuf) -> Vec<String> {
+    fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+}
+
+#[test]
+fn jsonl_records_parse_as_log_records() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = metrics_dir("jsonl");
+    let path = dir.join("node1.metrics.jsonl");
+    open_metrics_file(path.to_str().unwrap(), MetricsFormat::Jsonl).unwrap();
+
+    write_snapshot(&local_network(), &snapshot());
+    write_event(&local_network(), &event());
+    close_metrics();
+
+    let records: Vec<LogRecord> = lines(&path).iter().map(|line| LogRecord::from_json_bytes(line.as_bytes()).unwrap()).collect();
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|record| record.node == "node1" && record.nid == 0xAB));
+    match &records[0].event {
+        LogEvent::PcbSnapshot { pcb } => assert_eq!((pcb.data_request_tx, pcb.drops.forward_failed), (7, 3)),
+        other => panic!("expected a snapshot, got {:?}", other)
+    }
+    assert!(matches!(&records[1].event, LogEvent::NameResolved { fqdn, .. } if fqdn == "node2"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn csv_snapshots_have_a_column_per_counter() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = metrics_dir("csv");
+    let path = dir.join("node1.metrics.csv");
+    open_metrics_file(path.to_str().unwrap(), MetricsFormat::Csv).unwrap();
+
+    write_snapshot(&local_network(), &snapshot());
+    write_event(&local_network(), &event());
+    close_metrics();
+
+    let snapshots = lines(&path);
+    assert_eq!(snapshots.len(), 2);
+    let header: Vec<&str> = snapshots[0].split(',').collect();
+    let row: Vec<&str> = snapshots[1].split(',').collect();
+    assert_eq!(header.len(), row.len());
+    assert_eq!(&header[..2], &["ts", "nid"]);
+    assert_eq!(row[1], "0x00000000000000AB");
+    let column = |name: &str| row[header.iter().position(|column| *column == name).unwrap()];
+    assert_eq!(column("data_request_tx"), "7");
+    assert_eq!(column("drops.forward_failed"), "3");
+
+    // events carry their fields as quoted JSON
+    let events = lines(&dir.join("node1.metrics.events.csv"));
+    assert_eq!(events[0], "ts,nid,kind,fields");
+    assert!(events[1].ends_with(",0x00000000000000AB,name_resolved,\"{\"\"fqdn\"\":\"\"node2\"\",\"\"locator\"\":1,\"\"nid\"\":2}\""), "{}", events[1]);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn headers_are_only_written_to_new_files() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = metrics_dir("reopen");
+    let path = dir.join("node1.metrics.csv");
+
+    for _ in 0..2 {
+        open_metrics_file(path.to_str().unwrap(), MetricsFormat::Csv).unwrap();
+        write_snapshot(&local_network(), &snapshot());
+        close_metrics();
+    }
+
+    let snapshots = lines(&path);
+    assert_eq!(snapshots.len(), 3);
+    assert!(snapshots[0].starts_with("ts,nid,"));
+    assert!(!snapshots[2].starts_with("ts,"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn nothing_is_written_once_closed() {
+    let _serial = SERIAL.lock().unwrap();
+    let dir = metrics_dir("closed");
+    let path = dir.join("node1.metrics.jsonl");
+    open_metrics_file(path.to_str().unwrap(), MetricsFormat::Jsonl).unwrap();
+    close_metrics();
+
+    write_event(&local_network(), &event());
+    close_metrics();
+    assert!(lines(&path).is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,125 @@
+//! Neighbour unreachability detection (tests/fixtures/Config.toml with short timers)
+
+mod harness;
+
+use std::net::{Ipv6Addr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use emulator::models::network_models::NeighbourState;
+use emulator::models::network_packets::JcmpMessage;
+use emulator::services::network_services::{confirm_neighbour_cache, insert_into_neighbour_cache, lookup_forwarding_table_route, lookup_neighbour_cache};
+
+const NUD: &str = r#"
+[neighbour_discovery]
+reachable_time_ms = 50
+retrans_timer_ms = 10
+max_unicast_probes = 2
+"#;
+
+// the harness peer sends from [::1]:9
+fn advertisement(nid: u64, destination_identifier: u64) -> Vec<u8> {
+    harness::jcmp(nid, destination_identifier, &JcmpMessage::NeighbourAdvertisement { destination_port: 9 })
+}
+
+// the tests sending probes compare counters
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn state(nid: u64) -> Option<NeighbourState> {
+    lookup_neighbour_cache(&nid).unwrap().map(|neighbour| neighbour.state)
+}
+
+#[test]
+fn confirmations_age_to_stale() {
+    harness::use_config(NUD);
+    const NID: u64 = 0x0000000000000020;
+
+    // answer to our solicitation
+    harness::fuzz_multicast(&advertisement(NID, harness::LOCAL_NID));
+    assert_eq!(state(NID), Some(NeighbourState::Reachable));
+
+    harness::run_for(Duration::from_millis(60));
+    assert_eq!(state(NID), Some(NeighbourState::Stale));
+}
+
+#[test]
+fn received_traffic_confirms_the_neighbour() {
+    harness::use_config(NUD);
+    const NID: u64 = 0x0000000000000021;
+
+    // unsolicited advertisements aren't a confirmation
+    harness::fuzz_multicast(&advertisement(NID, 0x00000000ff02ff01));
+    assert_eq!(state(NID), Some(NeighbourState::Stale));
+
+    // JTP from the neighbour's address is
+    harness::fuzz_unicast(&harness::packet(harness::header(151, NID, harness::LOCAL_NID), &[0x44; 8]));
+    assert_eq!(state(NID), Some(NeighbourState::Reachable));
+}
+
+#[test]
+fn confirmations_follow_the_current_binding() {
+    harness::use_config(NUD);
+    const NID: u64 = 0x0000000000000024;
+    let interface = String::from("lo");
+
+    insert_into_neighbour_cache(&NID, &interface, Ipv6Addr::LOCALHOST, 10, false).unwrap();
+    assert_eq!(state(NID), Some(NeighbourState::Stale));
+
+    // the neighbour moved to another port, its old address no longer confirms it
+    insert_into_neighbour_cache(&NID, &interface, Ipv6Addr::LOCALHOST, 11, false).unwrap();
+    confirm_neighbour_cache(&Ipv6Addr::LOCALHOST, 10).unwrap();
+    assert_eq!(state(NID), Some(NeighbourState::Stale));
+
+    confirm_neighbour_cache(&Ipv6Addr::LOCALHOST, 11).unwrap();
+    assert_eq!(state(NID), Some(NeighbourState::Reachable));
+}
+
+#[test]
+fn silent_neighbours_are_removed_with_their_routes() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(NUD);
+    const NID: u64 = 0x0000000000000022;
+    const LOCATOR: u64 = 0x00000000000000DD;
+
+    // a route through the neighbour
+    harness::fuzz_multicast(&advertisement(NID, 0x00000000ff02ff01));
+    harness::fuzz_multicast(&harness::jcmp(NID, harness::LOCAL_NID, &JcmpMessage::RouterResponse { hop_count: 1, destination_locator: LOCATOR, ttl: 30 }));
+    assert_eq!(lookup_forwarding_table_route(&LOCATOR).unwrap().0, NID);
+
+    // using the stale neighbour starts the probes
+    let before = harness::pcb();
+    harness::resolve_nid(NID, 1).unwrap();
+    assert_eq!(state(NID), Some(NeighbourState::Probe));
+
+    // nobody answers at [::1]:9
+    harness::run_for(Duration::from_millis(100));
+    let after = harness::pcb();
+    assert_eq!(after.nd_probe_jcmp_tx, before.nd_probe_jcmp_tx + 2);
+    assert_eq!(after.nd_unreachable, before.nd_unreachable + 1);
+    assert_eq!(state(NID), None);
+    assert!(lookup_forwarding_table_route(&LOCATOR).is_err());
+}
+
+#[test]
+fn flows_probe_a_stale_next_hop() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(NUD);
+    const NID: u64 = 0x0000000000000023;
+
+    // a node answering on a port on [::1] we listen on
+    let sink = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+    let port = sink.local_addr().unwrap().port();
+    harness::fuzz_multicast(&harness::jcmp(NID, harness::LOCAL_NID, &JcmpMessage::DnsFqdnResponse { ttl: 60, fqdn: "node23".to_string() }));
+    harness::fuzz_multicast(&harness::jcmp(NID, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: port }));
+    let mut flow = harness::connect("node23").unwrap();
+    assert_eq!(state(NID), Some(NeighbourState::Reachable));
+
+    // the flow keeps its route but still notices the neighbour went stale
+    harness::run_for(Duration::from_millis(60));
+    harness::send(&mut flow, &[0x44; 8]).unwrap();
+    assert_eq!(state(NID), Some(NeighbourState::Probe));
+
+    // the probes go unanswered
+    harness::run_for(Duration::from_millis(100));
+    assert_eq!(state(NID), None);
+}
@@ -0,0 +1,99 @@
+//! Nonce sessions (RFC 6744) on the rfc6741 wire format (tests/fixtures/Config.toml with nonces enabled)
+
+mod harness;
+
+use emulator::layers::overlay_network::{LOCAL_NONCE, NONCE_SESSION_TABLE};
+use emulator::models::network_packets::JcmpMessage;
+use emulator::models::wire_format::{NEXT_HEADER_JCMP, NEXT_HEADER_JTP};
+use emulator::services::network_services::get_session_nonce;
+
+const NONCE: &str = r#"
+[wire]
+format = "rfc6741"
+
+[nonce]
+enabled = true
+"#;
+
+fn nonce_packet(next_header: u8, source_nid: u64, destination_nid: u64, nonce: Option<u32>, payload: &[u8]) -> Vec<u8> {
+    harness::rfc6741_packet(harness::header(next_header, source_nid, destination_nid), nonce, payload)
+}
+
+fn advertisement(source_nid: u64, destination_nid: u64, nonce: Option<u32>) -> Vec<u8> {
+    let jcmp = JcmpMessage::NeighbourAdvertisement { destination_port: 9 }.encode();
+    nonce_packet(NEXT_HEADER_JCMP, source_nid, destination_nid, nonce, &jcmp)
+}
+
+#[test]
+fn session_nonce_is_enforced() {
+    harness::use_config(NONCE);
+    let nonce = 0x01020304;
+
+    // first contact establishes the session
+    let before = harness::pcb();
+    harness::fuzz_multicast(&advertisement(harness::PEER_NID, harness::LOCAL_NID, Some(nonce)));
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx + 1);
+
+    // same nonce is accepted
+    harness::fuzz_multicast(&advertisement(harness::PEER_NID, harness::LOCAL_NID, Some(nonce)));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx + 1);
+
+    // wrong nonce is rejected
+    harness::fuzz_multicast(&advertisement(harness::PEER_NID, harness::LOCAL_NID, Some(nonce + 1)));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx);
+    assert_eq!(after.drops.nonce_mismatch, before.drops.nonce_mismatch + 1);
+
+    // missing nonce is rejected
+    harness::fuzz_multicast(&advertisement(harness::PEER_NID, harness::LOCAL_NID, None));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx);
+    assert_eq!(after.drops.nonce_missing, before.drops.nonce_missing + 1);
+
+    // overheard advertisement from a correspondent can't be verified
+    harness::fuzz_multicast(&advertisement(harness::PEER_NID, 0x0000000000000003, Some(nonce)));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx);
+    assert_eq!(after.drops.nonce_unverified, before.drops.nonce_unverified + 1);
+
+    // overheard advertisement from a node without a session is still collected
+    harness::fuzz_multicast(&advertisement(0x0000000000000004, 0x0000000000000003, Some(nonce)));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx + 1);
+
+    // data with the wrong nonce isn't delivered
+    harness::fuzz_unicast(&nonce_packet(NEXT_HEADER_JTP, harness::PEER_NID, harness::LOCAL_NID, Some(nonce + 1), b"data"));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.data_request_rx, before.data_request_rx);
+    assert_eq!(after.drops.nonce_mismatch, before.drops.nonce_mismatch + 1);
+
+    // data with the session nonce is
+    harness::fuzz_unicast(&nonce_packet(NEXT_HEADER_JTP, harness::PEER_NID, harness::LOCAL_NID, Some(nonce), b"data"));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.data_request_rx, before.data_request_rx + 1);
+}
+
+#[test]
+fn session_nonce_is_kept_and_placeholders_have_no_session() {
+    harness::use_config(NONCE);
+
+    // the nonce we send a correspondent doesn't change between sends
+    let correspondent = 0x0000000000000005;
+    let nonce = get_session_nonce(&correspondent).unwrap();
+    assert_eq!(get_session_nonce(&correspondent).unwrap(), nonce);
+
+    // DNS, all nodes and all routers placeholders use the local nonce and don't take a session
+    for placeholder in [0x0000000053535353, 0x00000000ff02ff01, 0x00000000ff02ff02] {
+        assert_eq!(get_session_nonce(&placeholder).unwrap(), *LOCAL_NONCE);
+        assert!(NONCE_SESSION_TABLE.lock().unwrap().get(&placeholder).is_none());
+    }
+}
@@ -0,0 +1,60 @@
+//! Nonce sessions together with unsolicited advertisements (tests/fixtures/Config.toml with nonces enabled)
+
+mod harness;
+
+use emulator::models::network_packets::JcmpMessage;
+use emulator::models::wire_format::NEXT_HEADER_JCMP;
+
+const NONCE: &str = r#"
+[wire]
+format = "rfc6741"
+
+[nonce]
+enabled = true
+"#;
+
+fn advertisement(destination_nid: u64, nonce: u32, destination_port: u16) -> Vec<u8> {
+    let jcmp = JcmpMessage::NeighbourAdvertisement { destination_port }.encode();
+    harness::rfc6741_packet(harness::header(NEXT_HEADER_JCMP, harness::PEER_NID, destination_nid), Some(nonce), &jcmp)
+}
+
+#[test]
+fn unsolicited_advertisement_resets_the_session() {
+    harness::use_config(NONCE);
+    let nonce = 0x01020304;
+    let restarted_nonce = 0x05060708;
+
+    // the peer's first advertisement to us establishes the session
+    harness::fuzz_multicast(&advertisement(harness::LOCAL_NID, nonce, 4000));
+    assert_eq!(harness::resolve_nid(harness::PEER_NID, 1).unwrap().1, 4000);
+
+    // the peer restarted on another port and multicasts to all nodes with its new nonce
+    let before = harness::pcb();
+    harness::fuzz_multicast(&advertisement(0x00000000ff02ff01, restarted_nonce, 4001));
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx + 1);
+    assert_eq!(after.drops.nonce_unverified, before.drops.nonce_unverified);
+    assert_eq!(harness::resolve_nid(harness::PEER_NID, 1).unwrap().1, 4001);
+
+    // its new nonce establishes the session again instead of being a mismatch
+    harness::fuzz_multicast(&advertisement(harness::LOCAL_NID, restarted_nonce, 4001));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx + 1);
+    assert_eq!(after.drops.nonce_mismatch, before.drops.nonce_mismatch);
+
+    // the old nonce is now the wrong one
+    harness::fuzz_multicast(&advertisement(harness::LOCAL_NID, nonce, 4000));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx);
+    assert_eq!(after.drops.nonce_mismatch, before.drops.nonce_mismatch + 1);
+
+    // other overheard advertisements still can't be verified
+    harness::fuzz_multicast(&advertisement(0x0000000000000003, restarted_nonce, 4002));
+    let before = after;
+    let after = harness::pcb();
+    assert_eq!(after.nd_advertisement_jcmp_rx, before.nd_advertisement_jcmp_rx);
+    assert_eq!(after.drops.nonce_unverified, before.drops.nonce_unverified + 1);
+    assert_eq!(harness::resolve_nid(harness::PEER_NID, 1).unwrap().1, 4001);
+}
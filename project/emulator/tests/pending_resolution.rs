@@ -0,0 +1,139 @@
+//! Resolution and path discovery through per-key waiters (tests/fixtures/Config.toml with slow retransmissions)
+
+mod harness;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use emulator::models::network_packets::JcmpMessage;
+use emulator::services::network_services::insert_into_forwarding_table;
+
+const PENDING: &str = r#"
+[network]
+ND_RTO_MS = 300
+ND_RETRANSMIT_LIMIT = 2
+AD_HOC_TIMEOUT_MS = 300
+"#;
+
+// the harness peer sends from [::1]:9
+fn advertisement(nid: u64) -> Vec<u8> {
+    harness::jcmp(nid, harness::LOCAL_NID, &JcmpMessage::NeighbourAdvertisement { destination_port: 9 })
+}
+
+fn router_response(locator: u64) -> Vec<u8> {
+    harness::jcmp(harness::PEER_NID, harness::LOCAL_NID, &JcmpMessage::RouterResponse { hop_count: 1, destination_locator: locator, ttl: 30 })
+}
+
+fn solicitations_sent() -> u64 {
+    harness::pcb().nd_solicitation_jcmp_tx
+}
+
+fn router_requests_sent() -> u64 {
+    harness::pcb().router_request_jcmp_tx
+}
+
+// the tests count what was sent
+static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[test]
+fn concurrent_lookups_share_one_solicitation_and_wake_on_the_advertisement() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(PENDING);
+    const NID: u64 = 0x0000000000000030;
+
+    let before = solicitations_sent();
+    let started = Instant::now();
+    let results = harness::resolve_nid_concurrently(NID, 1, 8, Some((Duration::from_millis(20), advertisement(NID))));
+
+    // woken by the advertisement well before the 300 ms retransmission timeout
+    assert!(started.elapsed() < Duration::from_millis(250));
+    assert_eq!(results.len(), 8);
+    for result in results {
+        assert_eq!(result.unwrap().1, 9);
+    }
+    assert_eq!(solicitations_sent() - before, 1);
+}
+
+#[test]
+fn unanswered_lookups_retransmit_once_per_timeout() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(PENDING);
+    const NID: u64 = 0x0000000000000031;
+
+    let before = solicitations_sent();
+    let results = harness::resolve_nid_concurrently(NID, 1, 8, None);
+
+    // ND_RETRANSMIT_LIMIT solicitations for everybody, then unreachable
+    assert!(results.iter().all(|result| result.is_err()));
+    assert_eq!(solicitations_sent() - before, 2);
+}
+
+#[test]
+fn concurrent_path_discoveries_share_one_flood() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(PENDING);
+    const LOCATOR: u64 = 0x00000000000000E0;
+
+    let before = router_requests_sent();
+    let started = Instant::now();
+    let results = harness::discover_path_concurrently(LOCATOR, 16, Some((Duration::from_millis(20), router_response(LOCATOR))));
+
+    // woken by the response well before the 300 ms discovery timeout
+    assert!(started.elapsed() < Duration::from_millis(250));
+    assert_eq!(results.len(), 16);
+    for result in results {
+        assert_eq!(result.unwrap().0, harness::PEER_NID);
+    }
+
+    // one router request per network
+    assert_eq!(router_requests_sent() - before, 2);
+}
+
+#[test]
+fn unanswered_path_discoveries_fail_together() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(PENDING);
+    const LOCATOR: u64 = 0x00000000000000E1;
+
+    let before = router_requests_sent();
+    let results = harness::discover_path_concurrently(LOCATOR, 16, None);
+
+    assert!(results.iter().all(|result| result.is_err()));
+    assert_eq!(router_requests_sent() - before, 2);
+}
+
+#[test]
+fn path_discoveries_wake_on_routes_learned_elsewhere() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(PENDING);
+    const LOCATOR: u64 = 0x00000000000000E3;
+
+    // a route inserted without a router response
+    let inserter = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(20));
+        insert_into_forwarding_table((harness::PEER_NID, LOCATOR, String::from("lo"), 1), 30).unwrap();
+    });
+    let started = Instant::now();
+    let results = harness::discover_path_concurrently(LOCATOR, 4, None);
+    inserter.join().unwrap();
+
+    assert!(started.elapsed() < Duration::from_millis(250));
+    for result in results {
+        assert_eq!(result.unwrap().0, harness::PEER_NID);
+    }
+}
+
+#[test]
+fn path_discoveries_from_different_networks_flood_separately() {
+    let _serial = SERIAL.lock().unwrap();
+    harness::use_config(PENDING);
+    const LOCATOR: u64 = 0x00000000000000E2;
+
+    let before = router_requests_sent();
+    let results = harness::discover_path_from_every_network(LOCATOR);
+
+    // each request goes out on the other network, neither one is skipped
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.is_err()));
+    assert_eq!(router_requests_sent() - before, 2);
+}
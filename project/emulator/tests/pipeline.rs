@@ -0,0 +1,43 @@
+//! Flow sharding for the unicast workers
+
+mod harness;
+
+use std::collections::HashSet;
+
+use emulator::layers::overlay_network::flow_shard;
+
+fn flow_packet(flow_label: u32, source_identifier: u64, destination_identifier: u64, hop_limit: u8, payload: &[u8]) -> Vec<u8> {
+    let header = harness::header(151, source_identifier, destination_identifier)
+        .with_flow_label(flow_label)
+        .with_hop_limit(hop_limit)
+        .with_destination_locator(2);
+    harness::packet(header, payload)
+}
+
+#[test]
+fn a_flow_always_goes_to_the_same_worker() {
+    let first = flow_shard(&flow_packet(7, 1, 2, 64, &[0; 8]), 8);
+
+    // hop limit, length and traffic class change along the way, the flow doesn't
+    assert_eq!(flow_shard(&flow_packet(7, 1, 2, 3, &[0; 100]), 8), first);
+    let mut with_traffic_class = flow_packet(7, 1, 2, 64, &[0; 8]);
+    with_traffic_class[0] |= 0x0f;
+    with_traffic_class[1] |= 0xf0;
+    assert_eq!(flow_shard(&with_traffic_class, 8), first);
+}
+
+#[test]
+fn flows_are_spread_across_workers() {
+    let shards: HashSet<usize> = (0..64u64).map(|nid| flow_shard(&flow_packet(0, nid, 2, 64, &[]), 8)).collect();
+    assert!(shards.len() > 4);
+    assert!(shards.iter().all(|shard| *shard < 8));
+
+    let labels: HashSet<usize> = (0..64u32).map(|label| flow_shard(&flow_packet(label, 1, 2, 64, &[]), 8)).collect();
+    assert!(labels.len() > 4);
+}
+
+#[test]
+fn short_packets_and_single_workers_use_the_first_queue() {
+    assert_eq!(flow_shard(&[0x60; 39], 8), 0);
+    assert_eq!(flow_shard(&flow_packet(7, 1, 2, 64, &[]), 1), 0);
+}
@@ -0,0 +1,35 @@
+//! Router solicitation and advertisement (tests/fixtures/Config.toml, the node is a router)
+
+mod harness;
+
+use emulator::models::network_packets::JcmpMessage;
+use emulator::services::network_services::lookup_default_router;
+
+#[test]
+fn solicitations_are_answered_by_routers() {
+    let before = harness::pcb();
+    harness::fuzz_multicast(&harness::jcmp(harness::PEER_NID, 0x00000000ff02ff02, &JcmpMessage::RouterSolicitation));
+    let after = harness::pcb();
+    assert_eq!(after.router_solicitation_jcmp_rx, before.router_solicitation_jcmp_rx + 1);
+    assert_eq!(after.router_advertisement_jcmp_tx, before.router_advertisement_jcmp_tx + 1);
+}
+
+#[test]
+fn advertisements_maintain_the_default_router_list() {
+    const ROUTER_NID: u64 = 0x0000000000000010;
+    const SERVED_LOCATOR: u64 = 0x00000000000000CC;
+    let advertisement = |lifetime: u16| JcmpMessage::RouterAdvertisement { lifetime, locators: vec![1, SERVED_LOCATOR] };
+
+    let before = harness::pcb();
+    harness::fuzz_multicast(&harness::jcmp(ROUTER_NID, 0x00000000ff02ff01, &advertisement(90)));
+    let after = harness::pcb();
+    assert_eq!(after.router_advertisement_jcmp_rx, before.router_advertisement_jcmp_rx + 1);
+
+    // the router serving the locator is picked
+    let (router_nid, _) = lookup_default_router(&SERVED_LOCATOR).unwrap();
+    assert_eq!(router_nid, ROUTER_NID);
+
+    // a lifetime of 0 withdraws it
+    harness::fuzz_multicast(&harness::jcmp(ROUTER_NID, 0x00000000ff02ff01, &advertisement(0)));
+    assert!(lookup_default_router(&SERVED_LOCATOR).map(|(router_nid, _)| router_nid != ROUTER_NID).unwrap_or(true));
+}
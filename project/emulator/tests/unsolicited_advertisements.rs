@@ -0,0 +1,19 @@
+//! Unsolicited neighbour advertisements (tests/fixtures/Config.toml)
+
+mod harness;
+
+use emulator::models::network_packets::JcmpMessage;
+
+fn unsolicited_advertisement(destination_port: u16) -> Vec<u8> {
+    harness::jcmp(harness::PEER_NID, 0x00000000ff02ff01, &JcmpMessage::NeighbourAdvertisement { destination_port })
+}
+
+#[test]
+fn advertisements_overwrite_stale_bindings() {
+    harness::fuzz_multicast(&unsolicited_advertisement(4000));
+    assert_eq!(harness::resolve_nid(harness::PEER_NID, 1).unwrap().1, 4000);
+
+    // the peer restarted on another port
+    harness::fuzz_multicast(&unsolicited_advertisement(4001));
+    assert_eq!(harness::resolve_nid(harness::PEER_NID, 1).unwrap().1, 4001);
+}
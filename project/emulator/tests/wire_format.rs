@@ -0,0 +1,150 @@
+use emulator::models::config_models::WireFormat;
+use emulator::models::network_packets::{INLPv6Packet, JcmpMessage};
+use emulator::models::wire_format::{decode_packet, encode_packet, rewrite_hop_limit, set_payload_length, upper_layer_checksum, WireError, NEXT_HEADER_DESTINATION_OPTIONS, NEXT_HEADER_ICMPV6, NEXT_HEADER_JCMP, NEXT_HEADER_JTP, OPTION_NONCE};
+use proptest::prelude::*;
+
+fn header(next_header: u8, payload_length: usize, source_locator: u64, source_identifier: u64) -> INLPv6Packet {
+    INLPv6Packet::new()
+        .with_version(6)
+        .with_payload_length(payload_length as u16)
+        .with_next_header(next_header)
+        .with_hop_limit(1)
+        .with_source_locator(source_locator)
+        .with_source_identifier(source_identifier)
+        .with_destination_locator(0x0000000000000002)
+        .with_destination_identifier(0x0000000000000003)
+}
+
+proptest! {
+    #[test]
+    fn rfc6741_round_trips(jtp in any::<bool>(), payload in proptest::collection::vec(any::<u8>(), 1..256), nonce in proptest::option::of(any::<u32>()), locator in any::<u64>(), nid in any::<u64>()) {
+        let next_header = if jtp { NEXT_HEADER_JTP } else { NEXT_HEADER_JCMP };
+        let native = header(next_header, payload.len(), locator, nid);
+
+        let pck = encode_packet(WireFormat::Rfc6741, native, nonce, &payload);
+        let packet = decode_packet(&pck).unwrap();
+
+        prop_assert_eq!(packet.header.into_bytes(), native.into_bytes());
+        prop_assert_eq!(packet.nonce, nonce);
+        prop_assert_eq!(&packet.payload[..], &payload[..]);
+    }
+
+    #[test]
+    fn hop_limit_rewrite_keeps_the_packet_valid(jtp in any::<bool>(), payload in proptest::collection::vec(any::<u8>(), 1..256), nonce in proptest::option::of(any::<u32>()), hop_limit in any::<u8>()) {
+        let next_header = if jtp { NEXT_HEADER_JTP } else { NEXT_HEADER_JCMP };
+        let native = header(next_header, payload.len(), 1, 2);
+
+        // the checksum still matches, only the hop limit differs
+        let mut pck = encode_packet(WireFormat::Rfc6741, native, nonce, &payload);
+        rewrite_hop_limit(&mut pck, hop_limit);
+        let packet = decode_packet(&pck).unwrap();
+
+        prop_assert_eq!(packet.header.into_bytes(), native.with_hop_limit(hop_limit).into_bytes());
+        prop_assert_eq!(packet.nonce, nonce);
+        prop_assert_eq!(&packet.payload[..], &payload[..]);
+    }
+
+    #[test]
+    fn a_header_encoded_once_fits_any_jtp_payload(rfc6741 in any::<bool>(), payload in proptest::collection::vec(any::<u8>(), 0..256), nonce in proptest::option::of(any::<u32>())) {
+        let format = if rfc6741 { WireFormat::Rfc6741 } else { WireFormat::Native };
+
+        let mut pck = encode_packet(format, header(NEXT_HEADER_JTP, 0, 1, 2), nonce, &[]);
+        pck.extend_from_slice(&payload);
+        set_payload_length(&mut pck);
+
+        prop_assert_eq!(pck, encode_packet(format, header(NEXT_HEADER_JTP, payload.len(), 1, 2), nonce, &payload));
+    }
+
+    #[test]
+    fn native_is_unchanged(payload in proptest::collection::vec(any::<u8>(), 0..256)) {
+        let native = header(NEXT_HEADER_JTP, payload.len(), 1, 2);
+
+        let pck = encode_packet(WireFormat::Native, native, Some(7), &payload);
+        prop_assert_eq!(&pck[..40], &native.into_bytes()[..]);
+        prop_assert_eq!(&pck[40..], &payload[..]);
+
+        let packet = decode_packet(&pck).unwrap();
+        prop_assert_eq!(packet.nonce, None);
+        prop_assert_eq!(&packet.payload[..], &payload[..]);
+    }
+
+    #[test]
+    fn corrupted_jcmp_fails_the_checksum(payload in proptest::collection::vec(any::<u8>(), 1..64), index in any::<prop::sample::Index>(), flip in 1u8..) {
+        let native = header(NEXT_HEADER_JCMP, payload.len(), 1, 2);
+        let mut pck = encode_packet(WireFormat::Rfc6741, native, Some(1), &payload);
+
+        // anything in the ICMPv6 message past the type
+        let offset = 40 + 8 + 1 + index.index(pck.len() - 49);
+        pck[offset] ^= flip;
+        let is_checksum_mismatch = matches!(decode_packet(&pck), Err(WireError::ChecksumMismatch { .. }));
+        prop_assert!(is_checksum_mismatch);
+    }
+
+    #[test]
+    fn decode_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..128)) {
+        let _ = decode_packet(&bytes);
+    }
+}
+
+#[test]
+fn rfc6741_layout() {
+    let jcmp = JcmpMessage::NeighbourAdvertisement { destination_port: 0x1234 }.encode();
+    let pck = encode_packet(WireFormat::Rfc6741, header(NEXT_HEADER_JCMP, jcmp.len(), 1, 2), Some(0xDEADBEEF), &jcmp);
+
+    // ILNPv6 header
+    assert_eq!(pck[6], NEXT_HEADER_DESTINATION_OPTIONS);
+    assert_eq!(u16::from_be_bytes([pck[4], pck[5]]), 8 + 4 + 2);
+    assert_eq!(pck.len(), 40 + 8 + 4 + 2);
+
+    // destination options with the nonce
+    assert_eq!(&pck[40..48], &[NEXT_HEADER_ICMPV6, 0, OPTION_NONCE, 4, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+    // ICMPv6 type, JCMP code, checksum then the body
+    assert_eq!(pck[48], 200);
+    assert_eq!(pck[49], 0x01);
+    assert_eq!(&pck[52..], &[0x12, 0x34]);
+}
+
+#[test]
+fn checksum_ignores_locators() {
+    let jcmp = JcmpMessage::NeighbourSolicitation.encode();
+    let first = encode_packet(WireFormat::Rfc6741, header(NEXT_HEADER_JCMP, jcmp.len(), 1, 2), None, &jcmp);
+    let second = encode_packet(WireFormat::Rfc6741, header(NEXT_HEADER_JCMP, jcmp.len(), 9, 2), None, &jcmp);
+    assert_eq!(&first[40..], &second[40..]);
+
+    // the checksum over the whole message (checksum included) folds to zero
+    let native = header(NEXT_HEADER_JCMP, jcmp.len(), 1, 2);
+    assert_eq!(upper_layer_checksum(&native, NEXT_HEADER_ICMPV6, &first[40..]), 0);
+}
+
+#[test]
+fn unknown_options_follow_the_action_bits() {
+    let payload = [0xAA; 4];
+    let mut pck = encode_packet(WireFormat::Rfc6741, header(NEXT_HEADER_JTP, payload.len(), 1, 2), Some(1), &payload);
+
+    // 0x1E (action 00) is skipped
+    pck[42] = 0x1E;
+    let packet = decode_packet(&pck).unwrap();
+    assert_eq!(packet.nonce, None);
+    assert_eq!(&packet.payload[..], &payload);
+
+    // 0x9E (action 10) is discarded
+    pck[42] = 0x9E;
+    assert!(matches!(decode_packet(&pck), Err(WireError::UnknownOption(0x9E))));
+}
+
+#[test]
+fn icmpv6_is_bounded_by_payload_length() {
+
+    // payload_length shorter than the ICMPv6 header
+    let mut pck = header(NEXT_HEADER_ICMPV6, 0, 1, 2).into_bytes().to_vec();
+    pck.extend_from_slice(&[200, 0, 0, 0, 0]);
+    assert!(matches!(decode_packet(&pck), Err(WireError::TooShort { expected: 4, actual: 0 })));
+
+    // bytes after payload_length aren't part of the JCMP message
+    let jcmp = JcmpMessage::NeighbourAdvertisement { destination_port: 0x1234 }.encode();
+    let mut pck = encode_packet(WireFormat::Rfc6741, header(NEXT_HEADER_JCMP, jcmp.len(), 1, 2), None, &jcmp);
+    pck.extend_from_slice(&[0xFF; 3]);
+    let packet = decode_packet(&pck).unwrap();
+    assert_eq!(&packet.payload[..], &jcmp[..]);
+}